@@ -0,0 +1,52 @@
+//! Converts the images in `images/` to constants of `app::images`.
+//!
+//! `logo.png` becomes `images::LOGO`.  Images are thresholded at the middle
+//! gray, unless their name ends with `_dither` (`photo_dither.png` becomes
+//! `images::PHOTO`, dithered).
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[path = "../host/src/bitmap.rs"]
+#[allow(dead_code)]
+mod bitmap;
+
+const DITHER: &str = "_dither";
+
+fn main() {
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("images.rs");
+    println!("cargo:rerun-if-changed=images");
+    println!("cargo:rerun-if-changed=../host/src/bitmap.rs");
+
+    let mut paths: Vec<PathBuf> = fs::read_dir("images")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let ext = path.extension().and_then(|e| e.to_str());
+            matches!(ext, Some("png") | Some("pbm"))
+        })
+        .collect();
+    paths.sort();
+
+    let mut source = String::new();
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let gray = bitmap::load(&path)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let mut name = bitmap::const_name(&path);
+        let bits = if name.ends_with(&DITHER.to_uppercase()) {
+            name.truncate(name.len() - DITHER.len());
+            bitmap::dither(&gray)
+        } else {
+            bitmap::threshold(&gray, 127)
+        };
+        source.push_str(&format!("\n/// `{}`\n", file_name(&path)));
+        source.push_str(&bitmap::to_rust(&name, &bits));
+    }
+    fs::write(out, source).unwrap();
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|n| n.to_str()).unwrap()
+}
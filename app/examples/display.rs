@@ -1,7 +1,8 @@
 //! Print "Hello world!" with "Hello rust!" underneath. Uses the `embedded_graphics` crate to draw
 //! the text with a 6x8 pixel font.
 //!
-//! This example is for the STM32F103 "Blue Pill" board using I2C1.
+//! This example is for the STM32F103 "Blue Pill" board using I2C1, or another
+//! display chosen with the features of `app::display`.
 //!
 //! Wiring connections are as follows for a CRIUS-branded display:
 //!
@@ -20,72 +21,42 @@
 
 extern crate cortex_m;
 extern crate cortex_m_rt as rt;
-extern crate embedded_graphics;
 extern crate panic_semihosting;
 extern crate stm32f1xx_hal as hal;
 
 use cortex_m_rt::ExceptionFrame;
 use cortex_m_rt::{entry, exception};
-use embedded_graphics::fonts::Font6x8;
-use embedded_graphics::prelude::*;
-use hal::i2c::{BlockingI2c, DutyCycle, Mode};
+use hal::delay::Delay;
 use hal::prelude::*;
 use hal::stm32;
-use ssd1306::prelude::*;
-use ssd1306::Builder;
+
+use app::screens;
 
 #[entry]
 fn main() -> ! {
     let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
 
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
 
     let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let mut delay = Delay::new(cp.SYST, clocks);
 
     let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
 
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
     let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
 
-    let scl = gpiob.pb8.into_alternate_open_drain(&mut gpiob.crh);
-    let sda = gpiob.pb9.into_alternate_open_drain(&mut gpiob.crh);
-
-    let i2c = BlockingI2c::i2c1(
-        dp.I2C1,
-        (scl, sda),
-        &mut afio.mapr,
-        Mode::Fast {
-            frequency: 400_000,
-            duty_cycle: DutyCycle::Ratio2to1,
-        },
-        clocks,
-        &mut rcc.apb1,
-        1000,
-        10,
-        1000,
-        1000,
-    );
-
-    let mut disp: GraphicsMode<_> = Builder::new().connect_i2c(i2c).into();
-
-    disp.init().unwrap();
-    disp.flush().unwrap();
-
-    disp.draw(
-        Font6x8::render_str("Hello world!")
-            .with_stroke(Some(1u8.into()))
-            .into_iter(),
-    );
-    disp.draw(
-        Font6x8::render_str("Hello Rust!")
-            .with_stroke(Some(1u8.into()))
-            .translate(Coord::new(0, 16))
-            .into_iter(),
-    );
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
 
-    disp.flush().unwrap();
+    screens::hello(&mut disp);
 
-    loop {}
+    // Unplug the display and plug it back: the text comes back
+    loop {
+        disp.update();
+        delay.delay_ms(100u16);
+    }
 }
 
 #[exception]
@@ -13,30 +13,36 @@
 //! (green)  SCL -> PB8
 //! ```
 //!
-//! Run on a Blue Pill with `cargo run --example text_i2c`.#![no_main]
+//! The LED (PC13) is on while the display doesn't answer.  Unplug it and
+//! plug it back: the counter comes back after the bus recovery.  A count
+//! that can't be saved in flash, or restored, is reported with semihosting.
+//!
+//! Run on a Blue Pill with `cargo run --example display2`.
 
 #![no_std]
 #![no_main]
 
 extern crate cortex_m;
 extern crate cortex_m_rt as rt;
-extern crate embedded_graphics;
-extern crate heapless;
 extern crate panic_semihosting;
 extern crate stm32f1xx_hal as hal;
 
-use core::fmt::Write;
 use cortex_m_rt::ExceptionFrame;
 use cortex_m_rt::{entry, exception};
-use embedded_graphics::fonts::Font12x16;
-use embedded_graphics::prelude::*;
-use hal::i2c::{BlockingI2c, DutyCycle, Mode};
+use cortex_m_semihosting::hprintln;
 use hal::prelude::*;
 use hal::stm32;
-use heapless::consts::*;
-use heapless::String;
-use ssd1306::prelude::*;
-use ssd1306::Builder;
+
+use app::button::{Button, Event, Timings};
+use app::eeprom::{self, Eeprom};
+use app::flash::InternalFlash;
+use app::oled;
+use app::screens;
+use app::time::{self, Millis, SysTickMillis};
+
+/// EEPROM emulation id of the counter, above the ids of the settings of
+/// `menu.rs`, which uses the same pages
+const COUNTER: u16 = 0x100;
 
 // About the main return type:
 // https://www.reddit.com/r/rust/comments/3j22vx/what_is_the_meaning_of_as_a_return_type/
@@ -45,87 +51,67 @@ use ssd1306::Builder;
 #[entry]
 fn main() -> ! {
     let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
 
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
 
     let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
 
     let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
 
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
     let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+    let mut gpioc = dp.GPIOC.split(&mut rcc.apb2);
 
-    let scl = gpiob.pb8.into_alternate_open_drain(&mut gpiob.crh);
-    let sda = gpiob.pb9.into_alternate_open_drain(&mut gpiob.crh);
-
-    let i2c = BlockingI2c::i2c1(
-        dp.I2C1,
-        (scl, sda),
-        &mut afio.mapr,
-        Mode::Fast {
-            frequency: 400_000,
-            duty_cycle: DutyCycle::Ratio2to1,
-        },
-        clocks,
-        &mut rcc.apb1,
-        1000,
-        10,
-        1000,
-        1000,
-    );
-
-    let mut disp: GraphicsMode<_> = Builder::new().connect_i2c(i2c).into();
-
-    disp.init().unwrap();
-    disp.flush().unwrap();
+    // Only the pages and columns that changed are sent on every flush
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
 
-    let button = gpiob.pb5.into_pull_down_input(&mut gpiob.crl);
-
-    let repeat_delay = 16;
-    let repeat_rate = 8;
-    let mut elapsed: u32 = 0;
+    let mut led = gpioc.pc13.into_push_pull_output(&mut gpioc.crh);
+    led.set_high();
 
-    let mut prev_state = false;
-    let mut counter: u32 = 0;
-    let mut s: String<U32> = String::new();
-    write!(s, "{}", counter).unwrap();
+    let button = gpiob.pb5.into_pull_down_input(&mut gpiob.crl);
+    // Hold the button to count faster
+    let mut button = Button::new(button).with_timings(Timings {
+        repeat_delay: 500,
+        repeat_rate: 100,
+        ..Timings::default()
+    });
+
+    // The counter survives resets
+    let mut eeprom = Eeprom::new(unsafe { InternalFlash::new() }, eeprom::DEFAULT_PAGES);
+    // Counts from 0 without it, the writes report their own failures
+    if let Err(e) = eeprom.init() {
+        hprintln!("counter not restored: {:?}", e).ok();
+    }
+    let mut counter: u32 = eeprom.read(COUNTER).unwrap_or(0);
     loop {
-        let state = button.is_high();
-        // value = match (prev_state, state) {
-        //     (false, true) => "ON",
-        //     (true, false) => "OFF",
-        //     _ => value,
-        // };
-        // if (prev_state, state) == (false, true) {
-        //     counter += 1;
-        // }
-        match (prev_state, state) {
-            (false, true) => counter += 1,
-            (true, true) => {
-                elapsed += 1;
-                if elapsed >= repeat_delay {
-                    if (elapsed - repeat_delay) % repeat_rate == 0 {
-                        counter += 1;
-                    }
+        match button.poll(time.millis()) {
+            Some(Event::Pressed) | Some(Event::Repeat) => counter += 1,
+            // Once per press, not on every repeat.  The next press tries again
+            Some(Event::Released) => {
+                if let Err(e) = eeprom.write(COUNTER, counter) {
+                    hprintln!("counter not saved: {:?}", e).ok();
                 }
             }
-            _ => elapsed = 0,
+            _ => {}
         }
-        s.clear();
-        write!(s, "{}", counter).unwrap();
-        let x = 128 - s.len() * 12;
         disp.clear();
-        disp.draw(
-            Font12x16::render_str(s.as_str())
-                .with_stroke(Some(1u8.into()))
-                .translate(Coord::new(x as i32, 28))
-                .into_iter(),
-        );
-        disp.flush().unwrap();
-        prev_state = state;
+        screens::counter(&mut disp, counter);
+        match disp.update() {
+            Some(oled::Event::Lost) => led.set_low(),
+            Some(oled::Event::Found) => led.set_high(),
+            _ => {}
+        }
     }
 }
 
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
 #[exception]
 fn HardFault(ef: &ExceptionFrame) -> ! {
     panic!("{:#?}", ef);
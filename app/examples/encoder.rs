@@ -0,0 +1,93 @@
+//! Show the position and velocity of a rotary encoder (like the KY-040
+//! modules) on the display. A click on its push button sets the position
+//! back to 0.
+//!
+//! The encoder is counted by TIM3 in encoder mode (`app::qei`), so no step is
+//! lost while the display is being updated.
+//!
+//! Wiring connections, with the display connected like in `display.rs`:
+//!
+//! ```
+//! Encoder -> Blue Pill
+//!     GND -> GND
+//!       + -> 3.3V
+//!     CLK -> PA6
+//!      DT -> PA7
+//!      SW -> PA5
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example encoder`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use hal::prelude::*;
+use hal::stm32;
+
+use app::button::{self, Button};
+use app::encoder::Encoder;
+use app::qei::Qei;
+use app::time::{self, Millis, SysTickMillis};
+use app::widgets::{Area, Label, Readout};
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let clk = gpioa.pa6.into_pull_up_input(&mut gpioa.crl);
+    let dt = gpioa.pa7.into_pull_up_input(&mut gpioa.crl);
+    let sw = gpioa.pa5.into_pull_up_input(&mut gpioa.crl);
+
+    let qei = Qei::tim3(dp.TIM3, (clk, dt), &mut rcc.apb1);
+    // 4 counts per detent
+    let mut encoder = Encoder::new(qei, 4).with_button(Button::new(sw).active_low());
+
+    let mut zero = 0;
+    loop {
+        let now = time.millis();
+        if let Some(button::Event::Click) = encoder.button(now) {
+            zero = encoder.position();
+        }
+        let position = encoder.position() - zero;
+        let velocity = encoder.velocity(now);
+
+        Label::new("Position", Area::new(0, 0, 128, 10)).draw(&mut disp);
+        Readout::new(position, Area::new(0, 12, 128, 16)).draw(&mut disp);
+        Label::new("Velocity", Area::new(0, 32, 128, 10)).draw(&mut disp);
+        Readout::new(velocity, Area::new(0, 44, 128, 16))
+            .with_unit("/s")
+            .draw(&mut disp);
+        disp.update();
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
@@ -0,0 +1,96 @@
+//! Count the presses of the button on PB5 using an external interrupt, with
+//! the core sleeping (WFI) in between instead of polling the pin.
+//!
+//! The rising edge of PB5 sets a flag from the `EXTI9_5` interrupt and
+//! toggles the LED.  The main loop wakes up, updates the counter on the
+//! display, waits for the bounces to settle and goes back to sleep.
+//!
+//! The display and the button are connected like in `display2.rs`.
+//!
+//! Run on a Blue Pill with `cargo run --example exti`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use hal::delay::Delay;
+use hal::gpio::gpioc::PC13;
+use hal::gpio::{Output, PushPull};
+use hal::prelude::*;
+use hal::stm32::{self, interrupt};
+
+use app::exti::{self, Edge, Exti, Port};
+use app::screens;
+
+static PRESSED: AtomicBool = AtomicBool::new(false);
+static LED: Mutex<RefCell<Option<PC13<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
+
+fn on_press(_line: u8) {
+    PRESSED.store(true, Ordering::Relaxed);
+    cortex_m::interrupt::free(|cs| {
+        if let Some(led) = LED.borrow(cs).borrow_mut().as_mut() {
+            led.toggle();
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let mut delay = Delay::new(cp.SYST, clocks);
+
+    // Also enables the AFIO clock, needed to route PB5 to the EXTI
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+    let mut gpioc = dp.GPIOC.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let led = gpioc.pc13.into_push_pull_output(&mut gpioc.crh);
+    cortex_m::interrupt::free(|cs| LED.borrow(cs).replace(Some(led)));
+
+    let _button = gpiob.pb5.into_pull_down_input(&mut gpiob.crl);
+    let mut exti = Exti::new(dp.EXTI);
+    exti.listen(Port::B, 5, Edge::Rising, Some(on_press));
+
+    let mut counter: u32 = 0;
+    loop {
+        disp.clear();
+        screens::counter(&mut disp, counter);
+        disp.update();
+
+        exti::sleep_until(|| PRESSED.load(Ordering::Relaxed));
+        counter += 1;
+        // Ignore the edges of the bounces
+        delay.delay_ms(20u16);
+        PRESSED.store(false, Ordering::Relaxed);
+    }
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    exti::handle_pending();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
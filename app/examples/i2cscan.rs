@@ -0,0 +1,198 @@
+//! I2C scanner: list what answers on I2C1 (PB6/PB7, and PB8/PB9 remapped)
+//! and on I2C2 (PB10/PB11), on the display and over serial.
+//!
+//! Every address is probed and the known parts are identified, see
+//! `app::scan`.  The display is connected like in `display.rs`, so it shows
+//! up itself on PB8/PB9.  The serial output works without a display:
+//!
+//! ```
+//! I2C1 PB6/PB7: 0 devices
+//! I2C1 PB8/PB9: 2 devices
+//!   0x3c SSD1306
+//!   0x68 MPU6050
+//! I2C2 PB10/PB11: 0 devices
+//! ```
+//!
+//! The buses are scanned at 100 kHz once, press reset to scan again.  When
+//! everything doesn't fit on the display it shows a page every 2 seconds.
+//!
+//! Wiring connections (USB to serial adapter, 115200 bps), with 4.7k pull-ups
+//! on the buses that don't have them on a module:
+//!
+//! ```
+//! PA9  -> RX
+//! PA10 -> TX
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example i2cscan`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use core::fmt::{self, Write};
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use hal::i2c::{I2c, Mode};
+use hal::prelude::*;
+use hal::serial::{Serial, Tx};
+use hal::stm32::{self, USART1};
+use heapless::consts::*;
+use heapless::{String, Vec};
+use nb::block;
+
+use app::i2c::{self, Bus, Master};
+use app::scan::{self, Devices};
+use app::time::{self, Millis, SysTickMillis};
+use app::widgets::{Area, Label};
+
+/// Milliseconds per page of results
+const PAGE: u32 = 2000;
+
+type Lines = Vec<String<U24>, U64>;
+
+struct SerialWriter(Tx<USART1>);
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            block!(self.0.write(b)).ok();
+        }
+        Ok(())
+    }
+}
+
+fn mode() -> Mode {
+    Mode::Standard { frequency: 100_000 }
+}
+
+/// Print the devices of a bus over serial and add them to the lines of the
+/// display.
+fn report(serial: &mut SerialWriter, lines: &mut Lines, bus: &str, devices: &Devices) {
+    writeln!(serial, "{}: {} devices\r", bus, devices.len()).ok();
+    let mut line = String::new();
+    write!(line, "{}: {}", bus, devices.len()).ok();
+    lines.push(line).ok();
+    for device in devices {
+        writeln!(serial, "  {}\r", device).ok();
+        let mut line = String::new();
+        write!(line, " {}", device).ok();
+        lines.push(line).ok();
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
+    i2c::enable_timeouts();
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+    let rx = gpioa.pa10;
+    let serial = Serial::usart1(
+        dp.USART1,
+        (tx, rx),
+        &mut afio.mapr,
+        115_200.bps(),
+        clocks,
+        &mut rcc.apb2,
+    );
+    let mut serial = SerialWriter(serial.split().0);
+    let mut lines = Lines::new();
+
+    // Every bus gives its peripheral and pins back for the next one and the
+    // display, the pins as inputs like after `split`
+    let scl = gpiob.pb6.into_alternate_open_drain(&mut gpiob.crl);
+    let sda = gpiob.pb7.into_alternate_open_drain(&mut gpiob.crl);
+    let bus = I2c::i2c1(
+        dp.I2C1,
+        (scl, sda),
+        &mut afio.mapr,
+        mode(),
+        clocks,
+        &mut rcc.apb1,
+    );
+    let devices = scan::scan(&mut Master(Bus::I2c1), |_, addr| {
+        i2c::probe(Bus::I2c1, addr)
+    });
+    report(&mut serial, &mut lines, "I2C1 PB6/PB7", &devices);
+    let (i2c1, (scl, sda)) = bus.free();
+    dp.I2C1 = i2c1;
+    gpiob.pb6 = scl.into_floating_input(&mut gpiob.crl);
+    gpiob.pb7 = sda.into_floating_input(&mut gpiob.crl);
+
+    let scl = gpiob.pb8.into_alternate_open_drain(&mut gpiob.crh);
+    let sda = gpiob.pb9.into_alternate_open_drain(&mut gpiob.crh);
+    let bus = I2c::i2c1(
+        dp.I2C1,
+        (scl, sda),
+        &mut afio.mapr,
+        mode(),
+        clocks,
+        &mut rcc.apb1,
+    );
+    let devices = scan::scan(&mut Master(Bus::I2c1), |_, addr| {
+        i2c::probe(Bus::I2c1, addr)
+    });
+    report(&mut serial, &mut lines, "I2C1 PB8/PB9", &devices);
+    let (i2c1, (scl, sda)) = bus.free();
+    dp.I2C1 = i2c1;
+    gpiob.pb8 = scl.into_floating_input(&mut gpiob.crh);
+    gpiob.pb9 = sda.into_floating_input(&mut gpiob.crh);
+
+    let scl = gpiob.pb10.into_alternate_open_drain(&mut gpiob.crh);
+    let sda = gpiob.pb11.into_alternate_open_drain(&mut gpiob.crh);
+    let bus = I2c::i2c2(dp.I2C2, (scl, sda), mode(), clocks, &mut rcc.apb1);
+    let devices = scan::scan(&mut Master(Bus::I2c2), |_, addr| {
+        i2c::probe(Bus::I2c2, addr)
+    });
+    report(&mut serial, &mut lines, "I2C2 PB10/PB11", &devices);
+    let (_, (scl, sda)) = bus.free();
+    gpiob.pb10 = scl.into_floating_input(&mut gpiob.crh);
+    gpiob.pb11 = sda.into_floating_input(&mut gpiob.crh);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+    let rows = disp.size().1 / 8;
+    let pages = (lines.len() as u32 + rows - 1) / rows;
+    loop {
+        let page = (time.millis() / PAGE) % pages;
+        disp.clear();
+        let shown = lines
+            .iter()
+            .skip((page * rows) as usize)
+            .take(rows as usize);
+        for (row, line) in shown.enumerate() {
+            // Bus names inverted, the devices under them
+            Label::new(line, Area::new(0, row as i32 * 8, 128, 8))
+                .with_inverted(!line.starts_with(' '))
+                .draw(&mut disp);
+        }
+        disp.update();
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
@@ -0,0 +1,86 @@
+//! Rotating logo: the image of `app/images/rust.pbm` turns by 90 degrees every
+//! half second while it slides from side to side.
+//!
+//! The image is converted to the page format of the display at build time,
+//! see `build.rs` and `app::image`.  The display is connected like in
+//! `display.rs`.
+//!
+//! Run on a Blue Pill with `cargo run --example image`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use embedded_graphics::coord::Coord;
+use hal::prelude::*;
+use hal::stm32;
+
+use app::image::Rotation;
+use app::images;
+use app::time::{self, Millis, SysTickMillis};
+
+/// Milliseconds per quarter turn
+const TURN: u32 = 500;
+/// Milliseconds per pixel of sliding
+const SLIDE: u32 = 20;
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let logo = images::RUST;
+    let mut rotation = Rotation::R0;
+    let mut last_turn = time.millis();
+    loop {
+        let now = time.millis();
+        if now.wrapping_sub(last_turn) >= TURN {
+            rotation = rotation.next();
+            last_turn = now;
+        }
+
+        // Back and forth between both edges, centered on 128x32 panels too
+        let (width, height) = disp.size();
+        let (w, h) = logo.size(rotation);
+        let range = width - w;
+        let step = (now / SLIDE) % (2 * range);
+        let x = if step < range { step } else { 2 * range - step };
+
+        disp.clear();
+        logo.draw(
+            &mut disp,
+            Coord::new(x as i32, (height as i32 - h as i32) / 2),
+            rotation,
+        );
+        disp.update();
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
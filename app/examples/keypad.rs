@@ -0,0 +1,115 @@
+//! Unlock with a PIN typed on a 4x4 membrane keypad, shown on the display.
+//!
+//! '*' deletes the last digit and '#' checks the PIN. Holding more keys than
+//! the keypad can tell apart shows a warning.
+//!
+//! Wiring connections, with the display connected like in `display.rs`. The
+//! keypad pins are numbered from left to right, looking at the keys:
+//!
+//! ```
+//! Keypad -> Blue Pill
+//!  1 (R1) -> PA0
+//!  2 (R2) -> PA1
+//!  3 (R3) -> PA2
+//!  4 (R4) -> PA3
+//!  5 (C1) -> PA4
+//!  6 (C2) -> PA5
+//!  7 (C3) -> PA6
+//!  8 (C4) -> PA7
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example keypad`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use hal::prelude::*;
+use hal::stm32;
+
+use app::keypad::{self, Entry, EntryKind, Keypad, PinMatrix, TextEntry};
+use app::time::{self, Millis, SysTickMillis};
+use app::widgets::{Area, Label};
+
+const PIN: &str = "1234";
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let mut r1 = gpioa.pa0.into_open_drain_output(&mut gpioa.crl);
+    let mut r2 = gpioa.pa1.into_open_drain_output(&mut gpioa.crl);
+    let mut r3 = gpioa.pa2.into_open_drain_output(&mut gpioa.crl);
+    let mut r4 = gpioa.pa3.into_open_drain_output(&mut gpioa.crl);
+    let c1 = gpioa.pa4.into_pull_up_input(&mut gpioa.crl);
+    let c2 = gpioa.pa5.into_pull_up_input(&mut gpioa.crl);
+    let c3 = gpioa.pa6.into_pull_up_input(&mut gpioa.crl);
+    let c4 = gpioa.pa7.into_pull_up_input(&mut gpioa.crl);
+    let mut rows: [&mut dyn OutputPin<Error = ()>; 4] = [&mut r1, &mut r2, &mut r3, &mut r4];
+    let cols: [&dyn InputPin<Error = ()>; 4] = [&c1, &c2, &c3, &c4];
+    let mut keypad = Keypad::new(PinMatrix::new(&mut rows, &cols), 20);
+
+    let mut entry = TextEntry::new("Enter PIN", EntryKind::Pin, 8);
+    let mut message = "";
+    loop {
+        keypad.poll(time.millis());
+        while let Some(event) = keypad.next_event() {
+            let c = match event {
+                keypad::Event::Pressed(key) => key.char(keypad::KEYMAP_4X4),
+                keypad::Event::Released(_) => None,
+            };
+            match c.and_then(|c| entry.key(c)) {
+                Some(Entry::Done) => {
+                    message = if entry.text() == PIN {
+                        "Unlocked"
+                    } else {
+                        "Wrong PIN"
+                    };
+                    entry.clear();
+                }
+                Some(Entry::Cancelled) => message = "",
+                None => {}
+            }
+        }
+
+        entry.draw(&mut disp, Area::new(0, 0, 128, 32));
+        let status = if keypad.ghosting() {
+            "Too many keys"
+        } else {
+            message
+        };
+        Label::new(status, Area::new(0, 44, 128, 16)).draw(&mut disp);
+        disp.update();
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
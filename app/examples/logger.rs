@@ -0,0 +1,175 @@
+//! Stream PB0, PB1 and the internal reference to the host, 500 times per
+//! second, for `adclog` to record them.
+//!
+//! The channels are sampled by `app::stream`, the frames are sent over
+//! USART1 at 115200 bps in the format of `app::frame`: 12 bytes per frame
+//! for these 3 channels, 6000 bytes per second, about half of what the port
+//! can take.  Frames that can't be sent in time are skipped and the host
+//! sees them missing from the sequence numbers.  Change `RATE` and the
+//! sequence to log other channels, an info frame tells the host about them
+//! once a second.
+//!
+//! Wiring (USB to serial adapter, the inputs like in `potentiometer.rs`):
+//!
+//! ```
+//! PA9  -> RX
+//! PA10 -> TX
+//! Input 1 -> PB0 (0 to 3.3V)
+//! Input 2 -> PB1 (0 to 3.3V)
+//! ```
+//!
+//! Record on the host with `adclog` (see `host/README.md`):
+//!
+//! ```
+//! cargo run --bin adclog -- --port /dev/ttyUSB0 --output trace.csv
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example logger`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use core::ptr::{addr_of, addr_of_mut};
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use embedded_hal::adc::Channel;
+use hal::gpio::gpiob::{PB0, PB1};
+use hal::gpio::Analog;
+use hal::prelude::*;
+use hal::serial::{Serial, Tx};
+use hal::stm32::{self, interrupt, ADC1, USART1};
+use nb::block;
+
+use app::adc::VREFINT_CHANNEL;
+use app::frame::{self, MAX_FRAME};
+use app::sequence::{SampleTime, Sequence};
+use app::stream::{self, Stream, BUFFER_SAMPLES};
+
+const RATE: u32 = 500;
+/// 10 blocks per second
+const FRAMES: usize = 50;
+
+// The last block, copied out of the DMA buffer for the main loop to send
+static mut BLOCK: [u16; BUFFER_SAMPLES / 2] = [0; BUFFER_SAMPLES / 2];
+static BLOCK_LEN: AtomicUsize = AtomicUsize::new(0);
+static BLOCK_NUMBER: AtomicU32 = AtomicU32::new(0);
+static READY: AtomicBool = AtomicBool::new(false);
+
+fn on_block(samples: &[u16], number: u32) {
+    // Still being sent: the block is skipped
+    if READY.load(Ordering::Acquire) {
+        return;
+    }
+    let block =
+        unsafe { slice::from_raw_parts_mut(addr_of_mut!(BLOCK) as *mut u16, samples.len()) };
+    block.copy_from_slice(samples);
+    BLOCK_LEN.store(samples.len(), Ordering::Relaxed);
+    BLOCK_NUMBER.store(number, Ordering::Relaxed);
+    READY.store(true, Ordering::Release);
+}
+
+fn send(tx: &mut Tx<USART1>, bytes: &[u8]) {
+    for &b in bytes {
+        block!(tx.write(b)).ok();
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .sysclk(72.mhz())
+        .pclk1(36.mhz())
+        .adcclk(12.mhz())
+        .freeze(&mut flash.acr);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+    let rx = gpioa.pa10;
+    let serial = Serial::usart1(
+        dp.USART1,
+        (tx, rx),
+        &mut afio.mapr,
+        115_200.bps(),
+        clocks,
+        &mut rcc.apb2,
+    );
+    let mut tx = serial.split().0;
+
+    let _in1 = gpiob.pb0.into_analog(&mut gpiob.crl);
+    let _in2 = gpiob.pb1.into_analog(&mut gpiob.crl);
+    let channels = [
+        <PB0<Analog> as Channel<ADC1>>::channel(),
+        <PB1<Analog> as Channel<ADC1>>::channel(),
+        VREFINT_CHANNEL,
+    ];
+    let sequence = Sequence::of(&[
+        (channels[0], SampleTime::T55_5),
+        (channels[1], SampleTime::T55_5),
+        (channels[2], SampleTime::T71_5),
+    ])
+    .unwrap();
+    let mut stream = Stream::new(
+        dp.ADC1,
+        dp.TIM3,
+        dp.DMA1,
+        &sequence,
+        RATE,
+        FRAMES,
+        clocks,
+        &mut rcc.ahb,
+        &mut rcc.apb1,
+        &mut rcc.apb2,
+    )
+    .unwrap();
+    let rate_millihz = stream.rate_millihz();
+    stream.start(on_block);
+
+    let mut out = [0; MAX_FRAME];
+    loop {
+        if !READY.load(Ordering::Acquire) {
+            continue;
+        }
+        let number = BLOCK_NUMBER.load(Ordering::Relaxed) as usize;
+        let len = BLOCK_LEN.load(Ordering::Relaxed);
+        let block = unsafe { slice::from_raw_parts(addr_of!(BLOCK) as *const u16, len) };
+        for (i, samples) in block.chunks(channels.len()).enumerate() {
+            // The frames count on through the skipped blocks
+            let count = number * FRAMES + i;
+            if count % RATE as usize == 0 {
+                let len = frame::encode_info(rate_millihz, &channels, &mut out);
+                send(&mut tx, &out[..len]);
+            }
+            let len = frame::encode_samples(count as u16, samples, &mut out);
+            send(&mut tx, &out[..len]);
+        }
+        READY.store(false, Ordering::Release);
+    }
+}
+
+#[interrupt]
+fn DMA1_CHANNEL1() {
+    stream::handle_interrupt();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
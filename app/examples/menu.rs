@@ -0,0 +1,378 @@
+//! Settings menu on the display, navigated with three buttons: up, down and
+//! select.  A long press on select goes back, holding up or down repeats.
+//! With `ONE_BUTTON` only select is used: a click moves to the next item, a
+//! long press selects and a double click goes back.
+//!
+//! The "LED" toggle switches the on-board LED and the "Blink" submenu makes
+//! it blink.  The "Display" submenu sets the contrast, inversion and rotation
+//! of the display, and the screensaver: after "Saver delay" seconds without
+//! a button press the display is dimmed, 4 times later it is turned off.
+//! The settings are kept in flash with `app::eeprom`, using the menu ids as
+//! variable ids, and are brought back into the ranges of their items when
+//! read back.
+//!
+//! The settings can be changed over serial as well (115200 bps), with a line
+//! per setting: its label, then the value for numbers and "on" or "off" for
+//! toggles.
+//!
+//! ```
+//! contrast 64
+//! saver delay 60
+//! led on
+//! reset counter
+//! ```
+//!
+//! The menu logic is in `app::menu` and is tested on the host.
+//!
+//! Wiring connections, with the display connected like in `display.rs`:
+//!
+//! ```
+//! Buttons -> Blue Pill
+//!  select -> PB5
+//!      up -> PB6
+//!    down -> PB7
+//!
+//! USB to serial adapter:
+//!      RX -> PA9
+//!      TX -> PA10
+//! ```
+//!
+//! The other end of the buttons goes to 3.3V, the pins are pulled down.
+//!
+//! Run on a Blue Pill with `cargo run --example menu`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use hal::prelude::*;
+use hal::serial::{Serial, Tx};
+use hal::stm32::{self, USART1};
+use heapless::consts::*;
+use heapless::String;
+use nb::block;
+
+use app::button::{self, Button, Timings};
+use app::eeprom::{self, Eeprom};
+use app::flash::{Flash, InternalFlash};
+use app::menu::{self, CommandError, Event, Input, Item, Menu, Press, Values};
+use app::oled;
+use app::screensaver::{self, Screensaver};
+use app::time::{self, Millis, SysTickMillis};
+
+const LED: u8 = 0;
+const BLINK: u8 = 1;
+const PERIOD: u8 = 2;
+const COUNTER: u8 = 3;
+const RESET: u8 = 4;
+const CONTRAST: u8 = 5;
+const INVERT: u8 = 6;
+const ROTATE: u8 = 7;
+const SAVER: u8 = 8;
+const SAVER_DELAY: u8 = 9;
+
+/// The variables kept in flash
+const STORED: &[u8] = &[
+    LED,
+    BLINK,
+    PERIOD,
+    COUNTER,
+    CONTRAST,
+    INVERT,
+    ROTATE,
+    SAVER,
+    SAVER_DELAY,
+];
+
+const BLINK_MENU: &[Item] = &[
+    Item::Toggle {
+        label: "Enabled",
+        id: BLINK,
+    },
+    Item::Number {
+        label: "Period",
+        id: PERIOD,
+        min: 1,
+        max: 20,
+        step: 1,
+    },
+];
+
+const DISPLAY_MENU: &[Item] = &[
+    Item::Number {
+        label: "Contrast",
+        id: CONTRAST,
+        min: 0,
+        max: 255,
+        step: 16,
+    },
+    Item::Toggle {
+        label: "Invert",
+        id: INVERT,
+    },
+    Item::Toggle {
+        label: "Rotate",
+        id: ROTATE,
+    },
+    Item::Toggle {
+        label: "Saver",
+        id: SAVER,
+    },
+    Item::Number {
+        label: "Saver delay",
+        id: SAVER_DELAY,
+        min: 10,
+        max: 600,
+        step: 10,
+    },
+];
+
+const MENU: &[Item] = &[
+    Item::Toggle {
+        label: "LED",
+        id: LED,
+    },
+    Item::Submenu {
+        label: "Blink",
+        items: BLINK_MENU,
+    },
+    Item::Number {
+        label: "Counter",
+        id: COUNTER,
+        min: -100,
+        max: 100,
+        step: 5,
+    },
+    Item::Action {
+        label: "Reset counter",
+        id: RESET,
+    },
+    Item::Submenu {
+        label: "Display",
+        items: DISPLAY_MENU,
+    },
+];
+
+struct Settings {
+    led: bool,
+    blink: bool,
+    /// In tenths of a second
+    period: i32,
+    counter: i32,
+    display: oled::Settings,
+    saver: bool,
+    /// In seconds
+    saver_delay: i32,
+}
+
+impl Values for Settings {
+    fn get(&self, id: u8) -> i32 {
+        match id {
+            LED => self.led as i32,
+            BLINK => self.blink as i32,
+            PERIOD => self.period,
+            COUNTER => self.counter,
+            CONTRAST => i32::from(self.display.contrast),
+            INVERT => self.display.inverted as i32,
+            ROTATE => self.display.rotated as i32,
+            SAVER => self.saver as i32,
+            SAVER_DELAY => self.saver_delay,
+            _ => 0,
+        }
+    }
+
+    fn set(&mut self, id: u8, value: i32) {
+        match id {
+            LED => self.led = value != 0,
+            BLINK => self.blink = value != 0,
+            PERIOD => self.period = value,
+            COUNTER => self.counter = value,
+            CONTRAST => self.display.contrast = value as u8,
+            INVERT => self.display.inverted = value != 0,
+            ROTATE => self.display.rotated = value != 0,
+            SAVER => self.saver = value != 0,
+            SAVER_DELAY => self.saver_delay = value,
+            _ => {}
+        }
+    }
+}
+
+const ONE_BUTTON: bool = false;
+
+/// Store a setting, telling over serial when the flash refuses it: the
+/// setting then only lasts until the next reset.
+fn save<F: Flash>(eeprom: &mut Eeprom<F>, tx: &mut Tx<USART1>, id: u8, value: i32) {
+    if eeprom.write(u16::from(id), value as u32).is_err() {
+        for &b in b"flash error\r\n" {
+            block!(tx.write(b)).ok();
+        }
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+    let mut gpioc = dp.GPIOC.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let mut select = Button::new(gpiob.pb5.into_pull_down_input(&mut gpiob.crl));
+    if !ONE_BUTTON {
+        // Don't wait to know if a click is a double click
+        select = select.with_timings(Timings {
+            double_click: 0,
+            ..Timings::default()
+        });
+    }
+    let mut up = Button::new(gpiob.pb6.into_pull_down_input(&mut gpiob.crl));
+    let mut down = Button::new(gpiob.pb7.into_pull_down_input(&mut gpiob.crl));
+    let mut led = gpioc.pc13.into_push_pull_output(&mut gpioc.crh);
+
+    let tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+    let rx = gpioa.pa10;
+    let serial = Serial::usart1(
+        dp.USART1,
+        (tx, rx),
+        &mut afio.mapr,
+        115_200.bps(),
+        clocks,
+        &mut rcc.apb2,
+    );
+    let (mut tx, mut rx) = serial.split();
+    let mut line: String<U32> = String::new();
+
+    let mut settings = Settings {
+        led: false,
+        blink: false,
+        period: 10,
+        counter: 0,
+        display: oled::Settings::default(),
+        saver: true,
+        saver_delay: 30,
+    };
+    let mut eeprom = Eeprom::new(unsafe { InternalFlash::new() }, eeprom::DEFAULT_PAGES);
+    eeprom.init().unwrap();
+    for &id in STORED {
+        // A period of 0 would divide by zero
+        if let Some(value) = eeprom.read(u16::from(id)) {
+            settings.set(id, menu::clamp(MENU, id, value as i32));
+        }
+    }
+    let mut menu = Menu::new("Settings", MENU);
+    let mut saver = Screensaver::new(screensaver::Timings::default(), time.millis());
+
+    loop {
+        let now = time.millis();
+        let input = match select.poll(now) {
+            Some(button::Event::Click) if ONE_BUTTON => Some(Input::from_single_button(Press::Short)),
+            Some(button::Event::LongPress) if ONE_BUTTON => {
+                Some(Input::from_single_button(Press::Long))
+            }
+            Some(button::Event::DoubleClick) => Some(Input::from_single_button(Press::Double)),
+            Some(button::Event::Click) => Some(Input::Select),
+            Some(button::Event::LongPress) => Some(Input::Back),
+            _ => None,
+        };
+        let input = input.or_else(|| match up.poll(now) {
+            Some(button::Event::Pressed) | Some(button::Event::Repeat) => Some(Input::Prev),
+            _ => None,
+        });
+        let input = input.or_else(|| match down.poll(now) {
+            Some(button::Event::Pressed) | Some(button::Event::Repeat) => Some(Input::Next),
+            _ => None,
+        });
+
+        let mut event = None;
+        if let Some(input) = input {
+            saver.activity(now);
+            event = menu.handle(input, &mut settings);
+        }
+
+        // Commands over serial, a line at a time
+        if let Ok(byte) = rx.read() {
+            saver.activity(now);
+            if byte == b'\r' || byte == b'\n' {
+                if !line.is_empty() {
+                    let reply = match menu::command(&line, MENU, &mut settings) {
+                        Ok(e) => {
+                            event = Some(e);
+                            "ok"
+                        }
+                        Err(CommandError::Unknown) => "unknown setting",
+                        Err(CommandError::Value) => "invalid value",
+                    };
+                    for b in reply.bytes().chain(b"\r\n".iter().cloned()) {
+                        block!(tx.write(b)).ok();
+                    }
+                    line = String::new();
+                }
+            } else if line.push(byte as char).is_err() {
+                line = String::new();
+            }
+        }
+
+        if let Some(event) = event {
+            match event {
+                Event::Changed(id, value) => save(&mut eeprom, &mut tx, id, value),
+                Event::Action(RESET) => {
+                    settings.counter = 0;
+                    save(&mut eeprom, &mut tx, COUNTER, 0);
+                }
+                // There is nothing to go back to from the top level
+                Event::Exit => menu = Menu::new("Settings", MENU),
+                _ => {}
+            }
+        }
+
+        let blink_on = (now / (settings.period as u32 * 100)) % 2 == 0;
+        // The LED is active low
+        if settings.led || (settings.blink && blink_on) {
+            led.set_low();
+        } else {
+            led.set_high();
+        }
+
+        let delay = settings.saver_delay as u32 * 1000;
+        saver.set_enabled(settings.saver);
+        saver.set_timings(screensaver::Timings {
+            dim: delay,
+            off: 4 * delay,
+            ..screensaver::Timings::default()
+        });
+        saver.apply(&mut disp, now);
+        disp.set_settings(settings.display);
+
+        // Cleared first, the shifted menu doesn't cover the old one
+        disp.clear();
+        menu.draw(&mut disp, &settings);
+        disp.update();
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
@@ -0,0 +1,129 @@
+//! Drive a DC motor through a low-side transistor at the speed of the
+//! potentiometer on PB0, with a soft start, a brake and an over-current
+//! shutdown.
+//!
+//! See `app::motor`.  Turning the potentiometer to its start brakes the
+//! motor; after an over-current the LED lights and the motor stays off until
+//! the potentiometer is turned back to its start.  Find `MIN_DUTY` with the
+//! current limit off: the speed at which the motor just starts turning.
+//!
+//! Wiring (the motor from its own supply, with its ground connected to the
+//! Blue Pill's; the potentiometer like in `potentiometer.rs`):
+//!
+//! ```
+//! PA0 -> gate of the 30N06L (or the base resistor of the driver)
+//! PA1 -> gate of the brake transistor, across the motor (optional)
+//! PB1 -> 1 kOhm -> source of the 30N06L, on the 0.1 Ohm shunt to GND
+//!        and 1 uF to GND
+//! Potentiometer -> PB0 (0 to 3.3V)
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example motor`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use embedded_hal::digital::v2::OutputPin;
+use hal::prelude::*;
+use hal::stm32;
+use heapless::consts::*;
+
+use app::adc::Adc;
+use app::filter::{Exponential, Median};
+use app::mapping::Mapping;
+use app::motor::{Config, Motor, State, FULL};
+use app::time::{self, Millis, SysTickMillis};
+
+/// Per mille, found by trying.
+const MIN_DUTY: u16 = 150;
+/// Average mA, through a shunt of `SHUNT_MOHM`.
+const CURRENT_LIMIT: u32 = 2000;
+const SHUNT_MOHM: u32 = 100;
+
+#[entry]
+fn main() -> ! {
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let dp = stm32::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .sysclk(72.mhz())
+        .pclk1(36.mhz())
+        .adcclk(12.mhz())
+        .freeze(&mut flash.acr);
+
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+    let mut gpioc = dp.GPIOC.split(&mut rcc.apb2);
+
+    let mut led = gpioc.pc13.into_push_pull_output(&mut gpioc.crh);
+    let _ = OutputPin::set_high(&mut led);
+
+    let config = Config::default()
+        .with_ramp(1000)
+        .with_min_duty(MIN_DUTY)
+        .with_current_limit(CURRENT_LIMIT, SHUNT_MOHM);
+    let gate = gpioa.pa0.into_alternate_push_pull(&mut gpioa.crl);
+    let brake = gpioa.pa1.into_push_pull_output(&mut gpioa.crl);
+    let pwm = dp.TIM2.pwm(
+        gate,
+        &mut afio.mapr,
+        config.frequency_hz.hz(),
+        clocks,
+        &mut rcc.apb1,
+    );
+    let mut motor = Motor::new(pwm, brake, config);
+
+    let mut adc = Adc::new(dp.ADC1, clocks, &mut rcc.apb2);
+    let mut pot = gpiob.pb0.into_analog(&mut gpiob.crl);
+    let mut sense = gpiob.pb1.into_analog(&mut gpiob.crl);
+    let mut median: Median<U5> = Median::new();
+    let mut smooth = Exponential::new(2);
+    // The start of the potentiometer is 0, to brake
+    let speeds = Mapping::new(0, 4095, 0, u32::from(FULL)).with_dead_zone(64);
+
+    loop {
+        motor.sense(adc.read_mv(&mut sense));
+
+        let reading = smooth.update(median.update(adc.read(&mut pot)));
+        let speed = speeds.map(u32::from(reading)) as u16;
+        match motor.state() {
+            State::OverCurrent => {
+                let _ = OutputPin::set_low(&mut led);
+                if speed == 0 {
+                    let _ = OutputPin::set_high(&mut led);
+                    motor.reset();
+                }
+            }
+            State::Run if speed == 0 => motor.brake(),
+            State::Run => motor.set_speed(speed),
+            _ if speed > 0 => motor.set_speed(speed),
+            _ => {}
+        }
+        motor.update(time.millis());
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
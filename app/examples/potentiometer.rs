@@ -1,3 +1,12 @@
+//! Read the voltage on PB0 in millivolts, the supply voltage and the die
+//! temperature, and print them with semihosting.
+//!
+//! The readings are compensated for the supply voltage with the internal
+//! reference, see `app::adc` and `app::calibration`, and averaged over the
+//! last 16 readings against the noise, see `app::filter`.
+//!
+//! Run on a Blue Pill with `cargo run --example potentiometer`.
+
 #![deny(unsafe_code)]
 #![no_main]
 #![no_std]
@@ -5,10 +14,15 @@
 use panic_semihosting as _;
 
 use cortex_m_rt::entry;
-use stm32f1xx_hal::{adc, pac, prelude::*};
+use stm32f1xx_hal::{pac, prelude::*};
 
 use cortex_m_semihosting::hprintln;
 
+use heapless::consts::*;
+
+use app::adc::Adc;
+use app::filter::MovingAverage;
+
 #[entry]
 fn main() -> ! {
     // Aquire peripherals
@@ -24,8 +38,8 @@ fn main() -> ! {
     let clocks = rcc.cfgr.adcclk(2.mhz()).freeze(&mut flash.acr);
     hprintln!("adc freq: {}", clocks.adcclk().0).unwrap();
 
-    // Setup ADC
-    let mut adc1 = adc::Adc::adc1(p.ADC1, &mut rcc.apb2);
+    // Setup ADC, with its self-calibration
+    let mut adc1 = Adc::new(p.ADC1, clocks, &mut rcc.apb2);
 
     // Setup GPIOB
     let mut gpiob = p.GPIOB.split(&mut rcc.apb2);
@@ -33,8 +47,24 @@ fn main() -> ! {
     // Configure pb0 as an analog input
     let mut ch0 = gpiob.pb0.into_analog(&mut gpiob.crl);
 
+    let mut average: MovingAverage<U16> = MovingAverage::new();
+
     loop {
-        let data: u16 = adc1.read(&mut ch0).unwrap();
-        hprintln!("adc1: {}", data).unwrap();
+        // The supply drifts with the load and the temperature
+        let vdd = adc1.update_vdd();
+        let data = average.update(adc1.read(&mut ch0));
+        let mv = adc1.calibration().to_mv(data, vdd);
+        let temperature = adc1.temperature();
+        let sign = if temperature < 0 { "-" } else { "" };
+        hprintln!(
+            "adc1: {} ({} mV), vdd: {} mV, temperature: {}{}.{} C",
+            data,
+            mv,
+            vdd,
+            sign,
+            temperature.abs() / 10,
+            temperature.abs() % 10
+        )
+        .unwrap();
     }
 }
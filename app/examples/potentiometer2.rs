@@ -7,6 +7,11 @@ use panic_semihosting as _;
 use cortex_m_rt::entry;
 use stm32f1xx_hal::{adc, pac, prelude::*};
 
+use heapless::consts::*;
+
+use app::filter::{Exponential, Hysteresis, Median};
+use app::mapping::{Curve, Mapping};
+
 #[entry]
 fn main() -> ! {
     // Aquire peripherals
@@ -40,13 +45,24 @@ fn main() -> ! {
         .TIM2
         .pwm(c1, &mut afio.mapr, 1000.hz(), clocks, &mut rcc.apb1);
     let max = pwm.get_max_duty();
+    // The upper half of the range, a little dead zone to reach the ends and
+    // gamma correction so that the brightness looks linear
+    let mapping = Mapping::new(2000, 4000, 0, u32::from(max))
+        .with_dead_zone(16)
+        .with_curve(Curve::Gamma(22));
     pwm.enable();
     // let mut pa0 = gpioa.pa0.into_push_pull_output(&mut gpioa.crl);
     // pa0.set_high();
 
+    // The readings jump by a few counts and the PWM adds spikes: without
+    // filtering the LED flickers
+    let mut median: Median<U5> = Median::new();
+    let mut smooth = Exponential::new(3);
+    let mut band = Hysteresis::new(8);
+
     loop {
-        let data: u16 = adc1.read(&mut ch0).unwrap();
-        let duty: f32 = (data as f32 - 2000.0) * max as f32 / 2000.0;
-        pwm.set_duty(duty as u16);
+        let reading: u16 = adc1.read(&mut ch0).unwrap();
+        let data = band.update(smooth.update(median.update(reading)));
+        pwm.set_duty(mapping.map(u32::from(data)) as u16);
     }
 }
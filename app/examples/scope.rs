@@ -0,0 +1,119 @@
+//! Oscilloscope on the display: the voltage on PB0 (ADC1 channel 8), sampled
+//! at a rate set by TIM3.
+//!
+//! Select (PB5) picks the setting to change, shown inverted: timebase, volts
+//! per division, trigger level or trigger slope.  Up (PB6) and down (PB7)
+//! change it.  The bottom line shows the maximum, minimum and average of the
+//! trace in volts.  A "?" in front of the trigger level means the trigger
+//! wasn't found and the trace isn't synchronized.
+//!
+//! The trigger detection and the decimation are in `app::scope` and are
+//! tested on the host, the sampling is done by `app::sampler`.
+//!
+//! Wiring connections, with the display connected like in `display.rs`, the
+//! buttons like in `menu.rs` and the input like in `potentiometer.rs`:
+//!
+//! ```
+//! Input -> PB0 (0 to 3.3V)
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example scope`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use hal::prelude::*;
+use hal::stm32::{self, interrupt};
+
+use app::button::{Button, Event, Timings};
+use app::sampler::{self, Sampler};
+use app::scope::Scope;
+use app::time::{self, Millis, SysTickMillis};
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    // A conversion takes 26 ADC cycles, 6.5 us at 4 MHz
+    let clocks = rcc.cfgr.adcclk(4.mhz()).freeze(&mut flash.acr);
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let mut select = Button::new(gpiob.pb5.into_pull_down_input(&mut gpiob.crl)).with_timings(
+        Timings {
+            double_click: 0,
+            ..Timings::default()
+        },
+    );
+    let mut up = Button::new(gpiob.pb6.into_pull_down_input(&mut gpiob.crl));
+    let mut down = Button::new(gpiob.pb7.into_pull_down_input(&mut gpiob.crl));
+
+    let input = gpiob.pb0.into_analog(&mut gpiob.crl);
+    let mut sampler = Sampler::new(
+        dp.ADC1,
+        dp.TIM3,
+        input,
+        clocks,
+        &mut rcc.apb1,
+        &mut rcc.apb2,
+    );
+
+    let mut scope = Scope::new();
+    let mut plan = scope.plan(sampler.timer_hz());
+    sampler.start(&plan);
+    loop {
+        let now = time.millis();
+        let mut changed = true;
+        match (select.poll(now), up.poll(now), down.poll(now)) {
+            (Some(Event::Click), _, _) => scope.selected = scope.selected.next(),
+            (_, Some(Event::Pressed), _) | (_, Some(Event::Repeat), _) => scope.adjust(1),
+            (_, _, Some(Event::Pressed)) | (_, _, Some(Event::Repeat)) => scope.adjust(-1),
+            _ => changed = false,
+        }
+
+        if changed {
+            // Start over, a slow timebase would take seconds to show it
+            plan = scope.plan(sampler.timer_hz());
+            sampler.start(&plan);
+        } else if sampler.is_done() {
+            scope.update(sampler.samples(), &plan);
+            sampler.start(&plan);
+        } else {
+            continue;
+        }
+        scope.draw(&mut disp);
+        disp.update();
+    }
+}
+
+#[interrupt]
+fn ADC1_2() {
+    sampler::handle_interrupt();
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
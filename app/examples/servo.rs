@@ -0,0 +1,126 @@
+//! Drive four hobby servos from TIM2: the first follows the potentiometer on
+//! PB0, the second sweeps from end to end at a limited speed, the other two
+//! stay in the middle.
+//!
+//! The pulses are 50 Hz, see `app::servo`.  The endpoints of `CONFIG` are
+//! the safe 1000 to 2000 us: widen them for the servos at hand, little by
+//! little, until they stop just before their end stops.
+//!
+//! Wiring (the servos need their own 5V supply, with its ground connected
+//! to the Blue Pill's; the potentiometer like in `potentiometer.rs`):
+//!
+//! ```
+//! PA0 -> signal of servo 1
+//! PA1 -> signal of servo 2
+//! PA2 -> signal of servo 3
+//! PA3 -> signal of servo 4
+//! Potentiometer -> PB0 (0 to 3.3V)
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example servo`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use hal::prelude::*;
+use hal::stm32;
+use heapless::consts::*;
+
+use app::adc::Adc;
+use app::filter::{Exponential, Median};
+use app::servo::{Config, Servo, FREQUENCY_HZ};
+use app::time::{self, Millis, SysTickMillis};
+
+const CONFIG: Config = Config {
+    min_us: 1000,
+    max_us: 2000,
+    range_deg: 180,
+    speed: 0,
+};
+/// Of the sweeping servo, microseconds per second: 2 s from end to end.
+const SWEEP_SPEED: u32 = 500;
+/// Between readings of the potentiometer, in milliseconds.
+const FOLLOW_PERIOD: u32 = 20;
+
+#[entry]
+fn main() -> ! {
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let dp = stm32::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .sysclk(72.mhz())
+        .pclk1(36.mhz())
+        .adcclk(12.mhz())
+        .freeze(&mut flash.acr);
+
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let pins = (
+        gpioa.pa0.into_alternate_push_pull(&mut gpioa.crl),
+        gpioa.pa1.into_alternate_push_pull(&mut gpioa.crl),
+        gpioa.pa2.into_alternate_push_pull(&mut gpioa.crl),
+        gpioa.pa3.into_alternate_push_pull(&mut gpioa.crl),
+    );
+    let (c1, c2, c3, c4) = dp.TIM2.pwm(
+        pins,
+        &mut afio.mapr,
+        FREQUENCY_HZ.hz(),
+        clocks,
+        &mut rcc.apb1,
+    );
+    let mut follower = Servo::new(c1, CONFIG);
+    let mut sweeper = Servo::new(c2, CONFIG.with_speed(SWEEP_SPEED));
+    // The channels are different types, they don't fit in an array
+    let mut third = Servo::new(c3, CONFIG);
+    let mut fourth = Servo::new(c4, CONFIG);
+    third.set_angle(90);
+    fourth.set_angle(90);
+
+    let mut adc = Adc::new(dp.ADC1, clocks, &mut rcc.apb2);
+    let mut pot = gpiob.pb0.into_analog(&mut gpiob.crl);
+    // The servo would twitch with the noise of the readings
+    let mut median: Median<U5> = Median::new();
+    let mut smooth = Exponential::new(2);
+
+    let mut last_reading = time.millis();
+    loop {
+        let now = time.millis();
+        if now.wrapping_sub(last_reading) >= FOLLOW_PERIOD {
+            last_reading = now;
+            let reading = adc.read(&mut pot);
+            follower.follow(smooth.update(median.update(reading)));
+        }
+
+        if !sweeper.is_moving() {
+            let angle = if sweeper.angle() == 0 { 180 } else { 0 };
+            sweeper.set_angle(angle);
+        }
+        sweeper.update(now);
+    }
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
@@ -0,0 +1,153 @@
+//! Sample PB0, PB1 and the internal reference 1000 times per second each,
+//! with ADC1 in scan mode and DMA, and show the average of every channel in
+//! millivolts.
+//!
+//! TIM3 triggers the scans, DMA1 moves the samples to a double buffer and
+//! the callback averages every block of 100 frames (10 blocks per second)
+//! while the other one is being filled, see `app::stream`.  The main loop
+//! only draws.
+//!
+//! The inputs are connected like in `potentiometer.rs`, the display like in
+//! `display.rs`:
+//!
+//! ```
+//! Input 1 -> PB0 (0 to 3.3V)
+//! Input 2 -> PB1 (0 to 3.3V)
+//! ```
+//!
+//! Run on a Blue Pill with `cargo run --example stream`.
+
+#![no_std]
+#![no_main]
+
+extern crate cortex_m;
+extern crate cortex_m_rt as rt;
+extern crate panic_semihosting;
+extern crate stm32f1xx_hal as hal;
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use embedded_hal::adc::Channel;
+use hal::gpio::gpiob::{PB0, PB1};
+use hal::gpio::Analog;
+use hal::prelude::*;
+use hal::stm32::{self, interrupt, ADC1};
+use heapless::consts::*;
+use heapless::String;
+
+use app::adc::VREFINT_CHANNEL;
+use app::calibration::Calibration;
+use app::sequence::{SampleTime, Sequence};
+use app::stream::{self, Stream};
+use app::widgets::{Area, Label};
+
+const RATE: u32 = 1000;
+const FRAMES: usize = 100;
+const CHANNELS: usize = 3;
+
+/// Averages of the last block, per channel
+static AVERAGES: [AtomicU32; CHANNELS] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
+
+fn on_block(samples: &[u16], _number: u32) {
+    for (channel, average) in AVERAGES.iter().enumerate() {
+        let sum: u32 = samples
+            .iter()
+            .skip(channel)
+            .step_by(CHANNELS)
+            .map(|&s| u32::from(s))
+            .sum();
+        average.store(sum / FRAMES as u32, Ordering::Relaxed);
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .sysclk(72.mhz())
+        .pclk1(36.mhz())
+        .adcclk(12.mhz())
+        .freeze(&mut flash.acr);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let _in1 = gpiob.pb0.into_analog(&mut gpiob.crl);
+    let _in2 = gpiob.pb1.into_analog(&mut gpiob.crl);
+    // At 12 MHz, 55.5 cycles (4.6 us) are enough for potentiometers of up to
+    // 50k, and Vrefint needs at least 5.1 us: 71.5 cycles are 6 us
+    let sequence = Sequence::of(&[
+        (<PB0<Analog> as Channel<ADC1>>::channel(), SampleTime::T55_5),
+        (<PB1<Analog> as Channel<ADC1>>::channel(), SampleTime::T55_5),
+        (VREFINT_CHANNEL, SampleTime::T71_5),
+    ])
+    .unwrap();
+    let mut stream = Stream::new(
+        dp.ADC1,
+        dp.TIM3,
+        dp.DMA1,
+        &sequence,
+        RATE,
+        FRAMES,
+        clocks,
+        &mut rcc.ahb,
+        &mut rcc.apb1,
+        &mut rcc.apb2,
+    )
+    .unwrap();
+    stream.start(on_block);
+
+    let calibration = Calibration::default();
+    loop {
+        let vdd = calibration.vdd_mv(AVERAGES[2].load(Ordering::Relaxed) as u16);
+        let lines = [
+            (
+                "PB0",
+                calibration.to_mv(AVERAGES[0].load(Ordering::Relaxed) as u16, vdd),
+            ),
+            (
+                "PB1",
+                calibration.to_mv(AVERAGES[1].load(Ordering::Relaxed) as u16, vdd),
+            ),
+            ("VDD", vdd),
+        ];
+        for (row, &(name, mv)) in lines.iter().enumerate() {
+            let mut text: String<U24> = String::new();
+            write!(text, "{} {:4} mV", name, mv).ok();
+            Label::new(&text, Area::new(0, row as i32 * 8, 128, 8)).draw(&mut disp);
+        }
+        let mut text: String<U24> = String::new();
+        write!(
+            text,
+            "{} blocks, {} lost",
+            stream.blocks(),
+            stream.overruns()
+        )
+        .ok();
+        Label::new(&text, Area::new(0, 24, 128, 8)).draw(&mut disp);
+        disp.update();
+    }
+}
+
+#[interrupt]
+fn DMA1_CHANNEL1() {
+    stream::handle_interrupt();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
@@ -0,0 +1,238 @@
+//! Receive files over USART1 with XMODEM/YMODEM and store them in flash.
+//!
+//! The files are written one after the other, each starting at a page
+//! boundary, into the upper 64 KiB of the flash (see `app::flash`), except
+//! for its last two pages, used by `app::eeprom`.  The progress is shown on
+//! the SSD1306 display.
+//!
+//! Wiring (USB to serial adapter, the display connected like in `display.rs`):
+//!
+//! ```
+//! PA9  -> RX
+//! PA10 -> TX
+//! ```
+//!
+//! Send a file from the host with lrzsz:
+//!
+//! ```
+//! sz --ymodem song.bin < /dev/ttyUSB0 > /dev/ttyUSB0
+//! sx -k image.bin < /dev/ttyUSB0 > /dev/ttyUSB0 # (with MODE = Mode::Xmodem)
+//! ```
+
+#![no_std]
+#![no_main]
+
+extern crate panic_semihosting;
+
+use core::fmt::Write;
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::{entry, exception};
+use embedded_graphics::fonts::Font6x8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rect;
+use heapless::consts::*;
+use heapless::String;
+use nb::block;
+use stm32f1xx_hal::pac::{self, USART1};
+use stm32f1xx_hal::prelude::*;
+use stm32f1xx_hal::serial::{Rx, Serial, Tx};
+
+use app::display::Display;
+use app::eeprom;
+use app::flash::{self, Flash, InternalFlash, PAGE_SIZE};
+use app::time::{self, Millis, SysTickMillis};
+use app::xmodem::{self, Mode, Port, Sink};
+
+const MODE: Mode = Mode::Ymodem;
+const REGION_START: u32 = flash::UPPER_FLASH;
+// Up to the pages of the EEPROM emulation
+const REGION_SIZE: u32 = eeprom::DEFAULT_PAGES[0] - REGION_START;
+
+struct SerialPort<'a> {
+    tx: Tx<USART1>,
+    rx: Rx<USART1>,
+    time: &'a SysTickMillis,
+}
+
+impl<'a> Port for SerialPort<'a> {
+    fn read(&mut self, timeout_ms: u32) -> Option<u8> {
+        let start = self.time.millis();
+        loop {
+            // Overrun and framing errors are left to the block CRC
+            if let Ok(b) = self.rx.read() {
+                return Some(b);
+            }
+            if self.time.millis().wrapping_sub(start) >= timeout_ms {
+                return None;
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        block!(self.tx.write(byte)).ok();
+    }
+}
+
+struct FlashSink {
+    flash: InternalFlash,
+    disp: Display,
+    /// Start of the current file
+    base: u32,
+    /// End of the data written so far
+    end: u32,
+    /// End of the erased area
+    erased: u32,
+}
+
+impl FlashSink {
+    fn show(&mut self, line0: &str, line1: &str, fill: u32) {
+        self.disp.clear();
+        self.disp.draw(
+            Font6x8::render_str(line0)
+                .with_stroke(Some(1u8.into()))
+                .into_iter(),
+        );
+        self.disp.draw(
+            Font6x8::render_str(line1)
+                .with_stroke(Some(1u8.into()))
+                .translate(Coord::new(0, 16))
+                .into_iter(),
+        );
+        self.disp.draw(
+            Rect::new(Coord::new(0, 40), Coord::new(127, 50))
+                .with_stroke(Some(1u8.into()))
+                .into_iter(),
+        );
+        if fill > 0 {
+            self.disp.draw(
+                Rect::new(Coord::new(0, 40), Coord::new(fill as i32, 50))
+                    .with_stroke(Some(1u8.into()))
+                    .with_fill(Some(1u8.into()))
+                    .into_iter(),
+            );
+        }
+        self.disp.update();
+    }
+}
+
+impl Sink for FlashSink {
+    type Error = flash::Error;
+
+    fn open(&mut self, name: &str, size: Option<u32>) -> Result<(), flash::Error> {
+        // Start every file on a new page
+        self.base = (self.end + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        if let Some(size) = size {
+            if self.base + size > REGION_START + REGION_SIZE {
+                return Err(flash::Error::Address);
+            }
+        }
+        self.show(name, "", 0);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), flash::Error> {
+        let address = self.base + offset;
+        let end = address + data.len() as u32;
+        if end > REGION_START + REGION_SIZE {
+            return Err(flash::Error::Address);
+        }
+        while self.erased < end {
+            self.flash.erase_page(self.erased)?;
+            self.erased += PAGE_SIZE;
+        }
+        // Flash is programmed by half-words, pad an odd tail
+        let even = data.len() & !1;
+        self.flash.program(address, &data[..even])?;
+        if even < data.len() {
+            self.flash
+                .program(address + even as u32, &[data[even], 0xff])?;
+        }
+        self.end = end;
+        Ok(())
+    }
+
+    fn progress(&mut self, received: u32, size: Option<u32>) {
+        let mut s: String<U32> = String::new();
+        let fill = match size {
+            Some(size) => {
+                write!(s, "{}/{}", received, size).unwrap();
+                received * 127 / size.max(1)
+            }
+            None => {
+                write!(s, "{}", received).unwrap();
+                received * 127 / REGION_SIZE
+            }
+        };
+        self.show("receiving", s.as_str(), fill);
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let dp = pac::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .sysclk(72.mhz())
+        .pclk1(36.mhz())
+        .freeze(&mut flash.acr);
+
+    let time = SysTickMillis::start(cp.SYST, clocks);
+
+    let mut afio = dp.AFIO.constrain(&mut rcc.apb2);
+    let mut gpioa = dp.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = dp.GPIOB.split(&mut rcc.apb2);
+
+    let disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+
+    let tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+    let rx = gpioa.pa10;
+    let serial = Serial::usart1(
+        dp.USART1,
+        (tx, rx),
+        &mut afio.mapr,
+        115_200.bps(),
+        clocks,
+        &mut rcc.apb2,
+    );
+    let (tx, rx) = serial.split();
+
+    let mut port = SerialPort {
+        tx,
+        rx,
+        time: &time,
+    };
+    let mut sink = FlashSink {
+        flash: unsafe { InternalFlash::new() },
+        disp,
+        base: REGION_START,
+        end: REGION_START,
+        erased: REGION_START,
+    };
+    sink.show("waiting...", "", 0);
+
+    let mut s: String<U32> = String::new();
+    match xmodem::receive(&mut port, &mut sink, MODE) {
+        Ok(n) => write!(s, "{} bytes", n).unwrap(),
+        Err(e) => write!(s, "{:?}", e).unwrap(),
+    }
+    let fill = (sink.end - REGION_START) * 127 / REGION_SIZE;
+    sink.show("done", s.as_str(), fill);
+
+    loop {}
+}
+
+#[exception]
+fn SysTick() {
+    time::tick();
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    panic!("{:#?}", ef);
+}
@@ -0,0 +1,168 @@
+//! ADC1 readings in millivolts, compensated for the supply voltage, and the
+//! die temperature.  See `calibration` for the math.
+//!
+//! The conversions are single and started by software, one channel at a
+//! time.  Every channel is sampled for 239.5 ADC cycles (20 us at 12 MHz):
+//! the temperature sensor needs 17.1 us, and potentiometers of up to ~50k
+//! don't need a buffer.
+//!
+//! The supply is measured by `update_vdd` (at startup, then from time to time
+//! as it drifts), `read_mv` uses the last measure:
+//!
+//! ```ignore
+//! let mut adc = Adc::new(dp.ADC1, clocks, &mut rcc.apb2);
+//! let vdd = adc.update_vdd();
+//! let mv = adc.read_mv(&mut pb0);
+//! let tenths = adc.temperature();
+//! ```
+
+use embedded_hal::adc::Channel;
+use stm32f1xx_hal::pac::{self, ADC1, TIM3};
+use stm32f1xx_hal::rcc::{Clocks, APB1, APB2};
+
+use crate::calibration::Calibration;
+
+pub const TEMPERATURE_CHANNEL: u8 = 16;
+pub const VREFINT_CHANNEL: u8 = 17;
+/// Readings averaged by `update_vdd` and `temperature`.
+pub const AVERAGED: u32 = 16;
+
+pub(crate) const ADON: u32 = 1 << 0;
+const CAL: u32 = 1 << 2;
+const RSTCAL: u32 = 1 << 3;
+/// EXTSEL = 100: TIM3 TRGO, see `trigger_timer`.
+pub(crate) const EXTSEL_TIM3_TRGO: u32 = 0b100 << 17;
+/// EXTSEL = 111: SWSTART.
+const EXTSEL_SWSTART: u32 = 0b111 << 17;
+pub(crate) const EXTTRIG: u32 = 1 << 20;
+const SWSTART: u32 = 1 << 22;
+/// Connects the temperature sensor and Vrefint.
+pub(crate) const TSVREFE: u32 = 1 << 23;
+const EOC: u32 = 1 << 1;
+/// SMPx = 111 for all the channels of a register: 239.5 cycles.
+const SMPR1_LONGEST: u32 = 0x00ff_ffff;
+const SMPR2_LONGEST: u32 = 0x3fff_ffff;
+
+/// Enable and reset ADC1, power it up and run its self-calibration, which
+/// compensates the offset of the capacitors of the converter.  CR2 is left
+/// with only ADON set.
+pub fn power_up(adc: &ADC1, clocks: Clocks, _apb2: &mut APB2) {
+    // The HAL doesn't expose the enable and reset registers
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.apb2enr.modify(|_, w| w.adc1en().set_bit());
+    rcc.apb2rstr.modify(|_, w| w.adc1rst().set_bit());
+    rcc.apb2rstr.modify(|_, w| w.adc1rst().clear_bit());
+
+    // Power up, wait tSTAB (1 us) and calibrate
+    adc.cr2.write(|w| unsafe { w.bits(ADON) });
+    cortex_m::asm::delay(clocks.sysclk().0 / 100_000);
+    adc.cr2.modify(|r, w| unsafe { w.bits(r.bits() | RSTCAL) });
+    while adc.cr2.read().bits() & RSTCAL != 0 {}
+    adc.cr2.modify(|r, w| unsafe { w.bits(r.bits() | CAL) });
+    while adc.cr2.read().bits() & CAL != 0 {}
+}
+
+/// Enable and reset TIM3 and make its update event its TRGO, which starts
+/// the conversions with `EXTSEL_TIM3_TRGO`.  Returns the clock of the timer.
+pub fn trigger_timer(tim: &TIM3, clocks: Clocks, _apb1: &mut APB1) -> u32 {
+    // The HAL doesn't expose the enable and reset registers
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    rcc.apb1enr.modify(|_, w| w.tim3en().set_bit());
+    rcc.apb1rstr.modify(|_, w| w.tim3rst().set_bit());
+    rcc.apb1rstr.modify(|_, w| w.tim3rst().clear_bit());
+
+    // MMS = 010: the update event is TRGO
+    tim.cr2.write(|w| unsafe { w.bits(0b010 << 4) });
+    // Twice the APB1 clock when it is divided
+    clocks.pclk1_tim().0
+}
+
+pub struct Adc {
+    adc: ADC1,
+    calibration: Calibration,
+    vdd_mv: u32,
+}
+
+impl Adc {
+    /// Set up ADC1, with the clock chosen with `rcc.cfgr.adcclk()`.  The
+    /// supply is taken as 3.3V until `update_vdd`.
+    pub fn new(adc: ADC1, clocks: Clocks, apb2: &mut APB2) -> Self {
+        power_up(&adc, clocks, apb2);
+        adc.smpr1.write(|w| unsafe { w.bits(SMPR1_LONGEST) });
+        adc.smpr2.write(|w| unsafe { w.bits(SMPR2_LONGEST) });
+        // One conversion per start
+        adc.sqr1.write(|w| unsafe { w.bits(0) });
+        adc.cr2
+            .write(|w| unsafe { w.bits(ADON | EXTTRIG | EXTSEL_SWSTART | TSVREFE) });
+        // The sensor takes 10 us to start (tSTART)
+        cortex_m::asm::delay(clocks.sysclk().0 / 100_000);
+        Adc {
+            adc,
+            calibration: Calibration::default(),
+            vdd_mv: 3300,
+        }
+    }
+
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    /// Convert a channel, 0 to 17.
+    pub fn read_channel(&mut self, channel: u8) -> u16 {
+        self.adc
+            .sqr3
+            .write(|w| unsafe { w.bits(u32::from(channel)) });
+        self.adc
+            .cr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | SWSTART) });
+        while self.adc.sr.read().bits() & EOC == 0 {}
+        // Reading DR clears EOC
+        self.adc.dr.read().bits() as u16
+    }
+
+    fn read_average(&mut self, channel: u8) -> u16 {
+        let sum: u32 = (0..AVERAGED)
+            .map(|_| u32::from(self.read_channel(channel)))
+            .sum();
+        ((sum + AVERAGED / 2) / AVERAGED) as u16
+    }
+
+    /// Raw reading of a pin.
+    pub fn read<PIN: Channel<ADC1, ID = u8>>(&mut self, _pin: &mut PIN) -> u16 {
+        self.read_channel(PIN::channel())
+    }
+
+    /// Measure the supply voltage with Vrefint.
+    pub fn update_vdd(&mut self) -> u32 {
+        let vrefint = self.read_average(VREFINT_CHANNEL);
+        self.vdd_mv = self.calibration.vdd_mv(vrefint);
+        self.vdd_mv
+    }
+
+    /// The supply voltage as of the last `update_vdd`.
+    pub fn vdd_mv(&self) -> u32 {
+        self.vdd_mv
+    }
+
+    /// Voltage on a pin.
+    pub fn read_mv<PIN: Channel<ADC1, ID = u8>>(&mut self, pin: &mut PIN) -> u32 {
+        let reading = self.read(pin);
+        self.calibration.to_mv(reading, self.vdd_mv)
+    }
+
+    /// Die temperature in tenths of degree.
+    pub fn temperature(&mut self) -> i32 {
+        let reading = self.read_average(TEMPERATURE_CHANNEL);
+        self.calibration.temperature(reading, self.vdd_mv)
+    }
+
+    pub fn release(self) -> ADC1 {
+        self.adc.cr2.write(|w| unsafe { w.bits(0) });
+        self.adc
+    }
+}
@@ -0,0 +1,370 @@
+//! Push button driver with debouncing, clicks, double clicks, long presses
+//! and auto-repeat.
+//!
+//! All the timings are in milliseconds and the driver is given the current
+//! time on every poll (for example from `time::Millis`), so they don't depend
+//! on how fast the main loop runs.  The time is free running and compared
+//! with `wrapping_sub`, so it may wrap around.
+//!
+//! The events of a press that is released quickly are:
+//!
+//! ```text
+//! Pressed, Released, Click                  (Click after `double_click` ms)
+//! Pressed, Released, Pressed, Released, DoubleClick
+//! ```
+//!
+//! And for a press that is held:
+//!
+//! ```text
+//! Pressed, Repeat, LongPress, Repeat, Repeat, ..., Released
+//! ```
+//!
+//! A press that caused a `LongPress` or a `Repeat` isn't a click.
+
+use embedded_hal::digital::v2::InputPin;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The button went down (after debouncing).
+    Pressed,
+    /// The button went up (after debouncing).
+    Released,
+    /// A short press, not followed by a second one.
+    Click,
+    /// Two short presses.
+    DoubleClick,
+    /// The button has been held for `long_press` ms.
+    LongPress,
+    /// The button is still held, every `repeat_rate` ms after `repeat_delay`.
+    Repeat,
+}
+
+/// Timings in milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Timings {
+    /// Time the input has to be stable to be taken into account.
+    pub debounce: u32,
+    /// Maximum time between the release of a click and the second press of a
+    /// double click.  0 disables double clicks, then `Click` comes right
+    /// after `Released`.
+    pub double_click: u32,
+    /// 0 disables long presses.
+    pub long_press: u32,
+    /// Time held before the first `Repeat`.
+    pub repeat_delay: u32,
+    /// Time between repeats. 0 disables auto-repeat.
+    pub repeat_rate: u32,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings {
+            debounce: 20,
+            double_click: 250,
+            long_press: 700,
+            repeat_delay: 500,
+            repeat_rate: 100,
+        }
+    }
+}
+
+/// The state machine of a button, fed with the sampled input level.
+pub struct Detector {
+    timings: Timings,
+    /// Last sampled level and since when it has been stable
+    raw: bool,
+    raw_since: u32,
+    /// Debounced level
+    pressed: bool,
+    pressed_at: u32,
+    released_at: u32,
+    /// Time of the next `Repeat`
+    next_repeat: u32,
+    long_press_sent: bool,
+    /// The current press sent `LongPress` or `Repeat`
+    held: bool,
+    /// A click waiting to know if it's a double click
+    click_pending: bool,
+    /// The second press of a double click is in progress
+    second_press: bool,
+    /// Event to return on the next update
+    queued: Option<Event>,
+}
+
+impl Detector {
+    pub fn new(timings: Timings) -> Self {
+        Detector {
+            timings,
+            raw: false,
+            raw_since: 0,
+            pressed: false,
+            pressed_at: 0,
+            released_at: 0,
+            next_repeat: 0,
+            long_press_sent: false,
+            held: false,
+            click_pending: false,
+            second_press: false,
+            queued: None,
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Feed the input level at time `now`. At most one event is returned per
+    /// call, so call it at least once per millisecond, or until it returns
+    /// `None`.
+    pub fn update(&mut self, level: bool, now: u32) -> Option<Event> {
+        if let Some(event) = self.queued.take() {
+            return Some(event);
+        }
+        if level != self.raw {
+            self.raw = level;
+            self.raw_since = now;
+        }
+        let stable = now.wrapping_sub(self.raw_since) >= self.timings.debounce;
+        if stable && self.raw != self.pressed {
+            self.pressed = self.raw;
+            return if self.pressed {
+                Some(self.press(now))
+            } else {
+                self.release(now);
+                Some(Event::Released)
+            };
+        }
+
+        let t = &self.timings;
+        if self.pressed {
+            let held_for = now.wrapping_sub(self.pressed_at);
+            if t.long_press > 0 && !self.long_press_sent && held_for >= t.long_press {
+                self.long_press_sent = true;
+                self.held = true;
+                return Some(Event::LongPress);
+            }
+            if t.repeat_rate > 0 && (now.wrapping_sub(self.next_repeat) as i32) >= 0 {
+                self.next_repeat = now.wrapping_add(t.repeat_rate);
+                self.held = true;
+                return Some(Event::Repeat);
+            }
+        } else if self.click_pending && now.wrapping_sub(self.released_at) >= t.double_click {
+            self.click_pending = false;
+            return Some(Event::Click);
+        }
+        None
+    }
+
+    fn press(&mut self, now: u32) -> Event {
+        self.pressed_at = now;
+        self.next_repeat = now.wrapping_add(self.timings.repeat_delay);
+        self.long_press_sent = false;
+        self.held = false;
+        self.second_press = self.click_pending;
+        self.click_pending = false;
+        Event::Pressed
+    }
+
+    fn release(&mut self, now: u32) {
+        self.released_at = now;
+        if self.held {
+            self.second_press = false;
+        } else if self.second_press {
+            self.second_press = false;
+            self.queued = Some(Event::DoubleClick);
+        } else if self.timings.double_click == 0 {
+            self.queued = Some(Event::Click);
+        } else {
+            self.click_pending = true;
+        }
+    }
+}
+
+/// A button on an input pin.
+pub struct Button<PIN> {
+    pin: PIN,
+    active_low: bool,
+    detector: Detector,
+}
+
+impl<PIN: InputPin> Button<PIN> {
+    /// A button that reads high when pressed, with the default timings.
+    pub fn new(pin: PIN) -> Self {
+        Button {
+            pin,
+            active_low: false,
+            detector: Detector::new(Timings::default()),
+        }
+    }
+
+    /// For buttons to ground with a pull-up.
+    pub fn active_low(mut self) -> Self {
+        self.active_low = true;
+        self
+    }
+
+    pub fn with_timings(mut self, timings: Timings) -> Self {
+        self.detector = Detector::new(timings);
+        self
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.detector.is_pressed()
+    }
+
+    /// Sample the pin. See `Detector::update`.
+    pub fn poll(&mut self, now: u32) -> Option<Event> {
+        // A pin that can't be read is taken as released
+        let level = self.pin.is_high().unwrap_or(self.active_low);
+        self.detector.update(level != self.active_low, now)
+    }
+
+    pub fn release(self) -> PIN {
+        self.pin
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Feeds a detector with levels, one millisecond at a time.
+    struct Clock {
+        now: u32,
+        detector: Detector,
+        events: std::vec::Vec<(u32, Event)>,
+    }
+
+    impl Clock {
+        fn new(timings: Timings, now: u32) -> Self {
+            Clock {
+                now,
+                detector: Detector::new(timings),
+                events: std::vec::Vec::new(),
+            }
+        }
+
+        /// Hold `level` for `ms` milliseconds.
+        fn hold(&mut self, level: bool, ms: u32) -> &mut Self {
+            for _ in 0..ms {
+                while let Some(event) = self.detector.update(level, self.now) {
+                    self.events.push((self.now, event));
+                }
+                self.now = self.now.wrapping_add(1);
+            }
+            self
+        }
+
+        fn events(&self) -> std::vec::Vec<Event> {
+            self.events.iter().map(|&(_, e)| e).collect()
+        }
+    }
+
+    fn timings() -> Timings {
+        Timings {
+            debounce: 10,
+            double_click: 200,
+            long_press: 1000,
+            repeat_delay: 500,
+            repeat_rate: 100,
+        }
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut clock = Clock::new(timings(), 0);
+        for _ in 0..5 {
+            clock.hold(true, 2).hold(false, 3);
+        }
+        clock.hold(false, 500);
+        assert_eq!(clock.events(), vec![]);
+    }
+
+    #[test]
+    fn click() {
+        use Event::*;
+        let mut clock = Clock::new(timings(), 0);
+        clock.hold(true, 3).hold(false, 2).hold(true, 100).hold(false, 300);
+        assert_eq!(clock.events(), vec![Pressed, Released, Click]);
+        // Pressed after the debounce time, the click when the double click
+        // window is over
+        let times: std::vec::Vec<u32> = clock.events.iter().map(|&(t, _)| t).collect();
+        assert_eq!(times, vec![15, 115, 315]);
+    }
+
+    #[test]
+    fn double_click() {
+        use Event::*;
+        let mut clock = Clock::new(timings(), 0);
+        clock
+            .hold(true, 50)
+            .hold(false, 100)
+            .hold(true, 50)
+            .hold(false, 300);
+        assert_eq!(
+            clock.events(),
+            vec![Pressed, Released, Pressed, Released, DoubleClick]
+        );
+    }
+
+    #[test]
+    fn long_press_and_repeat() {
+        use Event::*;
+        let mut clock = Clock::new(timings(), 0);
+        clock.hold(true, 1250).hold(false, 300);
+        // Repeats at 500, 600, ... 1200 ms after the press
+        let mut expected = vec![Pressed, Repeat, Repeat, Repeat, Repeat, Repeat, LongPress];
+        expected.extend(&[Repeat, Repeat, Repeat, Released]);
+        assert_eq!(clock.events(), expected);
+    }
+
+    #[test]
+    fn disabled_gestures() {
+        use Event::*;
+        let timings = Timings {
+            double_click: 0,
+            long_press: 0,
+            repeat_rate: 0,
+            ..timings()
+        };
+        let mut clock = Clock::new(timings, 0);
+        clock.hold(true, 2000).hold(false, 20);
+        assert_eq!(clock.events(), vec![Pressed, Released, Click]);
+    }
+
+    #[test]
+    fn time_wraps_around() {
+        use Event::*;
+        let mut clock = Clock::new(timings(), u32::MAX - 600);
+        clock.hold(true, 1250).hold(false, 300);
+        assert_eq!(clock.events().len(), 11);
+        clock.hold(true, 50).hold(false, 300);
+        assert_eq!(clock.events()[11..], [Pressed, Released, Click]);
+    }
+
+    struct MockPin<'a>(&'a Cell<bool>);
+
+    impl<'a> InputPin for MockPin<'a> {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            Ok(self.0.get())
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            Ok(!self.0.get())
+        }
+    }
+
+    #[test]
+    fn active_low_pin() {
+        let level = Cell::new(true);
+        let mut button = Button::new(MockPin(&level)).active_low();
+        assert_eq!(button.poll(0), None);
+        level.set(false);
+        assert_eq!(button.poll(100), None);
+        assert_eq!(button.poll(200), Some(Event::Pressed));
+        assert!(button.is_pressed());
+    }
+}
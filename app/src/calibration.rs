@@ -0,0 +1,178 @@
+//! Conversion of ADC readings to millivolts and degrees, for `adc`.
+//!
+//! The ADC measures against its supply (VDDA, the 3.3V rail on the Blue
+//! Pill), which sags with the load and varies from a regulator to another.
+//! The internal reference (Vrefint, channel 17) is a fixed 1.20V, so its
+//! reading tells the supply:
+//!
+//! ```text
+//! VDDA = 1200 mV * 4095 / Vrefint reading
+//! mV   = reading * VDDA / 4095
+//! ```
+//!
+//! The internal temperature sensor (channel 16) gives 1.43V at 25 C and
+//! 4.3 mV less per degree.  The STM32F103 has no factory calibration values,
+//! those are typical values from the datasheet: Vrefint may be 1.16 to 1.24V
+//! and the sensor is off by up to 45 C.  `Calibration::trim_vdd` and
+//! `Calibration::trim_temperature` correct them from a known voltage and
+//! temperature.
+//!
+//! Everything is integer: millivolts, microvolts for the sensor, tenths of
+//! degree for temperatures.
+
+/// Largest 12-bit reading.
+pub const FULL_SCALE: u32 = 4095;
+/// Typical Vrefint.
+pub const VREFINT_MV: u32 = 1200;
+/// Typical sensor voltage at 25 C.
+pub const V25_UV: i32 = 1_430_000;
+/// Typical sensor slope, the voltage drops with the temperature.
+pub const AVG_SLOPE_UV: i32 = 4300;
+
+/// Integer division rounded to the nearest.
+fn div_round(n: u32, d: u32) -> u32 {
+    (n + d / 2) / d
+}
+
+fn div_round_signed(n: i64, d: i64) -> i64 {
+    if (n < 0) == (d < 0) {
+        (n + d / 2) / d
+    } else {
+        (n - d / 2) / d
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub vrefint_mv: u32,
+    pub v25_uv: i32,
+    /// Microvolts per degree
+    pub slope_uv: i32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            vrefint_mv: VREFINT_MV,
+            v25_uv: V25_UV,
+            slope_uv: AVG_SLOPE_UV,
+        }
+    }
+}
+
+impl Calibration {
+    /// Supply voltage from a Vrefint reading, 0 for a 0 reading.
+    pub fn vdd_mv(&self, vrefint: u16) -> u32 {
+        if vrefint == 0 {
+            return 0;
+        }
+        div_round(self.vrefint_mv * FULL_SCALE, u32::from(vrefint))
+    }
+
+    /// Voltage of a reading taken with a supply of `vdd_mv`.
+    pub fn to_mv(&self, reading: u16, vdd_mv: u32) -> u32 {
+        // A bad Vrefint reading of a few steps makes a supply of volts
+        // by the thousand, too much for the product in 32 bits
+        let product = u64::from(reading) * u64::from(vdd_mv);
+        ((product + u64::from(FULL_SCALE) / 2) / u64::from(FULL_SCALE)) as u32
+    }
+
+    /// Die temperature in tenths of degree from a sensor reading.
+    pub fn temperature(&self, reading: u16, vdd_mv: u32) -> i32 {
+        let uv = u64::from(reading) * u64::from(vdd_mv) * 1000;
+        let sense_uv = ((uv + u64::from(FULL_SCALE) / 2) / u64::from(FULL_SCALE)) as i64;
+        let tenths = (i64::from(self.v25_uv) - sense_uv) * 10;
+        250 + div_round_signed(tenths, i64::from(self.slope_uv)) as i32
+    }
+
+    /// Correct Vrefint from the supply `vdd_mv` measured with a multimeter
+    /// while Vrefint read `vrefint`.
+    pub fn trim_vdd(&mut self, vdd_mv: u32, vrefint: u16) {
+        self.vrefint_mv = div_round(vdd_mv * u32::from(vrefint), FULL_SCALE);
+    }
+
+    /// Correct the sensor offset from a known die temperature (in tenths of
+    /// degree), like the room temperature after a while powered off.
+    pub fn trim_temperature(&mut self, temperature: i32, reading: u16, vdd_mv: u32) {
+        let error = self.temperature(reading, vdd_mv) - temperature;
+        self.v25_uv -= div_round_signed(i64::from(error) * i64::from(self.slope_uv), 10) as i32;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn supply_voltage() {
+        let cal = Calibration::default();
+        // 1.2V read with a 3.3V supply
+        assert_eq!(cal.vdd_mv(1489), 3300);
+        // A sagging supply makes Vrefint read higher
+        assert_eq!(cal.vdd_mv(1638), 3000);
+        assert_eq!(cal.vdd_mv(4095), 1200);
+        assert_eq!(cal.vdd_mv(0), 0);
+    }
+
+    #[test]
+    fn millivolts() {
+        let cal = Calibration::default();
+        assert_eq!(cal.to_mv(0, 3300), 0);
+        assert_eq!(cal.to_mv(4095, 3300), 3300);
+        assert_eq!(cal.to_mv(2048, 3300), 1650);
+        // The same voltage reads higher with a lower supply
+        assert_eq!(cal.to_mv(2253, 3000), 1651);
+    }
+
+    #[test]
+    fn bad_vrefint() {
+        // A Vrefint reading of 1 (shorted, or a wrong channel) makes a supply
+        // of almost 5 kV, which the conversions take without overflowing
+        let cal = Calibration::default();
+        let vdd = cal.vdd_mv(1);
+        assert_eq!(vdd, 4_914_000);
+        assert_eq!(cal.to_mv(4095, vdd), vdd);
+        assert_eq!(cal.to_mv(2048, vdd), 2_457_600);
+        assert!(cal.temperature(1737, vdd) < -4_000_000);
+    }
+
+    #[test]
+    fn compensated_reading() {
+        let cal = Calibration::default();
+        // 1.0V on a pin: the readings change with the supply, the result not
+        for &vdd in &[3000, 3300, 3600] {
+            let vrefint = (1200 * 4095 + vdd / 2) / vdd;
+            let reading = (1000 * 4095 + vdd / 2) / vdd;
+            let mv = cal.to_mv(reading as u16, cal.vdd_mv(vrefint as u16));
+            assert!((mv as i32 - 1000).abs() <= 2, "{} mV at {} mV", mv, vdd);
+        }
+    }
+
+    #[test]
+    fn temperature() {
+        let cal = Calibration::default();
+        let reading = |uv: u32| ((uv as u64 * 4095 + 1_650_000) / 3_300_000) as u16;
+        // A step of the reading is 0.8 mV, about 0.2 degrees
+        let t = cal.temperature(reading(1_430_000), 3300);
+        assert!((t - 250).abs() <= 2, "{}", t);
+        // 43 mV lower is 10 degrees warmer
+        let t = cal.temperature(reading(1_387_000), 3300);
+        assert!((t - 350).abs() <= 2, "{}", t);
+        let t = cal.temperature(reading(1_516_000), 3300);
+        assert!((t - 50).abs() <= 2, "{}", t);
+    }
+
+    #[test]
+    fn trimming() {
+        let mut cal = Calibration::default();
+        // This part's Vrefint is 1.21V: 1502 at a measured 3.31V
+        cal.trim_vdd(3310, 1502);
+        assert_eq!(cal.vrefint_mv, 1214);
+        assert_eq!(cal.vdd_mv(1502), 3310);
+
+        // The sensor reads 1.40V at 21 C
+        let reading = 1737;
+        cal.trim_temperature(210, reading, 3300);
+        assert_eq!(cal.temperature(reading, 3300), 210);
+    }
+}
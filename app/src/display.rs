@@ -0,0 +1,208 @@
+//! The display of the examples, chosen with cargo features.
+//!
+//! Without features it is an SSD1306 128x64 module on I2C1, like in
+//! `display.rs`.  The features change the bus and the panel:
+//!
+//! ```text
+//! display-spi1    SPI1: SCK PA5, MOSI (SDA, D1) PA7, CS PA4, DC PA3, RST PA2
+//! display-spi2    SPI2: SCK PB13, MOSI (SDA, D1) PB15, CS PB12, DC PB10,
+//!                 RST PB11
+//! display-sh1106  SH1106 controller, as in most 1.3" modules
+//! display-128x32  SSD1306 128x32 panel
+//! ```
+//!
+//! ```text
+//! cargo run --example display2 --features display-spi2,display-sh1106
+//! ```
+//!
+//! The examples build the display with `app::display!`, so that their code
+//! doesn't change with the display:
+//!
+//! ```ignore
+//! let mut disp = app::display!(dp, gpioa, gpiob, afio, rcc, clocks);
+//! disp.update();
+//! ```
+//!
+//! It takes the pins it needs from the GPIO parts (MISO, PA6 or PB14, is
+//! taken as well although the display doesn't use it).  SPI1 shares PA4-PA7
+//! with `keypad.rs` and `encoder.rs`, use SPI2 with those.
+
+use stm32f1xx_hal::rcc::Clocks;
+
+use crate::oled::{Oled, Panel};
+
+#[doc(hidden)]
+pub use embedded_hal as __embedded_hal;
+#[doc(hidden)]
+pub use stm32f1xx_hal as __hal;
+
+#[cfg(all(feature = "display-spi1", feature = "display-spi2"))]
+compile_error!("the display-spi1 and display-spi2 features can't be used together");
+#[cfg(all(feature = "display-sh1106", feature = "display-128x32"))]
+compile_error!("there are no SH1106 128x32 modules");
+
+#[cfg(not(any(feature = "display-sh1106", feature = "display-128x32")))]
+pub const PANEL: Panel = Panel::SSD1306_128X64;
+#[cfg(feature = "display-sh1106")]
+pub const PANEL: Panel = Panel::SH1106_128X64;
+#[cfg(feature = "display-128x32")]
+pub const PANEL: Panel = Panel::SSD1306_128X32;
+
+/// SPI clock, both controllers take up to 10 MHz.
+pub const SPI_MHZ: u32 = 8;
+
+/// I2C clock, the SSD1306 takes up to 400 kHz.
+pub const I2C_FREQUENCY: u32 = 400_000;
+/// How long a START, the address or a byte may take: twice the 9 clocks of
+/// a byte with its ACK.  The display is then recovered by `Oled::update`.
+pub const I2C_TIMEOUT_US: u32 = 2 * 9 * 1_000_000 / I2C_FREQUENCY + 1;
+/// Attempts at a START before giving up, a START waits for the bus to be free.
+pub const I2C_START_RETRIES: u8 = 3;
+
+#[cfg(not(any(feature = "display-spi1", feature = "display-spi2")))]
+pub type Display = Oled<
+    crate::oled::I2cInterface<
+        __hal::i2c::BlockingI2c<
+            __hal::pac::I2C1,
+            (
+                __hal::gpio::gpiob::PB8<__hal::gpio::Alternate<__hal::gpio::OpenDrain>>,
+                __hal::gpio::gpiob::PB9<__hal::gpio::Alternate<__hal::gpio::OpenDrain>>,
+            ),
+        >,
+    >,
+>;
+
+#[cfg(feature = "display-spi1")]
+pub type Display = Oled<
+    crate::oled::SpiInterface<
+        __hal::spi::Spi<
+            __hal::pac::SPI1,
+            (
+                __hal::gpio::gpioa::PA5<__hal::gpio::Alternate<__hal::gpio::PushPull>>,
+                __hal::gpio::gpioa::PA6<__hal::gpio::Input<__hal::gpio::Floating>>,
+                __hal::gpio::gpioa::PA7<__hal::gpio::Alternate<__hal::gpio::PushPull>>,
+            ),
+        >,
+        __hal::gpio::gpioa::PA4<__hal::gpio::Output<__hal::gpio::PushPull>>,
+        __hal::gpio::gpioa::PA3<__hal::gpio::Output<__hal::gpio::PushPull>>,
+    >,
+>;
+
+#[cfg(feature = "display-spi2")]
+pub type Display = Oled<
+    crate::oled::SpiInterface<
+        __hal::spi::Spi<
+            __hal::pac::SPI2,
+            (
+                __hal::gpio::gpiob::PB13<__hal::gpio::Alternate<__hal::gpio::PushPull>>,
+                __hal::gpio::gpiob::PB14<__hal::gpio::Input<__hal::gpio::Floating>>,
+                __hal::gpio::gpiob::PB15<__hal::gpio::Alternate<__hal::gpio::PushPull>>,
+            ),
+        >,
+        __hal::gpio::gpiob::PB12<__hal::gpio::Output<__hal::gpio::PushPull>>,
+        __hal::gpio::gpiob::PB10<__hal::gpio::Output<__hal::gpio::PushPull>>,
+    >,
+>;
+
+/// Pulse the reset pin of an SPI module: 1 ms low, then 1 ms to wake up.
+pub fn reset<RST: __embedded_hal::digital::v2::OutputPin>(rst: &mut RST, clocks: Clocks) {
+    let ms = clocks.sysclk().0 / 1000;
+    let _ = rst.set_low();
+    cortex_m::asm::delay(ms);
+    let _ = rst.set_high();
+    cortex_m::asm::delay(ms);
+}
+
+/// Build the `Display` from the device peripherals `dp`, the split GPIOA and
+/// GPIOB, AFIO and RCC, and the frozen clocks.  All of them are variables of
+/// the caller, the display pins and bus are moved out of them.
+#[cfg(not(any(feature = "display-spi1", feature = "display-spi2")))]
+#[macro_export]
+macro_rules! display {
+    ($dp:ident, $gpioa:ident, $gpiob:ident, $afio:ident, $rcc:ident, $clocks:ident) => {{
+        use $crate::display::__hal::i2c::{BlockingI2c, DutyCycle, Mode};
+
+        // Only the SPI displays use GPIOA
+        let _ = &mut $gpioa.crl;
+        $crate::i2c::enable_timeouts();
+        let scl = $gpiob.pb8.into_alternate_open_drain(&mut $gpiob.crh);
+        let sda = $gpiob.pb9.into_alternate_open_drain(&mut $gpiob.crh);
+        let i2c = BlockingI2c::i2c1(
+            $dp.I2C1,
+            (scl, sda),
+            &mut $afio.mapr,
+            Mode::Fast {
+                frequency: $crate::display::I2C_FREQUENCY,
+                duty_cycle: DutyCycle::Ratio2to1,
+            },
+            $clocks,
+            &mut $rcc.apb1,
+            $crate::display::I2C_TIMEOUT_US,
+            $crate::display::I2C_START_RETRIES,
+            $crate::display::I2C_TIMEOUT_US,
+            $crate::display::I2C_TIMEOUT_US,
+        );
+        let interface = $crate::oled::I2cInterface::new(i2c, $crate::oled::DEFAULT_ADDRESS)
+            .with_recovery($crate::i2c::recover_i2c1);
+        $crate::oled::Oled::new(interface, $crate::display::PANEL)
+    }};
+}
+
+#[cfg(feature = "display-spi1")]
+#[macro_export]
+macro_rules! display {
+    ($dp:ident, $gpioa:ident, $gpiob:ident, $afio:ident, $rcc:ident, $clocks:ident) => {{
+        use $crate::display::__embedded_hal::spi::MODE_0;
+        use $crate::display::__hal::spi::Spi;
+        use $crate::display::__hal::time::MegaHertz;
+
+        let _ = &mut $gpiob.crh;
+        let sck = $gpioa.pa5.into_alternate_push_pull(&mut $gpioa.crl);
+        let miso = $gpioa.pa6.into_floating_input(&mut $gpioa.crl);
+        let mosi = $gpioa.pa7.into_alternate_push_pull(&mut $gpioa.crl);
+        let cs = $gpioa.pa4.into_push_pull_output(&mut $gpioa.crl);
+        let dc = $gpioa.pa3.into_push_pull_output(&mut $gpioa.crl);
+        let mut rst = $gpioa.pa2.into_push_pull_output(&mut $gpioa.crl);
+        let spi = Spi::spi1(
+            $dp.SPI1,
+            (sck, miso, mosi),
+            &mut $afio.mapr,
+            MODE_0,
+            MegaHertz($crate::display::SPI_MHZ),
+            $clocks,
+            &mut $rcc.apb2,
+        );
+        $crate::display::reset(&mut rst, $clocks);
+        let interface = $crate::oled::SpiInterface::new(spi, cs, dc);
+        $crate::oled::Oled::new(interface, $crate::display::PANEL)
+    }};
+}
+
+#[cfg(feature = "display-spi2")]
+#[macro_export]
+macro_rules! display {
+    ($dp:ident, $gpioa:ident, $gpiob:ident, $afio:ident, $rcc:ident, $clocks:ident) => {{
+        use $crate::display::__embedded_hal::spi::MODE_0;
+        use $crate::display::__hal::spi::Spi;
+        use $crate::display::__hal::time::MegaHertz;
+
+        let _ = (&mut $gpioa.crl, &mut $afio.mapr);
+        let sck = $gpiob.pb13.into_alternate_push_pull(&mut $gpiob.crh);
+        let miso = $gpiob.pb14.into_floating_input(&mut $gpiob.crh);
+        let mosi = $gpiob.pb15.into_alternate_push_pull(&mut $gpiob.crh);
+        let cs = $gpiob.pb12.into_push_pull_output(&mut $gpiob.crh);
+        let dc = $gpiob.pb10.into_push_pull_output(&mut $gpiob.crh);
+        let mut rst = $gpiob.pb11.into_push_pull_output(&mut $gpiob.crh);
+        let spi = Spi::spi2(
+            $dp.SPI2,
+            (sck, miso, mosi),
+            MODE_0,
+            MegaHertz($crate::display::SPI_MHZ),
+            $clocks,
+            &mut $rcc.apb1,
+        );
+        $crate::display::reset(&mut rst, $clocks);
+        let interface = $crate::oled::SpiInterface::new(spi, cs, dc);
+        $crate::oled::Oled::new(interface, $crate::display::PANEL)
+    }};
+}
@@ -0,0 +1,465 @@
+//! EEPROM emulation: variables that survive a reset, stored in two pages of
+//! flash (the idea of ST's application note AN2594).
+//!
+//! Flash can only be erased by whole pages, and not that many times (10000
+//! cycles), so values are not rewritten in place: every write appends a
+//! record with the variable id and its new value to the active page, and a
+//! read looks for the last record of the id.  When the active page is full,
+//! the last value of every variable is copied to the other page, which
+//! becomes the active one, and the old page is erased.
+//!
+//! Page layout:
+//!
+//! ```text
+//! 0: generation (u32), CRC of the generation (u16), VALID marker (u16)
+//! 8: records of 8 bytes: id (u16), value (u32), CRC of id and value (u16)
+//! ```
+//!
+//! The generation counts the page transfers, so the pages are erased about
+//! `generation / 2` times each.  The copy to a new page only counts once
+//! the VALID marker is written, after all the records.  When power is lost:
+//!
+//! - While writing a record: its CRC doesn't match and it's ignored, the
+//!   variable keeps its previous value.
+//! - During a transfer, before the marker: the new page isn't valid, the old
+//!   one is still used and the incomplete one is erased.
+//! - After the marker, before the old page is erased: both pages are valid,
+//!   the one with the newest generation is used and the other one erased.
+
+use crate::flash::{self, Flash, PAGE_SIZE};
+use crate::xmodem::crc16;
+
+/// The last two pages of the upper 64 KiB of flash (see
+/// `flash::UPPER_FLASH`).
+pub const DEFAULT_PAGES: [u32; 2] = [
+    flash::UPPER_FLASH + 62 * PAGE_SIZE,
+    flash::UPPER_FLASH + 63 * PAGE_SIZE,
+];
+
+const VALID: u16 = 0x5aa5;
+const HEADER: u32 = 8;
+const RECORD: u32 = 8;
+const SLOTS: u32 = (PAGE_SIZE - HEADER) / RECORD;
+
+/// Id of the records that were never written.
+const NO_ID: u16 = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Flash(flash::Error),
+    /// The id 0xFFFF can't be used.
+    Id,
+    /// There are more variables than fit in a page.
+    Full,
+}
+
+impl From<flash::Error> for Error {
+    fn from(e: flash::Error) -> Self {
+        Error::Flash(e)
+    }
+}
+
+pub struct Eeprom<F> {
+    flash: F,
+    pages: [u32; 2],
+    /// Index of the page in use
+    active: usize,
+    /// Offset of the first free record in the active page
+    next: u32,
+    generation: u32,
+}
+
+impl<F: Flash> Eeprom<F> {
+    /// Use the pages at the addresses `pages`. Call `init` before anything
+    /// else.
+    pub fn new(flash: F, pages: [u32; 2]) -> Self {
+        Eeprom {
+            flash,
+            pages,
+            active: 0,
+            next: HEADER,
+            generation: 0,
+        }
+    }
+
+    /// Find the active page, finishing or undoing what was interrupted by a
+    /// reset.  Pages that don't hold valid data are erased.
+    pub fn init(&mut self) -> Result<(), Error> {
+        match (self.header(0), self.header(1)) {
+            (Some(a), Some(b)) => {
+                // Newest generation, with wrapping around
+                let newest = if (b.wrapping_sub(a) as i32) > 0 { 1 } else { 0 };
+                self.active = newest;
+                self.flash.erase_page(self.pages[1 - newest])?;
+            }
+            (Some(_), None) => {
+                self.active = 0;
+                self.clean(1)?;
+            }
+            (None, Some(_)) => {
+                self.active = 1;
+                self.clean(0)?;
+            }
+            (None, None) => self.format()?,
+        }
+        self.generation = self.header(self.active).unwrap_or(0);
+        self.next = self.first_free(self.active);
+        Ok(())
+    }
+
+    /// Erase everything.
+    pub fn format(&mut self) -> Result<(), Error> {
+        self.flash.erase_page(self.pages[0])?;
+        self.flash.erase_page(self.pages[1])?;
+        self.write_header(0, 0)?;
+        self.flash.program(self.pages[0] + 6, &VALID.to_le_bytes())?;
+        self.active = 0;
+        self.next = HEADER;
+        self.generation = 0;
+        Ok(())
+    }
+
+    /// Last value written for `id`.
+    pub fn read(&self, id: u16) -> Option<u32> {
+        let page = self.pages[self.active];
+        (HEADER..self.next)
+            .step_by(RECORD as usize)
+            .rev()
+            .filter_map(|offset| self.record(page + offset))
+            .find(|&(rid, _)| rid == id)
+            .map(|(_, value)| value)
+    }
+
+    pub fn write(&mut self, id: u16, value: u32) -> Result<(), Error> {
+        if id == NO_ID {
+            return Err(Error::Id);
+        }
+        if self.read(id) == Some(value) {
+            return Ok(());
+        }
+        if self.next + RECORD > PAGE_SIZE {
+            return self.transfer(id, value);
+        }
+        let address = self.pages[self.active] + self.next;
+        // Even if programming fails the slot can't be used anymore
+        self.next += RECORD;
+        self.flash.program(address, &encode(id, value))?;
+        Ok(())
+    }
+
+    /// Number of page transfers since the pages were formatted.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Copy the last value of every variable, and the new one, to the other
+    /// page and make it the active one.
+    fn transfer(&mut self, id: u16, value: u32) -> Result<(), Error> {
+        let old = self.active;
+        let new = 1 - old;
+        let (old_page, new_page) = (self.pages[old], self.pages[new]);
+        let generation = self.generation.wrapping_add(1);
+
+        if !self.is_erased(new) {
+            self.flash.erase_page(new_page)?;
+        }
+        self.write_header(new, generation)?;
+        self.flash.program(new_page + HEADER, &encode(id, value))?;
+        let mut next = HEADER + RECORD;
+        for offset in (HEADER..PAGE_SIZE).step_by(RECORD as usize).rev() {
+            let (rid, rvalue) = match self.record(old_page + offset) {
+                Some(record) => record,
+                None => continue,
+            };
+            let copied = (HEADER..next)
+                .step_by(RECORD as usize)
+                .any(|o| self.record(new_page + o).map(|(i, _)| i) == Some(rid));
+            if copied {
+                continue;
+            }
+            if next + RECORD > PAGE_SIZE {
+                return Err(Error::Full);
+            }
+            self.flash.program(new_page + next, &encode(rid, rvalue))?;
+            next += RECORD;
+        }
+        self.flash.program(new_page + 6, &VALID.to_le_bytes())?;
+        self.active = new;
+        self.next = next;
+        self.generation = generation;
+        self.flash.erase_page(old_page)?;
+        Ok(())
+    }
+
+    /// Generation of a valid page.
+    fn header(&self, index: usize) -> Option<u32> {
+        let mut header = [0; HEADER as usize];
+        self.flash.read(self.pages[index], &mut header);
+        let generation = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let crc = u16::from_le_bytes([header[4], header[5]]);
+        let marker = u16::from_le_bytes([header[6], header[7]]);
+        if marker == VALID && crc == crc16(&header[..4]) {
+            Some(generation)
+        } else {
+            None
+        }
+    }
+
+    fn write_header(&mut self, index: usize, generation: u32) -> Result<(), Error> {
+        let mut header = [0; 6];
+        header[..4].copy_from_slice(&generation.to_le_bytes());
+        let crc = crc16(&header[..4]);
+        header[4..].copy_from_slice(&crc.to_le_bytes());
+        self.flash.program(self.pages[index], &header)?;
+        Ok(())
+    }
+
+    /// Erase a page that isn't used, unless it already is.
+    fn clean(&mut self, index: usize) -> Result<(), Error> {
+        if !self.is_erased(index) {
+            self.flash.erase_page(self.pages[index])?;
+        }
+        Ok(())
+    }
+
+    fn is_erased(&self, index: usize) -> bool {
+        let mut buf = [0; 64];
+        (0..PAGE_SIZE).step_by(buf.len()).all(|offset| {
+            self.flash.read(self.pages[index] + offset, &mut buf);
+            buf.iter().all(|&b| b == 0xff)
+        })
+    }
+
+    /// Offset of the record after the last one that was (even partially)
+    /// written.
+    fn first_free(&self, index: usize) -> u32 {
+        let page = self.pages[index];
+        let mut buf = [0; RECORD as usize];
+        (0..SLOTS)
+            .rev()
+            .map(|slot| HEADER + slot * RECORD)
+            .find(|&offset| {
+                self.flash.read(page + offset, &mut buf);
+                buf.iter().any(|&b| b != 0xff)
+            })
+            .map(|offset| offset + RECORD)
+            .unwrap_or(HEADER)
+    }
+
+    /// The id and value of a complete record.
+    fn record(&self, address: u32) -> Option<(u16, u32)> {
+        let mut buf = [0; RECORD as usize];
+        self.flash.read(address, &mut buf);
+        let id = u16::from_le_bytes([buf[0], buf[1]]);
+        let value = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+        let crc = u16::from_le_bytes([buf[6], buf[7]]);
+        if id != NO_ID && crc == crc16(&buf[..6]) {
+            Some((id, value))
+        } else {
+            None
+        }
+    }
+}
+
+fn encode(id: u16, value: u32) -> [u8; RECORD as usize] {
+    let mut buf = [0; RECORD as usize];
+    buf[..2].copy_from_slice(&id.to_le_bytes());
+    buf[2..6].copy_from_slice(&value.to_le_bytes());
+    let crc = crc16(&buf[..6]);
+    buf[6..].copy_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flash::Error as FlashError;
+    use std::collections::HashMap;
+
+    const PAGES: [u32; 2] = [0x0800_0000, 0x0800_0000 + PAGE_SIZE];
+
+    /// Two pages of flash in memory, with the rules of the real one: erased
+    /// bytes are 0xff and half-words can only be programmed once.  After
+    /// `cut` half-word writes or page erases the power goes off: nothing
+    /// changes anymore and every operation fails.
+    struct MemFlash {
+        mem: Vec<u8>,
+        cut: Option<u32>,
+        erases: [u32; 2],
+    }
+
+    impl MemFlash {
+        fn new() -> Self {
+            MemFlash {
+                mem: vec![0xff; 2 * PAGE_SIZE as usize],
+                cut: None,
+                erases: [0; 2],
+            }
+        }
+
+        fn offset(address: u32) -> usize {
+            (address - PAGES[0]) as usize
+        }
+
+        /// Spend one operation, false if the power is off.
+        fn step(&mut self) -> bool {
+            match self.cut {
+                Some(0) => false,
+                Some(n) => {
+                    self.cut = Some(n - 1);
+                    true
+                }
+                None => true,
+            }
+        }
+    }
+
+    impl Flash for MemFlash {
+        fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+            let start = Self::offset(address);
+            if !self.step() {
+                // Interrupted erase: only the beginning of the page is erased
+                for b in &mut self.mem[start..start + PAGE_SIZE as usize / 2] {
+                    *b = 0xff;
+                }
+                return Err(FlashError::Programming);
+            }
+            self.erases[start / PAGE_SIZE as usize] += 1;
+            for b in &mut self.mem[start..start + PAGE_SIZE as usize] {
+                *b = 0xff;
+            }
+            Ok(())
+        }
+
+        fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+            assert!(address.is_multiple_of(2) && data.len().is_multiple_of(2));
+            for (i, half) in data.chunks(2).enumerate() {
+                let at = Self::offset(address) + 2 * i;
+                assert_eq!(&self.mem[at..at + 2], &[0xff, 0xff], "programmed twice");
+                if !self.step() {
+                    return Err(FlashError::Programming);
+                }
+                self.mem[at..at + 2].copy_from_slice(half);
+            }
+            Ok(())
+        }
+
+        fn read(&self, address: u32, buf: &mut [u8]) {
+            let start = Self::offset(address);
+            buf.copy_from_slice(&self.mem[start..start + buf.len()]);
+        }
+    }
+
+    /// Small xorshift generator, for reproducible fuzzing.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    fn eeprom(flash: MemFlash) -> Eeprom<MemFlash> {
+        let mut eeprom = Eeprom::new(flash, PAGES);
+        eeprom.init().unwrap();
+        eeprom
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut eeprom = eeprom(MemFlash::new());
+        assert_eq!(eeprom.read(1), None);
+        eeprom.write(1, 100).unwrap();
+        eeprom.write(2, 200).unwrap();
+        eeprom.write(1, 101).unwrap();
+        assert_eq!(eeprom.read(1), Some(101));
+        assert_eq!(eeprom.read(2), Some(200));
+        assert_eq!(eeprom.write(NO_ID, 0), Err(Error::Id));
+
+        // The same value isn't written again
+        let next = eeprom.next;
+        eeprom.write(2, 200).unwrap();
+        assert_eq!(eeprom.next, next);
+
+        let eeprom = self::eeprom(eeprom.release());
+        assert_eq!(eeprom.read(1), Some(101));
+        assert_eq!(eeprom.read(2), Some(200));
+    }
+
+    #[test]
+    fn pages_wear_evenly() {
+        let mut eeprom = eeprom(MemFlash::new());
+        for i in 0..10_000 {
+            eeprom.write((i % 5) as u16, i).unwrap();
+        }
+        for id in 0..5 {
+            assert_eq!(eeprom.read(id), Some(9995 + u32::from(id)));
+        }
+        // The first transfer after 127 writes, then one every 127 - 5
+        assert_eq!(eeprom.generation(), 1 + (10_000 - 1 - 127) / 122);
+        let erases = eeprom.release().erases;
+        assert!((erases[0] as i32 - erases[1] as i32).abs() <= 1);
+    }
+
+    #[test]
+    fn too_many_variables() {
+        let mut eeprom = eeprom(MemFlash::new());
+        for id in 0..SLOTS {
+            eeprom.write(id as u16, 0).unwrap();
+        }
+        assert_eq!(eeprom.write(SLOTS as u16, 0), Err(Error::Full));
+        // Nothing was lost
+        let eeprom = self::eeprom(eeprom.release());
+        assert_eq!(eeprom.read(0), Some(0));
+        assert_eq!(eeprom.read(SLOTS as u16 - 1), Some(0));
+    }
+
+    #[test]
+    fn power_cuts() {
+        let mut rng = Rng(0x1234_5678);
+        let mut flash = MemFlash::new();
+        let mut expected: HashMap<u16, u32> = HashMap::new();
+        for _ in 0..2000 {
+            // Power up, with the power going off again at a random point
+            flash.cut = Some(rng.next() % 400);
+            let mut eeprom = Eeprom::new(flash, PAGES);
+            if eeprom.init().is_err() {
+                // Cut while recovering
+                flash = eeprom.release();
+                continue;
+            }
+            for (&id, &value) in &expected {
+                assert_eq!(eeprom.read(id), Some(value), "id {}", id);
+            }
+            let (id, value) = loop {
+                let id = (rng.next() % 8) as u16;
+                let value = rng.next();
+                match eeprom.write(id, value) {
+                    Ok(()) => {
+                        expected.insert(id, value);
+                    }
+                    Err(Error::Flash(_)) => break (id, value),
+                    Err(e) => panic!("{:?}", e),
+                }
+            };
+
+            // The interrupted write may or may not have happened
+            flash = eeprom.release();
+            flash.cut = None;
+            let eeprom = self::eeprom(flash);
+            let now = eeprom.read(id);
+            assert!(now == Some(value) || now == expected.get(&id).cloned());
+            if let Some(now) = now {
+                expected.insert(id, now);
+            }
+            flash = eeprom.release();
+        }
+    }
+}
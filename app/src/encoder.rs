@@ -0,0 +1,230 @@
+//! Rotary encoder with an optional push button.
+//!
+//! The quadrature signals are counted by a timer in encoder mode (see
+//! `qei::Qei`), so no step is lost even when the main loop is busy.  The
+//! timer only has a 16-bit counter: `Encoder` extends it to an `i32` position
+//! by reading it often enough (at least once every 32767 counts) and adding
+//! up the differences, which also work across the wrap around.
+//!
+//! Most encoders produce 4 counts (a full quadrature cycle) per detent, so the
+//! position, delta and velocity are given in steps of `counts_per_step`.
+
+use embedded_hal::digital::v2::InputPin;
+
+use crate::button::{self, Button};
+
+/// A free running 16-bit counter of quadrature edges.
+pub trait Counter {
+    fn count(&self) -> u16;
+}
+
+/// Counts from `from` to `to`, taking the shortest way around the 16-bit
+/// wrap: 65535 -> 2 is +3 and 2 -> 65535 is -3.
+pub fn wrapping_delta(from: u16, to: u16) -> i32 {
+    i32::from(to.wrapping_sub(from) as i16)
+}
+
+/// Time over which the velocity is measured, in milliseconds.
+const VELOCITY_WINDOW: u32 = 100;
+
+/// For encoders without push button.
+pub struct NoButton;
+
+impl InputPin for NoButton {
+    type Error = ();
+
+    fn is_high(&self) -> Result<bool, ()> {
+        Ok(false)
+    }
+
+    fn is_low(&self) -> Result<bool, ()> {
+        Ok(true)
+    }
+}
+
+pub struct Encoder<C, PIN = NoButton> {
+    counter: C,
+    counts_per_step: i32,
+    /// Last value read from the counter
+    last: u16,
+    /// Extended position, in counts
+    counts: i32,
+    /// Position in steps at the last `delta()`
+    read_steps: i32,
+    /// Start of the current velocity window: time and counts
+    window: (u32, i32),
+    /// Steps per second
+    velocity: i32,
+    button: Option<Button<PIN>>,
+}
+
+impl<C: Counter> Encoder<C> {
+    /// The current position of the counter is taken as 0.
+    pub fn new(counter: C, counts_per_step: u8) -> Self {
+        let last = counter.count();
+        Encoder {
+            counter,
+            counts_per_step: i32::from(counts_per_step.max(1)),
+            last,
+            counts: 0,
+            read_steps: 0,
+            window: (0, 0),
+            velocity: 0,
+            button: None,
+        }
+    }
+
+    pub fn with_button<PIN: InputPin>(self, button: Button<PIN>) -> Encoder<C, PIN> {
+        Encoder {
+            counter: self.counter,
+            counts_per_step: self.counts_per_step,
+            last: self.last,
+            counts: self.counts,
+            read_steps: self.read_steps,
+            window: self.window,
+            velocity: self.velocity,
+            button: Some(button),
+        }
+    }
+}
+
+impl<C: Counter, PIN: InputPin> Encoder<C, PIN> {
+    /// Read the counter and add its change to the position.
+    fn update(&mut self) {
+        let count = self.counter.count();
+        self.counts = self
+            .counts
+            .wrapping_add(wrapping_delta(self.last, count));
+        self.last = count;
+    }
+
+    fn steps(&self) -> i32 {
+        // Rounding towards minus infinity, so that the step boundaries are
+        // the same on both sides of 0
+        let (counts, per_step) = (self.counts, self.counts_per_step);
+        if counts >= 0 {
+            counts / per_step
+        } else {
+            -((-counts + per_step - 1) / per_step)
+        }
+    }
+
+    /// Position in steps.
+    pub fn position(&mut self) -> i32 {
+        self.update();
+        self.steps()
+    }
+
+    /// Steps since the last call (or since the creation).
+    pub fn delta(&mut self) -> i32 {
+        self.update();
+        let steps = self.steps();
+        let delta = steps.wrapping_sub(self.read_steps);
+        self.read_steps = steps;
+        delta
+    }
+
+    /// Steps per second, positive when the position increases.  It's
+    /// measured over windows of 100 ms, call it at least that often with the
+    /// current time in milliseconds.
+    pub fn velocity(&mut self, now: u32) -> i32 {
+        self.update();
+        let (start, counts) = self.window;
+        let elapsed = now.wrapping_sub(start);
+        if elapsed >= VELOCITY_WINDOW {
+            let moved = i64::from(self.counts.wrapping_sub(counts));
+            self.velocity =
+                (moved * 1000 / i64::from(elapsed) / i64::from(self.counts_per_step)) as i32;
+            self.window = (now, self.counts);
+        }
+        self.velocity
+    }
+
+    /// Poll the push button, if there is one. See `button::Button::poll`.
+    pub fn button(&mut self, now: u32) -> Option<button::Event> {
+        self.button.as_mut().and_then(|b| b.poll(now))
+    }
+
+    pub fn release(self) -> (C, Option<Button<PIN>>) {
+        (self.counter, self.button)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+
+    struct MockCounter<'a>(&'a Cell<u16>);
+
+    impl<'a> Counter for MockCounter<'a> {
+        fn count(&self) -> u16 {
+            self.0.get()
+        }
+    }
+
+    /// Turn by `counts`, wrapping like the timer.
+    fn turn(count: &Cell<u16>, counts: i32) {
+        count.set((i32::from(count.get()) + counts) as u16);
+    }
+
+    #[test]
+    fn delta_wraps() {
+        assert_eq!(wrapping_delta(65535, 2), 3);
+        assert_eq!(wrapping_delta(2, 65535), -3);
+        assert_eq!(wrapping_delta(100, 100), 0);
+        assert_eq!(wrapping_delta(0, 32767), 32767);
+        assert_eq!(wrapping_delta(0, 32768), -32768);
+    }
+
+    #[test]
+    fn position_across_the_wrap() {
+        let count = Cell::new(65530);
+        let mut encoder = Encoder::new(MockCounter(&count), 1);
+        turn(&count, 10);
+        assert_eq!(count.get(), 4);
+        assert_eq!(encoder.position(), 10);
+        turn(&count, -20);
+        assert_eq!(encoder.position(), -10);
+        // Many turns, read often enough
+        for _ in 0..10 {
+            turn(&count, 30000);
+            encoder.position();
+        }
+        assert_eq!(encoder.position(), 299_990);
+    }
+
+    #[test]
+    fn steps_and_delta() {
+        let count = Cell::new(0);
+        let mut encoder = Encoder::new(MockCounter(&count), 4);
+        turn(&count, 3);
+        assert_eq!(encoder.delta(), 0);
+        turn(&count, 1);
+        assert_eq!(encoder.delta(), 1);
+        // Back past the start: -1 count is already in step -1
+        turn(&count, -5);
+        assert_eq!(encoder.position(), -1);
+        assert_eq!(encoder.delta(), -2);
+        turn(&count, -8);
+        assert_eq!(encoder.position(), -3);
+        turn(&count, 9);
+        assert_eq!(encoder.position(), 0);
+        assert_eq!(encoder.delta(), 1);
+    }
+
+    #[test]
+    fn velocity() {
+        let count = Cell::new(65000);
+        let mut encoder = Encoder::new(MockCounter(&count), 4);
+        assert_eq!(encoder.velocity(0), 0);
+        // 4 steps every 100 ms, across the wrap
+        for t in 1..=10 {
+            turn(&count, 16);
+            assert_eq!(encoder.velocity(t * 100), 40);
+        }
+        turn(&count, -8);
+        assert_eq!(encoder.velocity(1050), 40);
+        assert_eq!(encoder.velocity(1100), -20);
+    }
+}
@@ -0,0 +1,187 @@
+//! External interrupts (EXTI) on pin edges.
+//!
+//! Every line `n` of the EXTI can be connected to the pin `n` of one of the
+//! ports (PA`n`, PB`n`, PC`n`...) through the AFIO EXTICR registers, and can
+//! trigger on the rising edge, the falling edge or both.  Lines 0 to 4 have
+//! their own interrupt, lines 5 to 9 share `EXTI9_5` and lines 10 to 15 share
+//! `EXTI15_10`.
+//!
+//! The callbacks registered with `Exti::listen` run in interrupt context,
+//! from `handle_pending`, which the binary has to call from the handlers of
+//! the lines it uses:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn EXTI9_5() {
+//!     app::exti::handle_pending();
+//! }
+//! ```
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+use cortex_m::peripheral::NVIC;
+use stm32f1xx_hal::pac::{self, Interrupt};
+
+/// Lines connected to the GPIO pins.
+pub const LINES: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Called with the line number that triggered.
+pub type Callback = fn(u8);
+
+/// Set or clear the `mask` bits of an EXTI register. IMR, RTSR and FTSR have
+/// the same layout but different types.
+macro_rules! set_bits {
+    ($reg:expr, $mask:expr, $set:expr) => {
+        $reg.modify(|r, w| unsafe {
+            w.bits(if $set {
+                r.bits() | $mask
+            } else {
+                r.bits() & !$mask
+            })
+        })
+    };
+}
+
+static CALLBACKS: Mutex<Cell<[Option<Callback>; LINES as usize]>> =
+    Mutex::new(Cell::new([None; LINES as usize]));
+
+/// The interrupt of a line.
+pub fn interrupt(line: u8) -> Interrupt {
+    match line {
+        0 => Interrupt::EXTI0,
+        1 => Interrupt::EXTI1,
+        2 => Interrupt::EXTI2,
+        3 => Interrupt::EXTI3,
+        4 => Interrupt::EXTI4,
+        5..=9 => Interrupt::EXTI9_5,
+        _ => Interrupt::EXTI15_10,
+    }
+}
+
+pub struct Exti {
+    exti: pac::EXTI,
+}
+
+impl Exti {
+    /// The AFIO clock must be enabled for the routing to the ports to work,
+    /// `dp.AFIO.constrain(&mut rcc.apb2)` does it.
+    pub fn new(exti: pac::EXTI) -> Self {
+        Exti { exti }
+    }
+
+    /// Connect the line to the pin of `port` with the same number, call
+    /// `callback` on `edge` and enable the interrupt.  The pin has to be
+    /// configured as an input.
+    pub fn listen(&mut self, port: Port, line: u8, edge: Edge, callback: Option<Callback>) {
+        assert!(line < LINES);
+        let mask = 1 << line;
+
+        interrupt::free(|cs| {
+            let callbacks = CALLBACKS.borrow(cs);
+            let mut all = callbacks.get();
+            all[line as usize] = callback;
+            callbacks.set(all);
+        });
+
+        route(port, line);
+        let (rising, falling) = match edge {
+            Edge::Rising => (true, false),
+            Edge::Falling => (false, true),
+            Edge::Both => (true, true),
+        };
+        set_bits!(self.exti.rtsr, mask, rising);
+        set_bits!(self.exti.ftsr, mask, falling);
+        // A stale edge from before the configuration would fire right away
+        self.exti.pr.write(|w| unsafe { w.bits(mask) });
+        set_bits!(self.exti.imr, mask, true);
+        unsafe { NVIC::unmask(interrupt(line)) };
+    }
+
+    /// Disable the line. The NVIC interrupt is left enabled because it may be
+    /// shared with other lines.
+    pub fn unlisten(&mut self, line: u8) {
+        let mask = 1 << line;
+        set_bits!(self.exti.imr, mask, false);
+        self.exti.pr.write(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Trigger the line from software.
+    pub fn trigger(&mut self, line: u8) {
+        self.exti.swier.write(|w| unsafe { w.bits(1 << line) });
+    }
+}
+
+/// Clear the pending enabled lines and run their callbacks, in line order.
+pub fn handle_pending() {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    let pending = exti.pr.read().bits() & exti.imr.read().bits() & ((1 << LINES) - 1);
+    // Clear first, so that an edge during the callback isn't lost
+    exti.pr.write(|w| unsafe { w.bits(pending) });
+    let callbacks = interrupt::free(|cs| CALLBACKS.borrow(cs).get());
+    for line in 0..LINES {
+        if pending & (1 << line) != 0 {
+            if let Some(callback) = callbacks[line as usize] {
+                callback(line);
+            }
+        }
+    }
+}
+
+/// Sleep until `done` returns true.  Any interrupt wakes the core up (also
+/// SysTick, if `time::SysTickMillis` is running), so `done` is checked after
+/// each one, with interrupts disabled to not miss one that arrives between
+/// the check and the sleep.
+pub fn sleep_until<F: FnMut() -> bool>(mut done: F) {
+    loop {
+        let finished = interrupt::free(|_| {
+            if done() {
+                return true;
+            }
+            // WFI wakes up on a pending interrupt even when they are
+            // disabled, which then runs when leaving the critical section
+            cortex_m::asm::wfi();
+            false
+        });
+        if finished {
+            return;
+        }
+    }
+}
+
+/// Select the port of the line in AFIO_EXTICR1..4, 4 bits per line.
+fn route(port: Port, line: u8) {
+    let afio = unsafe { &*pac::AFIO::ptr() };
+    let shift = (line % 4) * 4;
+    let clear = !(0xf << shift);
+    let value = (port as u32) << shift;
+    match line / 4 {
+        0 => afio
+            .exticr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & clear | value) }),
+        1 => afio
+            .exticr2
+            .modify(|r, w| unsafe { w.bits(r.bits() & clear | value) }),
+        2 => afio
+            .exticr3
+            .modify(|r, w| unsafe { w.bits(r.bits() & clear | value) }),
+        _ => afio
+            .exticr4
+            .modify(|r, w| unsafe { w.bits(r.bits() & clear | value) }),
+    }
+}
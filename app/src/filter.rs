@@ -0,0 +1,503 @@
+//! Filters for noisy ADC readings.
+//!
+//! A potentiometer read with `adc` jumps by a few counts from a reading to
+//! the next, more when a PWM output switches nearby, and once in a while a
+//! reading is way off.  Fed straight to a PWM duty that makes an LED flicker.
+//! Each filter deals with a part of it:
+//!
+//! - `MovingAverage`: the average of the last N readings, against the noise.
+//! - `Exponential`: smoothing with a weight of 1/2^k for the new reading,
+//!   like a moving average of ~2^k readings in a single word.
+//! - `Median`: the median of the last N readings, removes isolated spikes
+//!   that an average would only spread.
+//! - `Hysteresis`: holds the output until the input moves by more than a
+//!   band, so what is left of the noise doesn't reach the output.
+//! - `Decimator`: adds up 4^n readings into one with n more bits, when the
+//!   noise dithers the input across several counts.
+//!
+//! They are usually chained, spikes out first and hysteresis last:
+//!
+//! ```ignore
+//! let mut median: Median<U5> = Median::new();
+//! let mut smooth = Exponential::new(3);
+//! let mut band = Hysteresis::new(8);
+//! let duty = band.update(smooth.update(median.update(reading)));
+//! ```
+//!
+//! Everything is integer: the readings are `u16`, `Exponential` keeps 12
+//! bits of fraction.
+
+use heapless::{ArrayLength, Vec};
+
+/// Fraction bits of the `Exponential` state.
+const FRACTION_BITS: u32 = 12;
+/// Most extra bits of a `Decimator`: 4^6 readings of 16 bits fit in a u32.
+pub const MAX_EXTRA_BITS: u32 = 6;
+
+/// Average of the last N readings.  Until N readings are in, the average of
+/// the ones there are.
+pub struct MovingAverage<N: ArrayLength<u16>> {
+    window: Vec<u16, N>,
+    /// Next reading to replace, once the window is full
+    next: usize,
+    sum: u32,
+}
+
+impl<N: ArrayLength<u16>> MovingAverage<N> {
+    pub fn new() -> Self {
+        MovingAverage {
+            window: Vec::new(),
+            next: 0,
+            sum: 0,
+        }
+    }
+
+    pub fn update(&mut self, reading: u16) -> u16 {
+        if self.window.len() < self.window.capacity() {
+            self.window.push(reading).ok();
+        } else {
+            self.sum -= u32::from(self.window[self.next]);
+            self.window[self.next] = reading;
+            self.next = (self.next + 1) % self.window.len();
+        }
+        self.sum += u32::from(reading);
+        self.value()
+    }
+
+    /// The average, rounded, 0 before the first reading.
+    pub fn value(&self) -> u16 {
+        let len = self.window.len() as u32;
+        if len == 0 {
+            return 0;
+        }
+        ((self.sum + len / 2) / len) as u16
+    }
+
+    pub fn reset(&mut self) {
+        // `Vec::clear` of heapless 0.4 indexes past the end, which the debug
+        // builds of the host tests catch
+        self.window = Vec::new();
+        self.next = 0;
+        self.sum = 0;
+    }
+}
+
+impl<N: ArrayLength<u16>> Default for MovingAverage<N> {
+    fn default() -> Self {
+        MovingAverage::new()
+    }
+}
+
+/// Exponential smoothing: every reading moves the output by 1/2^shift of
+/// the difference.  The first reading is taken as is.
+pub struct Exponential {
+    shift: u32,
+    /// Output with `FRACTION_BITS` bits of fraction, `None` before the first
+    /// reading
+    state: Option<i32>,
+}
+
+impl Exponential {
+    /// `shift` from 0 (no smoothing) to 12.
+    pub fn new(shift: u32) -> Self {
+        Exponential {
+            shift: shift.min(FRACTION_BITS),
+            state: None,
+        }
+    }
+
+    pub fn update(&mut self, reading: u16) -> u16 {
+        let target = i32::from(reading) << FRACTION_BITS;
+        let state = match self.state {
+            Some(state) => state + ((target - state) >> self.shift),
+            None => target,
+        };
+        self.state = Some(state);
+        self.value()
+    }
+
+    /// The output, rounded, 0 before the first reading.
+    pub fn value(&self) -> u16 {
+        let state = self.state.unwrap_or(0);
+        ((state + (1 << (FRACTION_BITS - 1))) >> FRACTION_BITS) as u16
+    }
+
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+/// Median of the last N readings, N odd.  A spike shorter than half the
+/// window never reaches the output.
+pub struct Median<N: ArrayLength<u16>> {
+    window: Vec<u16, N>,
+    next: usize,
+}
+
+impl<N: ArrayLength<u16>> Median<N> {
+    pub fn new() -> Self {
+        Median {
+            window: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn update(&mut self, reading: u16) -> u16 {
+        if self.window.len() < self.window.capacity() {
+            self.window.push(reading).ok();
+        } else {
+            self.window[self.next] = reading;
+            self.next = (self.next + 1) % self.window.len();
+        }
+        self.value()
+    }
+
+    /// The median, 0 before the first reading.  With an even number of
+    /// readings, the upper one of the middle two.
+    pub fn value(&self) -> u16 {
+        let mut sorted = self.window.clone();
+        sorted.sort_unstable();
+        sorted.get(sorted.len() / 2).cloned().unwrap_or(0)
+    }
+
+    pub fn reset(&mut self) {
+        self.window = Vec::new();
+        self.next = 0;
+    }
+}
+
+impl<N: ArrayLength<u16>> Default for Median<N> {
+    fn default() -> Self {
+        Median::new()
+    }
+}
+
+/// Dead band: the output only changes when the input is more than `band`
+/// away from it, and then jumps to the input.
+pub struct Hysteresis {
+    band: u16,
+    output: Option<u16>,
+}
+
+impl Hysteresis {
+    pub fn new(band: u16) -> Self {
+        Hysteresis { band, output: None }
+    }
+
+    pub fn update(&mut self, reading: u16) -> u16 {
+        let output = match self.output {
+            Some(output)
+                if (i32::from(reading) - i32::from(output)).abs() <= i32::from(self.band) =>
+            {
+                output
+            }
+            _ => reading,
+        };
+        self.output = Some(output);
+        output
+    }
+
+    /// The output, 0 before the first reading.
+    pub fn value(&self) -> u16 {
+        self.output.unwrap_or(0)
+    }
+
+    pub fn reset(&mut self) {
+        self.output = None;
+    }
+}
+
+/// Oversampling: 4^n readings make one with n more bits (a 12-bit ADC
+/// reads with 14 bits from 16 readings).  The extra bits are only real
+/// when there is at least a count of noise on the input to dither it.
+pub struct Decimator {
+    extra_bits: u32,
+    count: u32,
+    sum: u32,
+}
+
+impl Decimator {
+    /// `extra_bits` up to `MAX_EXTRA_BITS`.
+    pub fn new(extra_bits: u32) -> Self {
+        Decimator {
+            extra_bits: extra_bits.min(MAX_EXTRA_BITS),
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    /// Readings per output.
+    pub fn ratio(&self) -> u32 {
+        1 << (2 * self.extra_bits)
+    }
+
+    /// The reading with the extra bits when `ratio` readings are in.
+    pub fn update(&mut self, reading: u16) -> Option<u32> {
+        self.sum += u32::from(reading);
+        self.count += 1;
+        if self.count < self.ratio() {
+            return None;
+        }
+        let value = self.sum >> self.extra_bits;
+        self.count = 0;
+        self.sum = 0;
+        Some(value)
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.sum = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use heapless::consts::*;
+
+    /// Readings of a potentiometer that doesn't move, like the ones of
+    /// potentiometer2: a few counts of noise around `level` and a spike of
+    /// 200 counts every 37 readings, when the PWM switches.  Made with a
+    /// xorshift to be the same on every run.
+    fn trace(level: u16, len: usize) -> std::vec::Vec<u16> {
+        let mut seed: u32 = 0x1234_5678;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        (0..len)
+            .map(|i| {
+                // Sum of 4 uniform -3..=3: close to normal
+                let noise: i32 = (0..4).map(|_| (random() % 7) as i32 - 3).sum();
+                let spike = if i % 37 == 36 { 200 } else { 0 };
+                (i32::from(level) + noise + spike) as u16
+            })
+            .collect()
+    }
+
+    /// Readings of a potentiometer left alone on PB0, recorded with the
+    /// logger example and `adclog`: the `ch8` column of every CSV file in
+    /// `app/traces`.  There has to be at least one.
+    fn recorded() -> std::vec::Vec<(std::string::String, std::vec::Vec<u16>)> {
+        let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("traces");
+        let entries = std::fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("no recorded traces in {}: {}", dir.display(), e));
+        let mut files: std::vec::Vec<_> = entries
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some("csv".as_ref()))
+            .collect();
+        assert!(!files.is_empty(), "no CSV file in {}", dir.display());
+        files.sort();
+        files
+            .into_iter()
+            .map(|path| {
+                let text = std::fs::read_to_string(&path).unwrap();
+                let mut lines = text.lines();
+                let header = lines.next().unwrap_or("");
+                let column = header
+                    .split(',')
+                    .position(|name| name == "ch8")
+                    .unwrap_or_else(|| panic!("no ch8 column in {}", path.display()));
+                let readings = lines
+                    .map(|line| line.split(',').nth(column).unwrap().parse().unwrap())
+                    .collect();
+                (path.display().to_string(), readings)
+            })
+            .collect()
+    }
+
+    /// What the filters of potentiometer2 have to do with the readings of a
+    /// potentiometer that doesn't move.
+    fn check_steady(name: &str, trace: &[u16]) {
+        // Only spikes of a single reading, never two in a window: the median
+        // stays within the noise
+        let mut median: Median<U5> = Median::new();
+        let outputs: std::vec::Vec<u16> = trace.iter().map(|&r| median.update(r)).collect();
+        let settled = &outputs[5..];
+        assert!(spread(settled) <= 24, "{}: {}", name, spread(settled));
+
+        // Smoothed behind the median
+        let mut median: Median<U5> = Median::new();
+        let mut smooth = Exponential::new(4);
+        let outputs: std::vec::Vec<u16> = trace
+            .iter()
+            .map(|&r| smooth.update(median.update(r)))
+            .collect();
+        let settled = &outputs[100..];
+        assert!(spread(settled) <= 8, "{}: {}", name, spread(settled));
+
+        // The whole chain doesn't move once settled
+        let mut median: Median<U5> = Median::new();
+        let mut smooth = Exponential::new(3);
+        let mut band = Hysteresis::new(8);
+        let outputs: std::vec::Vec<u16> = trace
+            .iter()
+            .map(|&r| band.update(smooth.update(median.update(r))))
+            .collect();
+        assert_eq!(spread(&outputs[20..]), 0, "{}", name);
+    }
+
+    fn spread(outputs: &[u16]) -> u16 {
+        outputs.iter().max().unwrap() - outputs.iter().min().unwrap()
+    }
+
+    #[test]
+    fn noise_trace() {
+        let trace = trace(2000, 1000);
+        assert!(spread(&trace) > 200);
+        let quiet: std::vec::Vec<u16> = trace.iter().cloned().filter(|&r| r < 2100).collect();
+        assert!(spread(&quiet) >= 12, "{}", spread(&quiet));
+    }
+
+    #[test]
+    fn steady_synthetic_trace() {
+        check_steady("synthetic", &trace(2000, 1000));
+    }
+
+    #[test]
+    #[ignore = "needs a trace recorded with the logger example and adclog in app/traces"]
+    fn steady_recorded_traces() {
+        for (name, trace) in recorded() {
+            check_steady(&name, &trace);
+        }
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut filter: MovingAverage<U16> = MovingAverage::new();
+        assert_eq!(filter.value(), 0);
+        assert_eq!(filter.update(100), 100);
+        assert_eq!(filter.update(200), 150);
+
+        filter.reset();
+        let outputs: std::vec::Vec<u16> = trace(2000, 1000)
+            .into_iter()
+            .map(|r| filter.update(r))
+            .collect();
+        // A spike moves 16 outputs by 200 / 16
+        let settled = &outputs[16..];
+        assert!(spread(settled) <= 20, "{}", spread(settled));
+        for &output in settled {
+            assert!((i32::from(output) - 2005).abs() <= 12, "{}", output);
+        }
+    }
+
+    #[test]
+    fn exponential() {
+        let mut filter = Exponential::new(3);
+        assert_eq!(filter.update(1000), 1000);
+        // 1/8 of the way every time
+        assert_eq!(filter.update(1800), 1100);
+        for _ in 0..100 {
+            filter.update(1800);
+        }
+        assert_eq!(filter.value(), 1800);
+        for _ in 0..100 {
+            filter.update(0);
+        }
+        assert_eq!(filter.value(), 0);
+
+        let mut filter = Exponential::new(4);
+        let outputs: std::vec::Vec<u16> = trace(2000, 1000)
+            .into_iter()
+            .map(|r| filter.update(r))
+            .collect();
+        let settled = &outputs[100..];
+        assert!(spread(settled) <= 20, "{}", spread(settled));
+
+        // No smoothing
+        let mut filter = Exponential::new(0);
+        assert_eq!(filter.update(5), 5);
+        assert_eq!(filter.update(4095), 4095);
+    }
+
+    #[test]
+    fn median() {
+        let mut filter: Median<U5> = Median::new();
+        assert_eq!(filter.value(), 0);
+        assert_eq!(filter.update(10), 10);
+        assert_eq!(filter.update(30), 30);
+        assert_eq!(filter.update(20), 20);
+        filter.reset();
+
+        // The spikes are gone, only the noise is left
+        let outputs: std::vec::Vec<u16> = trace(2000, 1000)
+            .into_iter()
+            .map(|r| filter.update(r))
+            .collect();
+        assert!(outputs.iter().all(|&o| o < 2012 && o > 1988));
+
+        // A step goes through after half the window
+        let mut filter: Median<U5> = Median::new();
+        for _ in 0..5 {
+            filter.update(100);
+        }
+        assert_eq!(filter.update(900), 100);
+        assert_eq!(filter.update(900), 100);
+        assert_eq!(filter.update(900), 900);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut filter = Hysteresis::new(8);
+        assert_eq!(filter.update(100), 100);
+        assert_eq!(filter.update(108), 100);
+        assert_eq!(filter.update(92), 100);
+        assert_eq!(filter.update(109), 109);
+        assert_eq!(filter.update(101), 109);
+        assert_eq!(filter.update(0), 0);
+        // The ends are reached
+        assert_eq!(filter.update(4095), 4095);
+    }
+
+    #[test]
+    fn decimator() {
+        let mut filter = Decimator::new(2);
+        assert_eq!(filter.ratio(), 16);
+        // Noise between 100 and 101 gives 100.5, 402 in 14 bits
+        let outputs: std::vec::Vec<u32> =
+            (0..64).filter_map(|i| filter.update(100 + i % 2)).collect();
+        assert_eq!(outputs, [402, 402, 402, 402]);
+
+        let mut filter = Decimator::new(4);
+        let outputs: std::vec::Vec<u32> = trace(2000, 256 * 4)
+            .into_iter()
+            .filter_map(|r| filter.update(r))
+            .collect();
+        assert_eq!(outputs.len(), 4);
+        // 16 bits, the spikes add ~5.4 counts
+        for &output in &outputs {
+            assert!((output as i32 - 2005 * 16).abs() <= 16, "{}", output);
+        }
+
+        let mut filter = Decimator::new(10);
+        assert_eq!(filter.ratio(), 4096);
+        let outputs: std::vec::Vec<u32> = (0..4096).filter_map(|_| filter.update(65535)).collect();
+        assert_eq!(outputs, [65535 << 6]);
+    }
+
+    /// The chain of potentiometer2: no change of the duty while the
+    /// potentiometer doesn't move, and the new position when it does.
+    #[test]
+    fn no_flicker() {
+        let mut median: Median<U5> = Median::new();
+        let mut smooth = Exponential::new(3);
+        let mut band = Hysteresis::new(8);
+        let mut filter = |r| band.update(smooth.update(median.update(r)));
+
+        let mut outputs: std::vec::Vec<u16> =
+            trace(2000, 1000).into_iter().map(&mut filter).collect();
+        // Once the smoothing settled from the first reading
+        assert_eq!(spread(&outputs[20..]), 0);
+
+        outputs = trace(3000, 1000).into_iter().map(&mut filter).collect();
+        assert_eq!(spread(&outputs[100..]), 0);
+        assert!(
+            (i32::from(outputs[999]) - 3000).abs() <= 8,
+            "{}",
+            outputs[999]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Erasing and programming the internal flash.
+//!
+//! The HAL only exposes the `ACR` register, so the programming sequence from
+//! the STM32F10xxx flash programming manual (PM0075) is done here directly on
+//! the registers.
+
+use stm32f1xx_hal::pac;
+
+/// Size of a flash page on medium-density devices (like the STM32F103C8).
+pub const PAGE_SIZE: u32 = 1024;
+
+/// Start of the upper 64 KiB of flash. Most STM32F103C8 parts have 128 KiB
+/// even though only 64 KiB are advertised (and used by `memory.x`), which
+/// makes this area a good place to store data.
+pub const UPPER_FLASH: u32 = 0x0801_0000;
+
+/// The flash, 128 KiB including the upper part.
+const FLASH_START: u32 = 0x0800_0000;
+const FLASH_END: u32 = 0x0802_0000;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The address is not aligned or is outside of the flash.
+    Address,
+    /// Programming a half-word that was not erased.
+    Programming,
+    /// The page is write protected.
+    WriteProtection,
+}
+
+/// Flash memory that can be erased by pages and programmed by half-words.
+pub trait Flash {
+    /// Erase the page that starts at `address`, setting all its bytes to 0xff.
+    fn erase_page(&mut self, address: u32) -> Result<(), Error>;
+
+    /// Program `data` at `address`. Both the address and the length must be
+    /// multiples of 2, and the area must have been erased before.
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), Error>;
+
+    /// Read `buf.len()` bytes starting at `address`.
+    fn read(&self, address: u32, buf: &mut [u8]);
+}
+
+/// The internal flash of the microcontroller.
+pub struct InternalFlash {
+    _private: (),
+}
+
+impl InternalFlash {
+    /// The `FLASH` peripheral is usually consumed by `constrain()` to configure
+    /// the wait states, so this accesses its registers through a raw pointer.
+    ///
+    /// # Safety
+    ///
+    /// Only create one of these, and don't touch the flash registers anywhere
+    /// else while it is in use.
+    pub unsafe fn new() -> Self {
+        InternalFlash { _private: () }
+    }
+
+    fn regs(&self) -> &pac::flash::RegisterBlock {
+        unsafe { &*pac::FLASH::ptr() }
+    }
+
+    fn unlock(&self) {
+        let flash = self.regs();
+        if flash.cr.read().lock().bit_is_set() {
+            flash.keyr.write(|w| unsafe { w.key().bits(KEY1) });
+            flash.keyr.write(|w| unsafe { w.key().bits(KEY2) });
+        }
+    }
+
+    fn lock(&self) {
+        self.regs().cr.modify(|_, w| w.lock().set_bit());
+    }
+
+    fn wait(&self) -> Result<(), Error> {
+        let flash = self.regs();
+        while flash.sr.read().bsy().bit_is_set() {}
+        let sr = flash.sr.read();
+        // The status flags are cleared by writing 1 to them
+        flash.sr.write(|w| w.eop().set_bit().pgerr().set_bit().wrprterr().set_bit());
+        if sr.wrprterr().bit_is_set() {
+            Err(Error::WriteProtection)
+        } else if sr.pgerr().bit_is_set() {
+            Err(Error::Programming)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether `len` bytes at `address` are all in the flash.
+fn in_flash(address: u32, len: usize) -> bool {
+    address >= FLASH_START && u64::from(address) + len as u64 <= u64::from(FLASH_END)
+}
+
+impl Flash for InternalFlash {
+    fn erase_page(&mut self, address: u32) -> Result<(), Error> {
+        if !address.is_multiple_of(PAGE_SIZE) || !in_flash(address, PAGE_SIZE as usize) {
+            return Err(Error::Address);
+        }
+        self.unlock();
+        let flash = self.regs();
+        flash.cr.modify(|_, w| w.per().set_bit());
+        flash.ar.write(|w| unsafe { w.far().bits(address) });
+        flash.cr.modify(|_, w| w.strt().set_bit());
+        let res = self.wait();
+        flash.cr.modify(|_, w| w.per().clear_bit());
+        self.lock();
+        res
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        if !address.is_multiple_of(2) || !data.len().is_multiple_of(2) || !in_flash(address, data.len()) {
+            return Err(Error::Address);
+        }
+        self.unlock();
+        let flash = self.regs();
+        flash.cr.modify(|_, w| w.pg().set_bit());
+        let mut res = Ok(());
+        for (i, half) in data.chunks(2).enumerate() {
+            let value = u16::from(half[0]) | u16::from(half[1]) << 8;
+            let ptr = (address + i as u32 * 2) as *mut u16;
+            unsafe { core::ptr::write_volatile(ptr, value) };
+            res = self.wait();
+            if res.is_err() {
+                break;
+            }
+        }
+        flash.cr.modify(|_, w| w.pg().clear_bit());
+        self.lock();
+        res
+    }
+
+    fn read(&self, address: u32, buf: &mut [u8]) {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = unsafe { core::ptr::read_volatile((address + i as u32) as *const u8) };
+        }
+    }
+}
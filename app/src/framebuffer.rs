@@ -0,0 +1,188 @@
+//! 128x64 monochrome framebuffer with the memory layout of the SSD1306.
+//!
+//! The display memory is split in 8 pages of 8 rows.  Every byte holds a
+//! column of 8 pixels of a page, with the top pixel in the least significant
+//! bit:
+//!
+//! ```text
+//! byte = buf[(y / 8) * 128 + x], bit = y % 8
+//! ```
+//!
+//! It implements `Drawing` like `ssd1306::GraphicsMode`, so the screens of the
+//! examples can be drawn into it on the host and checked against images.
+//!
+//! It also remembers which pages were modified, so that `oled::Oled` only has
+//! to look at those when looking for changes to send.
+
+use embedded_graphics::drawable::Pixel;
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::unsignedcoord::UnsignedCoord;
+use embedded_graphics::Drawing;
+
+pub const WIDTH: u32 = 128;
+pub const HEIGHT: u32 = 64;
+pub const PAGES: u32 = HEIGHT / 8;
+
+pub struct Framebuffer {
+    buf: [u8; (WIDTH * PAGES) as usize],
+    /// Bit `n` is set when page `n` was written since the last `take_touched`
+    touched: u8,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            buf: [0; (WIDTH * PAGES) as usize],
+            touched: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for (page, bytes) in self.buf.chunks_mut(WIDTH as usize).enumerate() {
+            if bytes.iter().any(|&b| b != 0) {
+                self.touched |= 1 << page;
+                for b in bytes.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+    }
+
+    /// Pixels outside of the display are ignored.
+    pub fn set_pixel(&mut self, x: u32, y: u32, on: bool) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+        let idx = ((y / 8) * WIDTH + x) as usize;
+        let mask = 1 << (y % 8);
+        let old = self.buf[idx];
+        if on {
+            self.buf[idx] |= mask;
+        } else {
+            self.buf[idx] &= !mask;
+        }
+        if self.buf[idx] != old {
+            self.touched |= 1 << (y / 8);
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        if x >= WIDTH || y >= HEIGHT {
+            return false;
+        }
+        self.buf[((y / 8) * WIDTH + x) as usize] & (1 << (y % 8)) != 0
+    }
+
+    /// The display memory, page after page.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// The 128 bytes of a page.
+    pub fn page(&self, page: u32) -> &[u8] {
+        let start = (page * WIDTH) as usize;
+        &self.buf[start..start + WIDTH as usize]
+    }
+
+    fn page_mut(&mut self, page: u32) -> &mut [u8] {
+        let start = (page * WIDTH) as usize;
+        &mut self.buf[start..start + WIDTH as usize]
+    }
+
+    /// Copy a page from another framebuffer, without marking it as touched.
+    pub fn copy_page(&mut self, page: u32, from: &Framebuffer) {
+        self.page_mut(page).copy_from_slice(from.page(page));
+    }
+
+    /// Pages modified since the last call, as a bit mask.
+    pub fn take_touched(&mut self) -> u8 {
+        let touched = self.touched;
+        self.touched = 0;
+        touched
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drawing<PixelColorU8> for Framebuffer {
+    fn draw<T>(&mut self, item_pixels: T)
+    where
+        T: Iterator<Item = Pixel<PixelColorU8>>,
+    {
+        for Pixel(UnsignedCoord(x, y), color) in item_pixels {
+            self.set_pixel(x, y, color.into_inner() != 0);
+        }
+    }
+}
+
+/// Snapshot testing helpers: framebuffers are compared with PNG images in
+/// `app/snapshots`.  Run the tests with `UPDATE_SNAPSHOTS=1` to (re)create
+/// the images after an intended change, and review them before committing.
+#[cfg(test)]
+pub mod snapshot {
+    use super::*;
+    use std::fs::File;
+    use std::io::BufWriter;
+    use std::path::PathBuf;
+
+    fn path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("snapshots")
+            .join(format!("{}.png", name))
+    }
+
+    /// Write the framebuffer as an 8-bit grayscale PNG, lit pixels in white.
+    pub fn write_png(fb: &Framebuffer, path: &PathBuf) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = BufWriter::new(File::create(path).unwrap());
+        let mut encoder = png::Encoder::new(file, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        let data: Vec<u8> = (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| if fb.pixel(x, y) { 0xff } else { 0 })
+            .collect();
+        writer.write_image_data(&data).unwrap();
+    }
+
+    pub fn read_png(path: &PathBuf) -> Option<Framebuffer> {
+        let decoder = png::Decoder::new(File::open(path).ok()?);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height), (WIDTH, HEIGHT));
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        let mut fb = Framebuffer::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                fb.set_pixel(x, y, data[(y * WIDTH + x) as usize] >= 0x80);
+            }
+        }
+        Some(fb)
+    }
+
+    /// Compare `fb` with the `name` snapshot. On a mismatch the rendered
+    /// image is left next to it as `name.new.png`.
+    pub fn assert_snapshot(name: &str, fb: &Framebuffer) {
+        let golden = path(name);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            write_png(fb, &golden);
+            return;
+        }
+        let expected = read_png(&golden).unwrap_or_else(|| {
+            panic!(
+                "missing snapshot {}, create it with UPDATE_SNAPSHOTS=1",
+                golden.display()
+            )
+        });
+        if expected.as_bytes() != fb.as_bytes() {
+            let new = path(&format!("{}.new", name));
+            write_png(fb, &new);
+            panic!("{} differs from {}", new.display(), golden.display());
+        }
+    }
+}
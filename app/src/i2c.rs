@@ -0,0 +1,331 @@
+//! Recovery of a stuck I2C bus.
+//!
+//! A slave that was reset or glitched in the middle of a read can hold SDA
+//! low forever, waiting for clock pulses to finish sending its byte.  The I2C
+//! peripheral then sees a busy bus and every transfer fails (or, without
+//! timeouts, hangs).  `recover` takes the pins away from the peripheral,
+//! clocks SCL until the slave releases SDA (9 pulses at most: 8 bits and the
+//! ACK), sends a STOP condition and resets the peripheral, keeping its
+//! configuration.
+//!
+//! The timeouts of `BlockingI2c` count CPU cycles with the DWT, which is off
+//! after reset: without `enable_timeouts` a stuck bus hangs the transfer.
+//!
+//! `probe` tells whether an address answers, for `scan`.  The HAL can't do it:
+//! its transfers wait for the ACK until the timeout and leave the bus busy.
+//! `Master` reads the registers of what answers, on a bus set up by the HAL's
+//! `I2c`, which unlike `BlockingI2c` gives the peripheral and pins back.
+
+use cortex_m::peripheral::{DCB, DWT};
+use embedded_hal::blocking::i2c::WriteRead;
+use stm32f1xx_hal::pac;
+
+/// AFIO_MAPR bit that moves I2C1 from PB6/PB7 to PB8/PB9.
+const I2C1_REMAP: u32 = 1 << 1;
+/// CNF = 01, MODE = 10: general purpose open-drain output, 2 MHz.
+const GPIO_OPEN_DRAIN: u32 = 0b0110;
+const CR1_PE: u32 = 1 << 0;
+const CR1_START: u32 = 1 << 8;
+const CR1_STOP: u32 = 1 << 9;
+const CR1_ACK: u32 = 1 << 10;
+const CR1_POS: u32 = 1 << 11;
+const CR1_SWRST: u32 = 1 << 15;
+const SR1_SB: u32 = 1 << 0;
+const SR1_ADDR: u32 = 1 << 1;
+const SR1_BTF: u32 = 1 << 2;
+const SR1_RXNE: u32 = 1 << 6;
+const SR1_TXE: u32 = 1 << 7;
+const SR1_AF: u32 = 1 << 10;
+/// Status reads before giving up on a probe, a few ms at 72 MHz.
+const PROBE_TIMEOUT: u32 = 50_000;
+/// Half a period of the recovery clock, ~5 us at 72 MHz, slower below.
+const HALF_PERIOD: u32 = 360;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bus {
+    /// PB6/PB7 or, remapped, PB8/PB9
+    I2c1,
+    /// PB10/PB11
+    I2c2,
+}
+
+impl Bus {
+    /// SCL and SDA pins, all on GPIOB.
+    fn pins(self) -> (u32, u32) {
+        match self {
+            Bus::I2c1 => {
+                let afio = unsafe { &*pac::AFIO::ptr() };
+                if afio.mapr.read().bits() & I2C1_REMAP != 0 {
+                    (8, 9)
+                } else {
+                    (6, 7)
+                }
+            }
+            Bus::I2c2 => (10, 11),
+        }
+    }
+
+    fn regs(self) -> &'static pac::i2c1::RegisterBlock {
+        match self {
+            Bus::I2c1 => unsafe { &*pac::I2C1::ptr() },
+            Bus::I2c2 => unsafe { &*pac::I2C2::ptr() },
+        }
+    }
+}
+
+/// Let the DWT count cycles, for the timeouts of `BlockingI2c`.
+pub fn enable_timeouts() {
+    // The cortex-m crate only does it through the owned peripherals
+    unsafe {
+        (*DCB::PTR).demcr.modify(|r| r | (1 << 24));
+        (*DWT::PTR).ctrl.modify(|r| r | 1);
+    }
+}
+
+/// Set the 4 configuration bits of a GPIOB pin, returning the old ones.
+fn configure(pin: u32, cnf_mode: u32) -> u32 {
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+    let shift = (pin % 8) * 4;
+    let mut old = 0;
+    let mut update = |bits: u32| {
+        old = (bits >> shift) & 0xf;
+        (bits & !(0xf << shift)) | (cnf_mode << shift)
+    };
+    if pin < 8 {
+        gpiob.crl.modify(|r, w| unsafe { w.bits(update(r.bits())) });
+    } else {
+        gpiob.crh.modify(|r, w| unsafe { w.bits(update(r.bits())) });
+    }
+    old
+}
+
+fn set(pin: u32, high: bool) {
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+    let bit = if high { 1 << pin } else { 1 << (pin + 16) };
+    gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+    cortex_m::asm::delay(HALF_PERIOD);
+}
+
+fn is_high(pin: u32) -> bool {
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+    gpiob.idr.read().bits() & (1 << pin) != 0
+}
+
+/// Free the bus and reset the peripheral.  Returns whether SDA is released,
+/// if it isn't the slave is stuck for good or the line is shorted.
+pub fn recover(bus: Bus) -> bool {
+    let (scl, sda) = bus.pins();
+    let regs = bus.regs();
+    regs.cr1.write(|w| unsafe { w.bits(0) });
+
+    set(scl, true);
+    set(sda, true);
+    let scl_mode = configure(scl, GPIO_OPEN_DRAIN);
+    let sda_mode = configure(sda, GPIO_OPEN_DRAIN);
+    for _ in 0..9 {
+        if is_high(sda) {
+            break;
+        }
+        set(scl, false);
+        set(scl, true);
+    }
+    // STOP: SDA rises while SCL is high
+    set(scl, false);
+    set(sda, false);
+    set(scl, true);
+    set(sda, true);
+    let released = is_high(sda);
+    configure(scl, scl_mode);
+    configure(sda, sda_mode);
+
+    // The reset clears the timing configuration written by the HAL
+    let cr2 = regs.cr2.read().bits();
+    let ccr = regs.ccr.read().bits();
+    let trise = regs.trise.read().bits();
+    let oar1 = regs.oar1.read().bits();
+    regs.cr1.write(|w| unsafe { w.bits(CR1_SWRST) });
+    regs.cr1.write(|w| unsafe { w.bits(0) });
+    regs.cr2.write(|w| unsafe { w.bits(cr2) });
+    regs.ccr.write(|w| unsafe { w.bits(ccr) });
+    regs.trise.write(|w| unsafe { w.bits(trise) });
+    regs.oar1.write(|w| unsafe { w.bits(oar1) });
+    regs.cr1.write(|w| unsafe { w.bits(CR1_PE) });
+    released
+}
+
+/// Poll `done` until it returns true or the probe times out.
+fn wait<F: FnMut() -> bool>(mut done: F) -> bool {
+    (0..PROBE_TIMEOUT).any(|_| done())
+}
+
+/// Send the address `addr` and tell whether it is acknowledged.  The bus must
+/// have been set up, by `BlockingI2c` for example.
+pub fn probe(bus: Bus, addr: u8) -> bool {
+    let regs = bus.regs();
+    regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() | CR1_START) });
+    if !wait(|| regs.sr1.read().bits() & SR1_SB != 0) {
+        recover(bus);
+        return false;
+    }
+    // Write direction, the slave ACKs without anything to send
+    regs.dr.write(|w| unsafe { w.bits(u32::from(addr) << 1) });
+    let mut acked = false;
+    let answered = wait(|| {
+        let sr1 = regs.sr1.read().bits();
+        acked = sr1 & SR1_ADDR != 0;
+        acked || sr1 & SR1_AF != 0
+    });
+    if acked {
+        // Reading SR2 after SR1 clears ADDR
+        let _ = regs.sr2.read();
+    }
+    regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() | CR1_STOP) });
+    regs.sr1.modify(|r, w| unsafe { w.bits(r.bits() & !SR1_AF) });
+    if !answered || !wait(|| regs.cr1.read().bits() & CR1_STOP == 0) {
+        recover(bus);
+    }
+    acked
+}
+
+/// `recover(Bus::I2c1)`, for `oled::I2cInterface::with_recovery`.
+pub fn recover_i2c1() -> bool {
+    recover(Bus::I2c1)
+}
+
+/// `recover(Bus::I2c2)`, for `oled::I2cInterface::with_recovery`.
+pub fn recover_i2c2() -> bool {
+    recover(Bus::I2c2)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The address or a byte wasn't acknowledged
+    Nack,
+    /// The bus didn't move, it has been recovered
+    Timeout,
+}
+
+/// Blocking transfers straight on the registers of a bus that has been set
+/// up, with the timeouts of `probe`.
+pub struct Master(pub Bus);
+
+impl Master {
+    fn set_cr1(&self, bits: u32) {
+        let regs = self.0.regs();
+        regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() | bits) });
+    }
+
+    fn clear_cr1(&self, bits: u32) {
+        let regs = self.0.regs();
+        regs.cr1.modify(|r, w| unsafe { w.bits(r.bits() & !bits) });
+    }
+
+    /// Wait for a flag of SR1, failing on a NACK.
+    fn wait_for(&self, flag: u32) -> Result<(), Error> {
+        let regs = self.0.regs();
+        let mut nack = false;
+        let done = wait(|| {
+            let sr1 = regs.sr1.read().bits();
+            nack = sr1 & SR1_AF != 0;
+            nack || sr1 & flag != 0
+        });
+        if nack {
+            regs.sr1.modify(|r, w| unsafe { w.bits(r.bits() & !SR1_AF) });
+            self.set_cr1(CR1_STOP);
+            Err(Error::Nack)
+        } else if !done {
+            recover(self.0);
+            Err(Error::Timeout)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// START, or repeated START, and the address.  ADDR is left set.
+    fn start(&self, addr: u8, read: bool) -> Result<(), Error> {
+        self.set_cr1(CR1_START);
+        self.wait_for(SR1_SB)?;
+        let byte = (u32::from(addr) << 1) | read as u32;
+        self.0.regs().dr.write(|w| unsafe { w.bits(byte) });
+        self.wait_for(SR1_ADDR)
+    }
+
+    fn clear_addr(&self) {
+        let _ = self.0.regs().sr2.read();
+    }
+
+    fn read_dr(&self) -> u8 {
+        self.0.regs().dr.read().bits() as u8
+    }
+
+    /// The reads of 1, 2 and more bytes of the reference manual, like the
+    /// HAL does them.
+    fn read(&self, addr: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        match buffer.len() {
+            1 => {
+                self.clear_cr1(CR1_ACK);
+                self.start(addr, true)?;
+                self.clear_addr();
+                self.set_cr1(CR1_STOP);
+                self.wait_for(SR1_RXNE)?;
+                buffer[0] = self.read_dr();
+            }
+            2 => {
+                self.set_cr1(CR1_POS | CR1_ACK);
+                self.start(addr, true)?;
+                self.clear_addr();
+                self.clear_cr1(CR1_ACK);
+                self.wait_for(SR1_BTF)?;
+                self.set_cr1(CR1_STOP);
+                self.clear_cr1(CR1_POS);
+                buffer[0] = self.read_dr();
+                buffer[1] = self.read_dr();
+            }
+            len => {
+                self.set_cr1(CR1_ACK);
+                self.start(addr, true)?;
+                self.clear_addr();
+                let (first, last) = buffer.split_at_mut(len - 3);
+                for byte in first {
+                    self.wait_for(SR1_RXNE)?;
+                    *byte = self.read_dr();
+                }
+                self.wait_for(SR1_BTF)?;
+                self.clear_cr1(CR1_ACK);
+                last[0] = self.read_dr();
+                self.set_cr1(CR1_STOP);
+                last[1] = self.read_dr();
+                self.wait_for(SR1_RXNE)?;
+                last[2] = self.read_dr();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead for Master {
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        if !bytes.is_empty() {
+            self.start(addr, false)?;
+            self.clear_addr();
+            for &byte in bytes {
+                self.wait_for(SR1_TXE)?;
+                self.0.regs().dr.write(|w| unsafe { w.bits(u32::from(byte)) });
+            }
+            self.wait_for(SR1_BTF)?;
+        }
+        if !buffer.is_empty() {
+            self.read(addr, buffer)?;
+        } else if !bytes.is_empty() {
+            self.set_cr1(CR1_STOP);
+        }
+        // Until the STOP is sent the next START would be lost
+        if !wait(|| self.0.regs().cr1.read().bits() & CR1_STOP == 0) {
+            recover(self.0);
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+}
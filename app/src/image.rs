@@ -0,0 +1,166 @@
+//! 1-bit images in the page format of the SSD1306.
+//!
+//! The bytes are laid out like the display memory (see `framebuffer`): pages
+//! of 8 rows, one byte per column with the top pixel in the least significant
+//! bit.  They are made from PNG or PBM files by the `bitmap` tool of the
+//! `host` crate, or at build time for the files in `app/images` (see
+//! `images`).
+//!
+//! ```ignore
+//! images::RUST.draw(&mut disp, Coord::new(32, 0), Rotation::R90);
+//! ```
+
+use embedded_graphics::coord::Coord;
+use embedded_graphics::drawable::Pixel;
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::unsignedcoord::UnsignedCoord;
+use embedded_graphics::Drawing;
+
+/// Clockwise rotation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl Rotation {
+    /// The next rotation, 90 degrees further.
+    pub fn next(self) -> Self {
+        match self {
+            Rotation::R0 => Rotation::R90,
+            Rotation::R90 => Rotation::R180,
+            Rotation::R180 => Rotation::R270,
+            Rotation::R270 => Rotation::R0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    width: u32,
+    height: u32,
+    data: &'a [u8],
+}
+
+impl<'a> Image<'a> {
+    /// `data` must hold `width * ceil(height / 8)` bytes.
+    pub const fn new(width: u32, height: u32, data: &'a [u8]) -> Self {
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Width and height once rotated.
+    pub fn size(&self, rotation: Rotation) -> (u32, u32) {
+        match rotation {
+            Rotation::R0 | Rotation::R180 => (self.width, self.height),
+            Rotation::R90 | Rotation::R270 => (self.height, self.width),
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        self.data[((y / 8) * self.width + x) as usize] & (1 << (y % 8)) != 0
+    }
+
+    /// Pixel `x`, `y` of the rotated image.
+    fn rotated_pixel(&self, x: u32, y: u32, rotation: Rotation) -> bool {
+        let (w, h) = (self.width, self.height);
+        let (sx, sy) = match rotation {
+            Rotation::R0 => (x, y),
+            Rotation::R90 => (y, h - 1 - x),
+            Rotation::R180 => (w - 1 - x, h - 1 - y),
+            Rotation::R270 => (w - 1 - y, x),
+        };
+        self.pixel(sx, sy)
+    }
+
+    /// Copy the image with its top left corner at `pos`, unlit pixels
+    /// included.  The parts outside of the display are left out.
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D, pos: Coord, rotation: Rotation) {
+        let (w, h) = self.size(rotation);
+        disp.draw(
+            (0..w * h)
+                .map(move |i| {
+                    let (x, y) = (i % w, i / w);
+                    let on = self.rotated_pixel(x, y, rotation);
+                    (pos.0 + x as i32, pos.1 + y as i32, on)
+                })
+                .filter(|&(x, y, _)| x >= 0 && y >= 0)
+                .map(|(x, y, on)| Pixel(UnsignedCoord(x as u32, y as u32), (on as u8).into())),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+
+    // 3x2, an L lying on its back:
+    //
+    // #..
+    // ###
+    const L: Image = Image::new(3, 2, &[0b11, 0b10, 0b10]);
+
+    fn rows(fb: &Framebuffer, w: u32, h: u32, x0: u32, y0: u32) -> Vec<String> {
+        (y0..y0 + h)
+            .map(|y| {
+                (x0..x0 + w)
+                    .map(|x| if fb.pixel(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rotations() {
+        let cases = [
+            (Rotation::R0, vec!["#..", "###"]),
+            (Rotation::R90, vec!["##", "#.", "#."]),
+            (Rotation::R180, vec!["###", "..#"]),
+            (Rotation::R270, vec![".#", ".#", "##"]),
+        ];
+        for (rotation, expected) in cases.iter() {
+            let mut fb = Framebuffer::new();
+            L.draw(&mut fb, Coord::new(0, 0), *rotation);
+            let (w, h) = L.size(*rotation);
+            assert_eq!(rows(&fb, w, h, 0, 0), *expected, "{:?}", rotation);
+        }
+        assert_eq!(Rotation::R270.next(), Rotation::R0);
+    }
+
+    #[test]
+    fn blit_at_any_offset() {
+        let mut fb = Framebuffer::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                fb.set_pixel(x, y, true);
+            }
+        }
+        // Across a page boundary, unlit pixels are copied as well
+        L.draw(&mut fb, Coord::new(1, 7), Rotation::R0);
+        assert_eq!(rows(&fb, 5, 3, 0, 6), vec!["#####", "##..#", ".###."]);
+
+        // Clipped at the edges
+        let mut fb = Framebuffer::new();
+        L.draw(&mut fb, Coord::new(-1, -1), Rotation::R0);
+        assert_eq!(rows(&fb, 3, 2, 0, 0), vec!["##.", "..."]);
+        L.draw(&mut fb, Coord::new(126, 63), Rotation::R0);
+        assert!(fb.pixel(126, 63) && !fb.pixel(127, 63));
+    }
+}
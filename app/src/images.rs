@@ -0,0 +1,5 @@
+//! The images of `app/images`, converted by `build.rs`.
+
+use crate::image::Image;
+
+include!(concat!(env!("OUT_DIR"), "/images.rs"));
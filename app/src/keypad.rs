@@ -0,0 +1,491 @@
+//! Matrix keypads, like the 4x4 membrane ones.
+//!
+//! The keys connect a row line to a column line.  The rows are driven low
+//! one at a time (open drain outputs, so that two keys of the same column
+//! don't short two rows) and the columns, inputs with pull-ups, read low for
+//! the pressed keys of that row.
+//!
+//! Without a diode per key, three keys on the corners of a rectangle also
+//! connect the fourth corner, which then reads as pressed (ghosting).  Such
+//! scans are ambiguous: they are ignored and `Keypad::ghosting` is set until
+//! the keys are released.
+//!
+//! Every key is debounced on its own, and the changes are queued as events.
+
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::Drawing;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use heapless::consts::*;
+use heapless::spsc::Queue;
+use heapless::String;
+
+use crate::widgets::{Area, Label};
+
+pub const MAX_ROWS: usize = 8;
+pub const MAX_COLS: usize = 16;
+
+/// Times the columns are read after driving a row, the last one counts. The
+/// reads before give the lines some time to settle.
+const READS: u32 = 4;
+
+/// Characters of the keys, by row and column.
+pub type Keymap = &'static [&'static [char]];
+
+pub const KEYMAP_4X4: Keymap = &[
+    &['1', '2', '3', 'A'],
+    &['4', '5', '6', 'B'],
+    &['7', '8', '9', 'C'],
+    &['*', '0', '#', 'D'],
+];
+
+pub const KEYMAP_4X3: Keymap = &[
+    &['1', '2', '3'],
+    &['4', '5', '6'],
+    &['7', '8', '9'],
+    &['*', '0', '#'],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Key {
+    pub row: u8,
+    pub col: u8,
+}
+
+impl Key {
+    pub fn char(self, keymap: Keymap) -> Option<char> {
+        keymap
+            .get(self.row as usize)
+            .and_then(|row| row.get(self.col as usize))
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Pressed(Key),
+    Released(Key),
+}
+
+/// The wiring of a keypad.
+pub trait Matrix {
+    fn rows(&self) -> usize;
+    fn cols(&self) -> usize;
+    /// Bit mask of the columns that read as pressed on `row`.
+    fn scan_row(&mut self, row: usize) -> u16;
+}
+
+/// A keypad on GPIO pins.
+pub struct PinMatrix<'a, EO, EI> {
+    rows: &'a mut [&'a mut dyn OutputPin<Error = EO>],
+    cols: &'a [&'a dyn InputPin<Error = EI>],
+}
+
+impl<'a, EO, EI> PinMatrix<'a, EO, EI> {
+    /// Up to `MAX_ROWS` open drain outputs and `MAX_COLS` inputs with
+    /// pull-ups.
+    pub fn new(
+        rows: &'a mut [&'a mut dyn OutputPin<Error = EO>],
+        cols: &'a [&'a dyn InputPin<Error = EI>],
+    ) -> Self {
+        assert!(rows.len() <= MAX_ROWS && cols.len() <= MAX_COLS);
+        for row in rows.iter_mut() {
+            row.set_high().ok();
+        }
+        PinMatrix { rows, cols }
+    }
+}
+
+impl<'a, EO, EI> Matrix for PinMatrix<'a, EO, EI> {
+    fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn cols(&self) -> usize {
+        self.cols.len()
+    }
+
+    fn scan_row(&mut self, row: usize) -> u16 {
+        self.rows[row].set_low().ok();
+        let mut pressed = 0;
+        for _ in 0..READS {
+            pressed = 0;
+            for (col, pin) in self.cols.iter().enumerate() {
+                if pin.is_low().unwrap_or(false) {
+                    pressed |= 1 << col;
+                }
+            }
+        }
+        self.rows[row].set_high().ok();
+        pressed
+    }
+}
+
+pub struct Keypad<M> {
+    matrix: M,
+    /// Time a key has to be stable, in milliseconds
+    debounce: u32,
+    /// Last accepted scan
+    raw: [u16; MAX_ROWS],
+    /// Debounced state
+    pressed: [u16; MAX_ROWS],
+    /// Time of the last raw change of every key
+    since: [[u32; MAX_COLS]; MAX_ROWS],
+    ghosting: bool,
+    events: Queue<Event, U16>,
+}
+
+impl<M: Matrix> Keypad<M> {
+    pub fn new(matrix: M, debounce: u32) -> Self {
+        Keypad {
+            matrix,
+            debounce,
+            raw: [0; MAX_ROWS],
+            pressed: [0; MAX_ROWS],
+            since: [[0; MAX_COLS]; MAX_ROWS],
+            ghosting: false,
+            events: Queue::new(),
+        }
+    }
+
+    /// Scan the matrix at time `now` (in milliseconds) and queue the keys
+    /// that changed. Call it every few milliseconds.
+    pub fn poll(&mut self, now: u32) {
+        let mut scan = [0; MAX_ROWS];
+        for (row, mask) in scan.iter_mut().enumerate().take(self.matrix.rows()) {
+            *mask = self.matrix.scan_row(row);
+        }
+        self.update(&scan, now);
+    }
+
+    fn update(&mut self, scan: &[u16; MAX_ROWS], now: u32) {
+        self.ghosting = is_ambiguous(scan);
+        if self.ghosting {
+            return;
+        }
+        for (row, &scanned) in scan.iter().enumerate().take(self.matrix.rows()) {
+            for col in 0..self.matrix.cols() {
+                let mask = 1 << col;
+                if (scanned ^ self.raw[row]) & mask != 0 {
+                    self.since[row][col] = now;
+                }
+                let stable = now.wrapping_sub(self.since[row][col]) >= self.debounce;
+                if stable && (scanned ^ self.pressed[row]) & mask != 0 {
+                    self.pressed[row] ^= mask;
+                    let key = Key {
+                        row: row as u8,
+                        col: col as u8,
+                    };
+                    let event = if self.pressed[row] & mask != 0 {
+                        Event::Pressed(key)
+                    } else {
+                        Event::Released(key)
+                    };
+                    // Events are dropped when nobody reads them
+                    self.events.enqueue(event).ok();
+                }
+            }
+            self.raw[row] = scanned;
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.dequeue()
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed[key.row as usize] & (1 << key.col) != 0
+    }
+
+    /// The last scan was ignored because more keys than can be told apart
+    /// are pressed.
+    pub fn ghosting(&self) -> bool {
+        self.ghosting
+    }
+
+    pub fn release(self) -> M {
+        self.matrix
+    }
+}
+
+/// Two rows that share two columns could be a ghost key.
+fn is_ambiguous(scan: &[u16]) -> bool {
+    scan.iter().enumerate().any(|(i, a)| {
+        scan[i + 1..]
+            .iter()
+            .any(|b| (a & b).count_ones() >= 2)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    /// Digits shown as '*'
+    Pin,
+    /// A number, with '-' on the 'A' key to change the sign
+    Number,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    Done,
+    Cancelled,
+}
+
+/// Typing digits on a keypad: '*' deletes the last digit (or cancels if there
+/// is none), '#' is done.
+pub struct TextEntry<'a> {
+    prompt: &'a str,
+    kind: EntryKind,
+    max_len: usize,
+    text: String<U16>,
+}
+
+impl<'a> TextEntry<'a> {
+    pub fn new(prompt: &'a str, kind: EntryKind, max_len: usize) -> Self {
+        TextEntry {
+            prompt,
+            kind,
+            max_len: max_len.min(16),
+            text: String::new(),
+        }
+    }
+
+    /// Handle the character of a pressed key.
+    pub fn key(&mut self, c: char) -> Option<Entry> {
+        match c {
+            '0'..='9' if self.text.len() < self.max_len => {
+                self.text.push(c).ok();
+                None
+            }
+            'A' if self.kind == EntryKind::Number => {
+                let mut text: String<U16> = String::new();
+                if self.text.starts_with('-') {
+                    text.push_str(&self.text[1..]).ok();
+                } else if self.text.len() < self.max_len {
+                    text.push('-').ok();
+                    text.push_str(&self.text).ok();
+                } else {
+                    return None;
+                }
+                self.text = text;
+                None
+            }
+            '*' => {
+                if self.text.pop().is_none() {
+                    Some(Entry::Cancelled)
+                } else {
+                    None
+                }
+            }
+            '#' => Some(Entry::Done),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The number typed, if it is one.
+    pub fn value(&self) -> Option<i32> {
+        self.text.parse().ok()
+    }
+
+    pub fn clear(&mut self) {
+        self.text = String::new();
+    }
+
+    /// The prompt on the first line of the area, the text with a cursor on
+    /// the second.
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D, area: Area) {
+        let line = area.height / 2;
+        Label::new(self.prompt, Area::new(area.x, area.y, area.width, line)).draw(disp);
+        let mut shown: String<U32> = String::new();
+        for c in self.text.chars() {
+            let c = if self.kind == EntryKind::Pin { '*' } else { c };
+            shown.push(c).ok();
+        }
+        if self.text.len() < self.max_len {
+            shown.push('_').ok();
+        }
+        let value = Area::new(area.x, area.y + line as i32, area.width, area.height - line);
+        Label::new(&shown, value).draw(disp);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Pins of a keypad with some keys held: the column pins read low when a
+    /// held key connects them to the row that is driven low.
+    struct Board {
+        driven: Cell<Option<usize>>,
+        held: Cell<[u16; 4]>,
+    }
+
+    struct RowPin<'a>(&'a Board, usize);
+    struct ColPin<'a>(&'a Board, usize);
+
+    impl<'a> OutputPin for RowPin<'a> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.0.driven.set(Some(self.1));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            if self.0.driven.get() == Some(self.1) {
+                self.0.driven.set(None);
+            }
+            Ok(())
+        }
+    }
+
+    impl<'a> InputPin for ColPin<'a> {
+        type Error = ();
+
+        fn is_high(&self) -> Result<bool, ()> {
+            self.is_low().map(|low| !low)
+        }
+
+        fn is_low(&self) -> Result<bool, ()> {
+            Ok(match self.0.driven.get() {
+                Some(row) => self.0.held.get()[row] & (1 << self.1) != 0,
+                None => false,
+            })
+        }
+    }
+
+    impl Board {
+        fn new() -> Self {
+            Board {
+                driven: Cell::new(None),
+                held: Cell::new([0; 4]),
+            }
+        }
+
+        fn hold(&self, keys: &[(usize, usize)]) {
+            let mut held = [0; 4];
+            for &(row, col) in keys {
+                held[row] |= 1 << col;
+            }
+            self.held.set(held);
+        }
+    }
+
+    fn events<M: Matrix>(keypad: &mut Keypad<M>) -> std::vec::Vec<Event> {
+        std::iter::from_fn(|| keypad.next_event()).collect()
+    }
+
+    fn key(row: u8, col: u8) -> Key {
+        Key { row, col }
+    }
+
+    /// Run `test` with a 4x4 keypad on mock pins.
+    fn with_keypad(test: impl FnOnce(&Board, &mut Keypad<PinMatrix<(), ()>>)) {
+        let board = Board::new();
+        let (mut r0, mut r1, mut r2, mut r3) = (
+            RowPin(&board, 0),
+            RowPin(&board, 1),
+            RowPin(&board, 2),
+            RowPin(&board, 3),
+        );
+        let (c0, c1, c2, c3) = (
+            ColPin(&board, 0),
+            ColPin(&board, 1),
+            ColPin(&board, 2),
+            ColPin(&board, 3),
+        );
+        let mut rows: [&mut dyn OutputPin<Error = ()>; 4] = [&mut r0, &mut r1, &mut r2, &mut r3];
+        let cols: [&dyn InputPin<Error = ()>; 4] = [&c0, &c1, &c2, &c3];
+        let mut keypad = Keypad::new(PinMatrix::new(&mut rows, &cols), 10);
+        test(&board, &mut keypad);
+    }
+
+    #[test]
+    fn press_and_release() {
+        with_keypad(|board, keypad| {
+            board.hold(&[(1, 2)]);
+            keypad.poll(0);
+            keypad.poll(5);
+            assert_eq!(events(keypad), vec![]);
+            keypad.poll(10);
+            assert_eq!(events(keypad), vec![Event::Pressed(key(1, 2))]);
+            assert_eq!(key(1, 2).char(KEYMAP_4X4), Some('6'));
+            assert!(keypad.is_pressed(key(1, 2)));
+
+            board.hold(&[]);
+            keypad.poll(20);
+            keypad.poll(30);
+            assert_eq!(events(keypad), vec![Event::Released(key(1, 2))]);
+        });
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        with_keypad(|board, keypad| {
+            for t in 0..10 {
+                board.hold(if t % 2 == 0 { &[(0, 0)] } else { &[] });
+                keypad.poll(t * 3);
+            }
+            board.hold(&[]);
+            keypad.poll(100);
+            assert_eq!(events(keypad), vec![]);
+        });
+    }
+
+    #[test]
+    fn ghost_keys() {
+        with_keypad(|board, keypad| {
+            // Two keys in a column and two in a row are fine
+            board.hold(&[(0, 0), (2, 0), (2, 3)]);
+            keypad.poll(0);
+            keypad.poll(10);
+            assert!(!keypad.ghosting());
+            assert_eq!(events(keypad).len(), 3);
+
+            // A fourth key that closes the rectangle can't be told apart from
+            // a ghost, so nothing changes
+            board.hold(&[(0, 0), (2, 0), (2, 3), (0, 3)]);
+            keypad.poll(20);
+            keypad.poll(30);
+            assert!(keypad.ghosting());
+            assert_eq!(events(keypad), vec![]);
+            assert!(!keypad.is_pressed(key(0, 3)));
+
+            board.hold(&[(2, 3)]);
+            keypad.poll(40);
+            keypad.poll(50);
+            assert!(!keypad.ghosting());
+            assert_eq!(
+                events(keypad),
+                vec![Event::Released(key(0, 0)), Event::Released(key(2, 0))]
+            );
+        });
+    }
+
+    #[test]
+    fn pin_entry() {
+        let mut entry = TextEntry::new("PIN", EntryKind::Pin, 4);
+        for c in "12345*9".chars() {
+            assert_eq!(entry.key(c), None);
+        }
+        assert_eq!(entry.text(), "1239");
+        assert_eq!(entry.key('#'), Some(Entry::Done));
+        entry.clear();
+        assert_eq!(entry.key('*'), Some(Entry::Cancelled));
+    }
+
+    #[test]
+    fn number_entry() {
+        let mut entry = TextEntry::new("Tempo", EntryKind::Number, 4);
+        for c in "12A".chars() {
+            entry.key(c);
+        }
+        assert_eq!(entry.value(), Some(-12));
+        entry.key('A');
+        assert_eq!(entry.value(), Some(12));
+    }
+}
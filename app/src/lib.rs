@@ -0,0 +1,46 @@
+//! Code shared by the examples of this crate.
+//!
+//! Most of the modules only implement logic and don't touch the hardware, so
+//! they can be tested on the host (see `examples/test_on_host.rs` for the
+//! idea behind this):
+//!
+//! ```text
+//! cargo test --lib --target x86_64-unknown-linux-gnu
+//! ```
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod adc;
+pub mod button;
+pub mod calibration;
+pub mod display;
+pub mod eeprom;
+pub mod encoder;
+pub mod exti;
+pub mod filter;
+pub mod flash;
+pub mod frame;
+pub mod framebuffer;
+pub mod i2c;
+pub mod image;
+pub mod images;
+pub mod keypad;
+pub mod mapping;
+pub mod menu;
+#[cfg(test)]
+mod mock;
+pub mod motor;
+pub mod oled;
+pub mod qei;
+pub mod sampler;
+pub mod scan;
+pub mod scope;
+pub mod screens;
+pub mod screensaver;
+pub mod sequence;
+pub mod servo;
+pub mod slew;
+pub mod stream;
+pub mod time;
+pub mod widgets;
+pub mod xmodem;
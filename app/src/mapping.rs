@@ -0,0 +1,334 @@
+//! Mapping of an integer range into another, like a potentiometer reading
+//! into a PWM duty.
+//!
+//! The input is clamped to its range, so the output never leaves its own.
+//! A dead zone at both ends of the input makes them reachable despite the
+//! noise and a potentiometer that doesn't quite reach the rails.  In
+//! between, the position in the input range goes through a `Curve`:
+//!
+//! ```text
+//! Linear   the same position in the output range
+//! Log(b)   the taper of "logarithmic" (audio) potentiometers: the output
+//!          is multiplied by the same ratio every step, from 1/2^b of the
+//!          range to all of it
+//! Gamma(g) the position to the power g/10, like the gamma correction of
+//!          displays: 22 makes an LED look linear
+//! ```
+//!
+//! For example potentiometer readings from 2000 to 4000 into a duty:
+//!
+//! ```ignore
+//! let mapping = Mapping::new(2000, 4000, 0, max)
+//!     .with_dead_zone(16)
+//!     .with_curve(Curve::Gamma(22));
+//! pwm.set_duty(mapping.map(u32::from(reading)) as u16);
+//! ```
+//!
+//! Everything is integer: the position is a 16-bit fraction and the curves
+//! use a fixed-point `log2` and `exp2`.
+
+/// 1.0 for positions.
+const ONE: u32 = 1 << 16;
+
+/// 2^(1/2), 2^(1/4)... 2^(1/65536), with 31 bits of fraction.
+const EXP2_ROOTS: [u32; 16] = [
+    0xb504_f334,
+    0x9837_f052,
+    0x8b95_c1e4,
+    0x85aa_c368,
+    0x82cd_8699,
+    0x8164_d1f4,
+    0x80b1_ed50,
+    0x8058_d7d3,
+    0x802c_6437,
+    0x8016_302f,
+    0x800b_179d,
+    0x8005_8baf,
+    0x8002_c5d0,
+    0x8001_62e6,
+    0x8000_b173,
+    0x8000_58b9,
+];
+
+/// Largest `Curve::Log` range, in bits.
+pub const MAX_LOG_BITS: u8 = 12;
+
+/// log2 of `x` with 16 bits of fraction, `x` > 0 having 16 bits of fraction
+/// too.
+fn log2(x: u32) -> i32 {
+    let msb = 31 - x.leading_zeros() as i32;
+    // x / 2^msb, 1 <= y < 2, 31 bits of fraction
+    let mut y = u64::from(x) << (31 - msb);
+    let mut fraction = 0;
+    for bit in (0..16).rev() {
+        y = (y * y) >> 31;
+        if y >= 1 << 32 {
+            y >>= 1;
+            fraction |= 1 << bit;
+        }
+    }
+    ((msb - 16) << 16) + fraction
+}
+
+/// 2^x for `x` <= 0, both with 16 bits of fraction.
+fn exp2(x: i32) -> u32 {
+    // x = -shift + fraction, 0 <= fraction < 1
+    let shift = (-(x >> 16)) as u32;
+    let fraction = (x & 0xffff) as u32;
+    let mut y: u64 = 1 << 31;
+    for (i, root) in EXP2_ROOTS.iter().enumerate() {
+        if fraction & (1 << (15 - i)) != 0 {
+            y = (y * u64::from(*root)) >> 31;
+        }
+    }
+    // 31 to 16 bits of fraction, divided by 2^shift
+    let shift = 15 + shift;
+    if shift >= 48 {
+        return 0;
+    }
+    ((y + (1 << (shift - 1))) >> shift) as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// Range of the output in bits, 1 to `MAX_LOG_BITS`
+    Log(u8),
+    /// Exponent in tenths, more than 10 is slower at the start
+    Gamma(u8),
+}
+
+impl Curve {
+    /// The output position for `position`, both from 0 to `ONE`.  0 and
+    /// `ONE` stay where they are.
+    fn apply(self, position: u32) -> u32 {
+        if position == 0 || position >= ONE {
+            return position.min(ONE);
+        }
+        match self {
+            Curve::Linear => position,
+            Curve::Log(bits) => {
+                // (2^(b p) - 1) / (2^b - 1), computed as
+                // (2^(b (p - 1)) - 2^-b) / (1 - 2^-b) to stay below 1
+                let bits = i32::from(bits.clamp(1, MAX_LOG_BITS));
+                let floor = ONE >> bits;
+                let exponent = bits * (position as i32 - ONE as i32);
+                let above = exp2(exponent).saturating_sub(floor);
+                ((u64::from(above) * u64::from(ONE) + u64::from(ONE - floor) / 2)
+                    / u64::from(ONE - floor)) as u32
+            }
+            Curve::Gamma(tenths) => {
+                // p^g = 2^(g log2(p)), log2(p) < 0
+                let exponent = i64::from(log2(position)) * i64::from(tenths) / 10;
+                exp2(exponent.max(-(48 << 16)) as i32)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    from: (u32, u32),
+    to: (u32, u32),
+    inverted: bool,
+    dead_zone: u32,
+    curve: Curve,
+}
+
+impl Mapping {
+    /// Linear from `in_min`..`in_max` to `out_min`..`out_max`.  An output
+    /// range the other way around (`out_min` > `out_max`) is fine.
+    pub fn new(in_min: u32, in_max: u32, out_min: u32, out_max: u32) -> Self {
+        Mapping {
+            from: (in_min.min(in_max), in_min.max(in_max)),
+            to: (out_min, out_max),
+            inverted: in_min > in_max,
+            dead_zone: 0,
+            curve: Curve::Linear,
+        }
+    }
+
+    /// The start of the input range maps to the end of the output range.
+    pub fn inverted(mut self) -> Self {
+        self.inverted = !self.inverted;
+        self
+    }
+
+    /// Inputs within `counts` of the ends of the input range map to the
+    /// ends of the output range.
+    pub fn with_dead_zone(mut self, counts: u32) -> Self {
+        // Leave at least a count in between
+        let span = self.from.1 - self.from.0;
+        self.dead_zone = counts.min(span.saturating_sub(1) / 2);
+        self
+    }
+
+    pub fn with_curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Position of `input` in the input range, from 0 to `ONE`.
+    fn position(&self, input: u32) -> u32 {
+        let low = self.from.0 + self.dead_zone;
+        let high = self.from.1 - self.dead_zone;
+        let position = if input <= low {
+            0
+        } else if input >= high {
+            ONE
+        } else {
+            (u64::from(input - low) * u64::from(ONE) / u64::from(high - low)) as u32
+        };
+        if self.inverted {
+            ONE - position
+        } else {
+            position
+        }
+    }
+
+    pub fn map(&self, input: u32) -> u32 {
+        let position = i64::from(self.curve.apply(self.position(input)));
+        let (out_min, out_max) = (i64::from(self.to.0), i64::from(self.to.1));
+        // Rounded to the nearest, either way
+        let scaled = position * (out_max - out_min);
+        let half = i64::from(ONE / 2) * scaled.signum();
+        (out_min + (scaled + half) / i64::from(ONE)) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CURVES: [Curve; 8] = [
+        Curve::Linear,
+        Curve::Log(1),
+        Curve::Log(8),
+        Curve::Log(12),
+        Curve::Gamma(5),
+        Curve::Gamma(10),
+        Curve::Gamma(22),
+        Curve::Gamma(30),
+    ];
+
+    #[test]
+    fn fixed_point() {
+        assert_eq!(log2(ONE), 0);
+        assert_eq!(log2(ONE / 2), -(ONE as i32));
+        assert_eq!(log2(1), -16 << 16);
+        // log2(0.75) = -0.415
+        assert!(
+            (log2(3 * ONE / 4) + 27_200).abs() < 4,
+            "{}",
+            log2(3 * ONE / 4)
+        );
+
+        assert_eq!(exp2(0), ONE);
+        assert_eq!(exp2(-(1 << 16)), ONE / 2);
+        assert_eq!(exp2(-(16 << 16)), 1);
+        assert_eq!(exp2(-(40 << 16)), 0);
+        // 2^-0.5
+        assert_eq!(exp2(-(1 << 15)), 46341);
+
+        for &x in &[1, 100, 30_000, 50_000, 65_535] {
+            let y = exp2(log2(x)) as i32;
+            assert!((y - x as i32).abs() <= 1 + x as i32 / 20_000, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn curves() {
+        let half = |curve: Curve| Mapping::new(0, 1000, 0, 10_000).with_curve(curve).map(500);
+        assert_eq!(half(Curve::Linear), 5000);
+        // 0.5^2.2 = 0.2176
+        assert_eq!(half(Curve::Gamma(22)), 2176);
+        assert_eq!(half(Curve::Gamma(5)), 7071);
+        // (2^4 - 1) / (2^8 - 1) = 0.0588
+        assert_eq!(half(Curve::Log(8)), 588);
+        // Every step multiplies the output by the same ratio
+        let log = Mapping::new(0, 8, 0, 255).with_curve(Curve::Log(8));
+        let outputs: std::vec::Vec<u32> = (0..=8).map(|i| log.map(i)).collect();
+        assert_eq!(outputs, [0, 1, 3, 7, 15, 31, 63, 127, 255]);
+    }
+
+    #[test]
+    fn monotonic() {
+        for &curve in CURVES.iter() {
+            for &inverted in &[false, true] {
+                let mut mapping = Mapping::new(0, 4095, 0, 65535).with_curve(curve);
+                if inverted {
+                    mapping = mapping.inverted();
+                }
+                let outputs: std::vec::Vec<u32> = (0..=4095).map(|i| mapping.map(i)).collect();
+                for pair in outputs.windows(2) {
+                    if inverted {
+                        assert!(pair[0] >= pair[1], "{:?} {:?}", curve, pair);
+                    } else {
+                        assert!(pair[0] <= pair[1], "{:?} {:?}", curve, pair);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        for &curve in CURVES.iter() {
+            let mapping = Mapping::new(2000, 4000, 0, 7200).with_curve(curve);
+            assert_eq!(mapping.map(2000), 0, "{:?}", curve);
+            assert_eq!(mapping.map(4000), 7200, "{:?}", curve);
+            // Clamped
+            assert_eq!(mapping.map(0), 0, "{:?}", curve);
+            assert_eq!(mapping.map(4095), 7200, "{:?}", curve);
+
+            let inverted = mapping.inverted();
+            assert_eq!(inverted.map(1000), 7200, "{:?}", curve);
+            assert_eq!(inverted.map(4000), 0, "{:?}", curve);
+
+            // An output range the other way around
+            let reversed = Mapping::new(0, 100, 255, 0).with_curve(curve);
+            assert_eq!(reversed.map(0), 255, "{:?}", curve);
+            assert_eq!(reversed.map(100), 0, "{:?}", curve);
+            assert!(
+                reversed.map(50) < 255 && reversed.map(50) > 0,
+                "{:?}",
+                curve
+            );
+        }
+        // An input range the other way around inverts
+        assert_eq!(Mapping::new(100, 0, 0, 10).map(0), 10);
+    }
+
+    #[test]
+    fn dead_zone() {
+        let mapping = Mapping::new(0, 4095, 0, 1000).with_dead_zone(50);
+        assert_eq!(mapping.map(0), 0);
+        assert_eq!(mapping.map(50), 0);
+        assert_eq!(mapping.map(51), 0);
+        assert!(mapping.map(60) > 0);
+        assert_eq!(mapping.map(4045), 1000);
+        assert!(mapping.map(4035) < 1000);
+        assert_eq!(mapping.map(2048), 500);
+
+        // At most half of the range
+        let mapping = Mapping::new(0, 10, 0, 100).with_dead_zone(1000);
+        assert_eq!(mapping.map(4), 0);
+        assert_eq!(mapping.map(6), 100);
+    }
+
+    /// potentiometer2 used `(data - 2000) * max / 2000` in floating point:
+    /// negative below 2000 and above `max` over 4000.
+    #[test]
+    fn potentiometer_duty() {
+        let max = 7200;
+        let mapping = Mapping::new(2000, 4000, 0, max);
+        for reading in 0..=4095 {
+            let duty = mapping.map(reading);
+            assert!(duty <= max);
+            if (2000..=4000).contains(&reading) {
+                let exact = (reading - 2000) * max / 2000;
+                assert!((duty as i32 - exact as i32).abs() <= 1, "{}", reading);
+            }
+        }
+    }
+}
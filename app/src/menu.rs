@@ -0,0 +1,620 @@
+//! Hierarchical menu driven by buttons.
+//!
+//! The menu is a static tree of `Item`s.  The values edited by the menu
+//! (numbers and on/off toggles) are not stored in the tree but read and
+//! written through the `Values` trait by their id, so they can live wherever
+//! the application keeps its settings.
+//!
+//! Navigation uses four inputs, which can come from three buttons (up, down
+//! and select, with a long press on select to go back) or from a single one
+//! (see `Input::from_single_button`):
+//!
+//! - `Next` / `Prev` move the selection, or change the number being edited.
+//! - `Select` enters a submenu, flips a toggle, starts and confirms the
+//!   editing of a number, or triggers an action.
+//! - `Back` leaves a submenu, or cancels the editing of a number.
+//!
+//! The same values can be changed with text commands, e.g. from a serial
+//! port, see `command`.
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::prelude::*;
+use heapless::consts::*;
+use heapless::{String, Vec};
+
+use crate::widgets::{icons, Align, Area, Grid, Icon, Label};
+
+/// Items shown at once, below the title.
+pub const VISIBLE_ITEMS: usize = 4;
+
+pub enum Item<'a> {
+    Submenu {
+        label: &'a str,
+        items: &'a [Item<'a>],
+    },
+    Number {
+        label: &'a str,
+        id: u8,
+        min: i32,
+        max: i32,
+        step: i32,
+    },
+    Toggle {
+        label: &'a str,
+        id: u8,
+    },
+    Action {
+        label: &'a str,
+        id: u8,
+    },
+}
+
+impl<'a> Item<'a> {
+    pub fn label(&self) -> &'a str {
+        match self {
+            Item::Submenu { label, .. }
+            | Item::Number { label, .. }
+            | Item::Toggle { label, .. }
+            | Item::Action { label, .. } => label,
+        }
+    }
+}
+
+/// Storage of the values edited by the menu. Toggles are 0 or 1.
+pub trait Values {
+    fn get(&self, id: u8) -> i32;
+    fn set(&mut self, id: u8, value: i32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Next,
+    Prev,
+    Select,
+    Back,
+}
+
+/// Presses of a single button.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Press {
+    Short,
+    Long,
+    Double,
+}
+
+impl Input {
+    /// With a single button a short press moves to the next item (numbers
+    /// wrap around when they reach their maximum), a long press selects and a
+    /// double press goes back.
+    pub fn from_single_button(press: Press) -> Input {
+        match press {
+            Press::Short => Input::Next,
+            Press::Long => Input::Select,
+            Press::Double => Input::Back,
+        }
+    }
+}
+
+/// What the application has to react to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// An action item was selected.
+    Action(u8),
+    /// A value was changed (and already stored in `Values`).
+    Changed(u8, i32),
+    /// `Back` was pressed in the top level menu.
+    Exit,
+}
+
+/// Why a command was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandError {
+    /// No item has that label
+    Unknown,
+    /// Missing or invalid value, or a number out of range
+    Value,
+}
+
+/// Find the value or action item whose label starts the line, in the
+/// submenus too and ignoring case.  The longest label wins.
+fn find<'a>(items: &'a [Item<'a>], line: &str) -> Option<&'a Item<'a>> {
+    let mut found: Option<&'a Item<'a>> = None;
+    for item in items {
+        let candidate = match item {
+            Item::Submenu { items, .. } => find(items, line),
+            _ => {
+                let label = item.label();
+                let rest = line
+                    .get(label.len()..)
+                    .filter(|_| line[..label.len()].eq_ignore_ascii_case(label));
+                match rest {
+                    Some(rest) if rest.is_empty() || rest.starts_with(' ') => Some(item),
+                    _ => None,
+                }
+            }
+        };
+        found = match (found, candidate) {
+            (Some(f), Some(c)) if c.label().len() > f.label().len() => Some(c),
+            (None, candidate) => candidate,
+            (found, _) => found,
+        };
+    }
+    found
+}
+
+/// Run a text command on the items of a menu: the label of an item, then
+/// for numbers their value and for toggles "on" or "off" ("1" or "0").
+///
+/// ```text
+/// contrast 128
+/// invert on
+/// reset counter
+/// ```
+///
+/// The events are the ones of `Menu::handle` for the same change.
+pub fn command<V: Values>(line: &str, items: &[Item], values: &mut V) -> Result<Event, CommandError> {
+    let line = line.trim();
+    let item = find(items, line).ok_or(CommandError::Unknown)?;
+    let arg = line[item.label().len()..].trim();
+    let (id, value) = match *item {
+        Item::Number { id, min, max, .. } => match arg.parse::<i32>() {
+            Ok(value) if value >= min && value <= max => (id, value),
+            _ => return Err(CommandError::Value),
+        },
+        Item::Toggle { id, .. } => match arg {
+            "on" | "1" => (id, 1),
+            "off" | "0" => (id, 0),
+            _ => return Err(CommandError::Value),
+        },
+        Item::Action { id, .. } if arg.is_empty() => return Ok(Event::Action(id)),
+        _ => return Err(CommandError::Value),
+    };
+    values.set(id, value);
+    Ok(Event::Changed(id, value))
+}
+
+/// The number or toggle item of `id`, in the submenus too.
+fn value_item<'a>(items: &'a [Item<'a>], id: u8) -> Option<&'a Item<'a>> {
+    items.iter().find_map(|item| match *item {
+        Item::Submenu { items, .. } => value_item(items, id),
+        Item::Number { id: i, .. } | Item::Toggle { id: i, .. } if i == id => Some(item),
+        _ => None,
+    })
+}
+
+/// Bring a value of `id` that didn't come from the menu, like one read back
+/// from flash, into the range of its item: min to max for numbers, 0 or 1
+/// for toggles.  Values of ids that aren't in the menu are left alone.
+pub fn clamp(items: &[Item], id: u8, value: i32) -> i32 {
+    match value_item(items, id) {
+        Some(&Item::Number { min, max, .. }) => value.max(min).min(max),
+        Some(&Item::Toggle { .. }) => (value != 0) as i32,
+        _ => value,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Level {
+    selected: usize,
+    /// First visible item
+    scroll: usize,
+}
+
+pub struct Menu<'a> {
+    title: &'a str,
+    root: &'a [Item<'a>],
+    /// One level per open submenu, the last one is the current list.
+    levels: Vec<Level, U8>,
+    /// Value of the number being edited
+    editing: Option<i32>,
+}
+
+impl<'a> Menu<'a> {
+    pub fn new(title: &'a str, root: &'a [Item<'a>]) -> Self {
+        let mut levels = Vec::new();
+        levels
+            .push(Level {
+                selected: 0,
+                scroll: 0,
+            })
+            .ok();
+        Menu {
+            title,
+            root,
+            levels,
+            editing: None,
+        }
+    }
+
+    /// Title and items of the current list.
+    fn current(&self) -> (&'a str, &'a [Item<'a>]) {
+        let mut title = self.title;
+        let mut items = self.root;
+        for level in self.levels.iter().take(self.levels.len() - 1) {
+            if let Item::Submenu {
+                label,
+                items: sub,
+            } = &items[level.selected]
+            {
+                title = label;
+                items = sub;
+            }
+        }
+        (title, items)
+    }
+
+    fn level(&mut self) -> &mut Level {
+        let last = self.levels.len() - 1;
+        &mut self.levels[last]
+    }
+
+    /// The selected item.
+    pub fn selected(&self) -> &'a Item<'a> {
+        let (_, items) = self.current();
+        &items[self.levels[self.levels.len() - 1].selected]
+    }
+
+    /// Depth of the current list, 0 for the top level.
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editing.is_some()
+    }
+
+    pub fn handle<V: Values>(&mut self, input: Input, values: &mut V) -> Option<Event> {
+        if let Some(value) = self.editing {
+            return self.edit(input, value, values);
+        }
+        let (_, items) = self.current();
+        // Nothing to move to or select, but an empty menu can still be left
+        if items.is_empty() && input != Input::Back {
+            return None;
+        }
+        match input {
+            Input::Next => {
+                let level = self.level();
+                level.selected = (level.selected + 1) % items.len();
+                self.scroll();
+                None
+            }
+            Input::Prev => {
+                let level = self.level();
+                level.selected = (level.selected + items.len() - 1) % items.len();
+                self.scroll();
+                None
+            }
+            Input::Back => {
+                if self.levels.len() > 1 {
+                    self.levels.pop();
+                    None
+                } else {
+                    Some(Event::Exit)
+                }
+            }
+            Input::Select => match self.selected() {
+                Item::Submenu { items, .. } if !items.is_empty() => {
+                    self.levels
+                        .push(Level {
+                            selected: 0,
+                            scroll: 0,
+                        })
+                        .ok();
+                    None
+                }
+                Item::Submenu { .. } => None,
+                Item::Number { id, .. } => {
+                    self.editing = Some(values.get(*id));
+                    None
+                }
+                Item::Toggle { id, .. } => {
+                    let value = if values.get(*id) != 0 { 0 } else { 1 };
+                    values.set(*id, value);
+                    Some(Event::Changed(*id, value))
+                }
+                Item::Action { id, .. } => Some(Event::Action(*id)),
+            },
+        }
+    }
+
+    fn edit<V: Values>(&mut self, input: Input, value: i32, values: &mut V) -> Option<Event> {
+        let (id, min, max, step) = match self.selected() {
+            Item::Number {
+                id, min, max, step, ..
+            } => (*id, *min, *max, *step),
+            _ => {
+                self.editing = None;
+                return None;
+            }
+        };
+        match input {
+            Input::Next => {
+                self.editing = Some(if value >= max {
+                    min
+                } else {
+                    value.saturating_add(step).min(max)
+                });
+                None
+            }
+            Input::Prev => {
+                self.editing = Some(if value <= min {
+                    max
+                } else {
+                    value.saturating_sub(step).max(min)
+                });
+                None
+            }
+            Input::Select => {
+                self.editing = None;
+                values.set(id, value);
+                Some(Event::Changed(id, value))
+            }
+            Input::Back => {
+                self.editing = None;
+                None
+            }
+        }
+    }
+
+    /// Keep the selected item visible.
+    fn scroll(&mut self) {
+        let level = self.level();
+        if level.selected < level.scroll {
+            level.scroll = level.selected;
+        } else if level.selected >= level.scroll + VISIBLE_ITEMS {
+            level.scroll = level.selected + 1 - VISIBLE_ITEMS;
+        }
+    }
+
+    /// Draw the menu on the whole screen.
+    pub fn draw<D: Drawing<PixelColorU8>, V: Values>(&self, disp: &mut D, values: &V) {
+        let grid = Grid::new(Area::screen(), 1, VISIBLE_ITEMS as u32 + 1);
+        let (title, items) = self.current();
+        let level = self.levels[self.levels.len() - 1];
+
+        let header = grid.cell(0, 0);
+        Label::new(title, Area::new(header.x, header.y, header.width - 8, header.height))
+            .draw(disp);
+        let arrows = Area::new(header.x + header.width as i32 - 8, header.y, 8, header.height);
+        arrows.fill(disp, 0);
+        if level.scroll > 0 {
+            Icon::new(&icons::ARROW_UP, Coord::new(arrows.x, arrows.y)).draw(disp);
+        } else if level.scroll + VISIBLE_ITEMS < items.len() {
+            Icon::new(&icons::ARROW_DOWN, Coord::new(arrows.x, arrows.y)).draw(disp);
+        }
+
+        for row in 0..VISIBLE_ITEMS {
+            let area = grid.cell(0, row as u32 + 1);
+            let index = level.scroll + row;
+            let item = match items.get(index) {
+                Some(item) => item,
+                None => {
+                    area.fill(disp, 0);
+                    continue;
+                }
+            };
+            let selected = index == level.selected;
+            let mut value: String<U16> = String::new();
+            match item {
+                Item::Submenu { .. } => {
+                    value.push('>').ok();
+                }
+                Item::Number { id, .. } => {
+                    let v = match self.editing {
+                        Some(v) if selected => v,
+                        _ => values.get(*id),
+                    };
+                    write!(value, "{}", v).ok();
+                }
+                Item::Toggle { id, .. } => {
+                    let text = if values.get(*id) != 0 { "on" } else { "off" };
+                    value.push_str(text).ok();
+                }
+                Item::Action { .. } => {}
+            }
+            let value_width = (value.len() as u32 + 1) * 6;
+            let label_area = Area::new(area.x, area.y, area.width - value_width, area.height);
+            let value_area = Area::new(
+                area.x + label_area.width as i32,
+                area.y,
+                value_width,
+                area.height,
+            );
+            // While editing, only the value is highlighted
+            Label::new(item.label(), label_area)
+                .with_inverted(selected && !self.is_editing())
+                .draw(disp);
+            Label::new(value.as_str(), value_area)
+                .with_align(Align::Right)
+                .with_inverted(selected)
+                .draw(disp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::snapshot::assert_snapshot;
+    use crate::framebuffer::Framebuffer;
+
+    const BRIGHTNESS: u8 = 0;
+    const INVERT: u8 = 1;
+    const FREQUENCY: u8 = 2;
+    const RESET: u8 = 3;
+
+    const PWM: &[Item] = &[Item::Number {
+        label: "Frequency",
+        id: FREQUENCY,
+        min: 100,
+        max: 1000,
+        step: 100,
+    }];
+
+    const ROOT: &[Item] = &[
+        Item::Number {
+            label: "Brightness",
+            id: BRIGHTNESS,
+            min: 0,
+            max: 3,
+            step: 1,
+        },
+        Item::Toggle {
+            label: "Invert",
+            id: INVERT,
+        },
+        Item::Submenu {
+            label: "PWM",
+            items: PWM,
+        },
+        Item::Action {
+            label: "Reset",
+            id: RESET,
+        },
+        Item::Action {
+            label: "About",
+            id: 4,
+        },
+    ];
+
+    struct Settings([i32; 4]);
+
+    impl Values for Settings {
+        fn get(&self, id: u8) -> i32 {
+            self.0[id as usize]
+        }
+
+        fn set(&mut self, id: u8, value: i32) {
+            self.0[id as usize] = value;
+        }
+    }
+
+    fn run(menu: &mut Menu, settings: &mut Settings, inputs: &[Input]) -> std::vec::Vec<Event> {
+        inputs
+            .iter()
+            .filter_map(|&input| menu.handle(input, settings))
+            .collect()
+    }
+
+    #[test]
+    fn navigation_wraps() {
+        let mut settings = Settings([0, 0, 500, 0]);
+        let mut menu = Menu::new("Settings", ROOT);
+        run(&mut menu, &mut settings, &[Input::Prev]);
+        assert_eq!(menu.selected().label(), "About");
+        run(&mut menu, &mut settings, &[Input::Next, Input::Next]);
+        assert_eq!(menu.selected().label(), "Invert");
+    }
+
+    #[test]
+    fn empty_menu() {
+        use Input::*;
+        let mut settings = Settings([0, 0, 500, 0]);
+        let mut menu = Menu::new("Empty", &[]);
+        let events = run(&mut menu, &mut settings, &[Next, Prev, Select, Back]);
+        assert_eq!(events, [Event::Exit]);
+    }
+
+    #[test]
+    fn toggle_and_action() {
+        let mut settings = Settings([0, 0, 500, 0]);
+        let mut menu = Menu::new("Settings", ROOT);
+        let events = run(
+            &mut menu,
+            &mut settings,
+            &[Input::Next, Input::Select, Input::Next, Input::Next, Input::Select],
+        );
+        assert_eq!(events, vec![Event::Changed(INVERT, 1), Event::Action(RESET)]);
+        assert_eq!(settings.0[INVERT as usize], 1);
+    }
+
+    #[test]
+    fn commands() {
+        let mut settings = Settings([0, 0, 500, 0]);
+        let mut run = |line| command(line, ROOT, &mut settings);
+        assert_eq!(run("invert on"), Ok(Event::Changed(INVERT, 1)));
+        assert_eq!(run(" Brightness 3\r\n"), Ok(Event::Changed(BRIGHTNESS, 3)));
+        // In a submenu
+        assert_eq!(run("frequency 200"), Ok(Event::Changed(FREQUENCY, 200)));
+        assert_eq!(run("reset"), Ok(Event::Action(RESET)));
+        assert_eq!(settings.0, [3, 1, 200, 0]);
+    }
+
+    #[test]
+    fn clamped_values() {
+        assert_eq!(clamp(ROOT, BRIGHTNESS, -1), 0);
+        assert_eq!(clamp(ROOT, BRIGHTNESS, 2), 2);
+        assert_eq!(clamp(ROOT, INVERT, 7), 1);
+        assert_eq!(clamp(ROOT, FREQUENCY, 0), 100);
+        assert_eq!(clamp(ROOT, FREQUENCY, 5000), 1000);
+        assert_eq!(clamp(ROOT, RESET, 5000), 5000);
+    }
+
+    #[test]
+    fn bad_commands() {
+        let mut settings = Settings([0, 0, 500, 0]);
+        let mut run = |line| command(line, ROOT, &mut settings);
+        assert_eq!(run("brightness 4"), Err(CommandError::Value));
+        assert_eq!(run("brightness"), Err(CommandError::Value));
+        assert_eq!(run("invert maybe"), Err(CommandError::Value));
+        assert_eq!(run("reset now"), Err(CommandError::Value));
+        assert_eq!(run("pwm"), Err(CommandError::Unknown));
+        assert_eq!(run("inverted on"), Err(CommandError::Unknown));
+        assert_eq!(run("é"), Err(CommandError::Unknown));
+        assert_eq!(settings.0, [0, 0, 500, 0]);
+    }
+
+    #[test]
+    fn submenu_and_number_editor() {
+        use Input::*;
+        let mut settings = Settings([0, 0, 500, 0]);
+        let mut menu = Menu::new("Settings", ROOT);
+        let events = run(
+            &mut menu,
+            &mut settings,
+            &[Next, Next, Select, Select, Next, Next, Select],
+        );
+        assert_eq!(events, vec![Event::Changed(FREQUENCY, 700)]);
+        assert_eq!(menu.depth(), 1);
+
+        // Cancelled edits don't change the value
+        run(&mut menu, &mut settings, &[Select, Prev, Back]);
+        assert_eq!(settings.0[FREQUENCY as usize], 700);
+        assert!(!menu.is_editing());
+
+        let events = run(&mut menu, &mut settings, &[Back, Back]);
+        assert_eq!(events, vec![Event::Exit]);
+        assert_eq!(menu.selected().label(), "PWM");
+    }
+
+    #[test]
+    fn single_button_numbers_wrap() {
+        let mut settings = Settings([2, 0, 500, 0]);
+        let mut menu = Menu::new("Settings", ROOT);
+        let presses = [Press::Long, Press::Short, Press::Short, Press::Long];
+        for &press in presses.iter() {
+            menu.handle(Input::from_single_button(press), &mut settings);
+        }
+        assert_eq!(settings.0[BRIGHTNESS as usize], 0);
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut settings = Settings([1, 1, 500, 0]);
+        let mut menu = Menu::new("Settings", ROOT);
+        let mut fb = Framebuffer::new();
+        menu.draw(&mut fb, &settings);
+        assert_snapshot("menu_top", &fb);
+
+        run(&mut menu, &mut settings, &[Input::Prev]);
+        assert_eq!(menu.levels[0].scroll, 1);
+        run(&mut menu, &mut settings, &[Input::Next]);
+        assert_eq!(menu.levels[0].scroll, 0);
+        run(&mut menu, &mut settings, &[Input::Prev, Input::Select]);
+        menu.draw(&mut fb, &settings);
+        assert_snapshot("menu_scrolled", &fb);
+    }
+}
@@ -0,0 +1,44 @@
+//! Mocks of the embedded-hal traits, shared by the host tests.
+
+use embedded_hal::PwmPin;
+
+/// A PWM channel that remembers its duty and whether it is enabled.
+pub struct MockPwm {
+    pub duty: u16,
+    pub max_duty: u16,
+    pub enabled: bool,
+}
+
+impl MockPwm {
+    pub fn new(max_duty: u16) -> Self {
+        MockPwm {
+            duty: 0,
+            max_duty,
+            enabled: false,
+        }
+    }
+}
+
+impl PwmPin for MockPwm {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    fn get_duty(&self) -> u16 {
+        self.duty
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.max_duty
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        self.duty = duty;
+    }
+}
@@ -0,0 +1,538 @@
+//! DC motors switched by a low-side transistor with PWM.
+//!
+//! The transistor (a 30N06L straight from the pin, or an IRF520, BC517 or
+//! BD139 as in `examples.md`) sits between the motor and ground, with a
+//! diode across the motor for the current that keeps flowing when it turns
+//! off.  The duty sets the average voltage, and so the speed:
+//!
+//! ```ignore
+//! let pwm = dp.TIM2.pwm(pa0, &mut afio.mapr, config.frequency_hz.hz(), clocks, &mut rcc.apb1);
+//! let mut motor = Motor::new(pwm, NoBrake, config);
+//! motor.set_speed(800);
+//! loop {
+//!     motor.sense(adc.read_mv(&mut pb1));
+//!     motor.update(time.millis());
+//! }
+//! ```
+//!
+//! Speeds are in per mille of the full speed.  Starting at once draws the
+//! stall current of the motor and jerks the load, so `update` ramps the
+//! speed toward the one set, from 0 to full in `Config::ramp_ms` (`slew`).
+//! Below some duty the motor hums without turning, held by its friction:
+//! any speed above 0 starts at `Config::min_duty` instead.
+//!
+//! `coast` turns the transistor off and lets the motor spin down on its own.
+//! `brake` also turns on a second transistor across the motor, on the brake
+//! pin, which shorts it and stops it quickly.  Both transistors on would
+//! short the supply, so the brake waits for an update two ticks of the
+//! millisecond clock later, a whole millisecond at least, and `set_speed`
+//! releases it before driving again.
+//! Without that transistor the pin is `NoBrake` and braking is coasting.
+//!
+//! With a shunt resistor between the source (or emitter) and ground, `sense`
+//! takes the voltage on it and turns the motor off for good, until `reset`,
+//! when the current stays above `Config::current_limit_ma`.  The voltage is
+//! only there while the transistor is on: an RC filter in front of the ADC
+//! (1 kOhm and 1 uF) makes it the average current.
+
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::PwmPin;
+
+use crate::slew::Slew;
+
+/// Full speed, and full duty, in per mille.
+pub const FULL: u16 = 1000;
+/// Consecutive readings above the limit that trip the over-current
+/// shutdown: shorter spikes pass.
+const TRIP_READINGS: u8 = 3;
+/// Fractions of a per mille on the ramp, for slow ramps to move by less
+/// than a per mille per update.
+const SCALE: u32 = 1_000_000;
+/// Ticks of the millisecond clock between turning the transistor off and
+/// the brake on.  The first may come right after the transistor turned off.
+const BRAKE_DELAY: u32 = 2;
+
+/// Duty of the timer for `speed`, from `min_duty` (per mille) for the
+/// slowest speed to `max_duty` for the full speed.  0 is off.
+pub fn duty(speed: u16, min_duty: u16, max_duty: u16) -> u16 {
+    if speed == 0 {
+        return 0;
+    }
+    let speed = u32::from(speed.min(FULL));
+    let min_duty = u32::from(min_duty.min(FULL));
+    let per_mille = min_duty + speed * (u32::from(FULL) - min_duty) / u32::from(FULL);
+    (per_mille * u32::from(max_duty) / u32::from(FULL)) as u16
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// PWM frequency, of the timer of the pin
+    pub frequency_hz: u32,
+    /// Milliseconds from stopped to full speed, 0 for no ramp
+    pub ramp_ms: u32,
+    /// Duty in per mille at which the motor starts turning
+    pub min_duty: u16,
+    /// Average current in mA that shuts the motor off, 0 for no limit
+    pub current_limit_ma: u32,
+    /// Shunt resistor in milliohms
+    pub shunt_mohm: u32,
+}
+
+impl Default for Config {
+    /// 20 kHz, above hearing, with a ramp of half a second.
+    fn default() -> Self {
+        Config {
+            frequency_hz: 20_000,
+            ramp_ms: 500,
+            min_duty: 0,
+            current_limit_ma: 0,
+            shunt_mohm: 0,
+        }
+    }
+}
+
+impl Config {
+    pub fn with_frequency(mut self, frequency_hz: u32) -> Self {
+        self.frequency_hz = frequency_hz;
+        self
+    }
+
+    pub fn with_ramp(mut self, ramp_ms: u32) -> Self {
+        self.ramp_ms = ramp_ms;
+        self
+    }
+
+    /// Found by raising the duty slowly until the motor starts.
+    pub fn with_min_duty(mut self, min_duty: u16) -> Self {
+        self.min_duty = min_duty.min(FULL);
+        self
+    }
+
+    pub fn with_current_limit(mut self, limit_ma: u32, shunt_mohm: u32) -> Self {
+        self.current_limit_ma = limit_ma;
+        self.shunt_mohm = shunt_mohm;
+        self
+    }
+
+    /// The current through the shunt for a voltage of `mv`, 0 without one.
+    pub fn current_ma(&self, mv: u32) -> u32 {
+        if self.shunt_mohm == 0 {
+            return 0;
+        }
+        (u64::from(mv) * 1000 / u64::from(self.shunt_mohm)) as u32
+    }
+}
+
+/// The pin of a circuit without a brake transistor.
+pub struct NoBrake;
+
+impl OutputPin for NoBrake {
+    type Error = ();
+
+    fn set_low(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// Driven at the speed of the ramp, maybe 0.
+    Run,
+    /// Off, spinning down.
+    Coast,
+    /// Off and shorted by the brake transistor, once `braked` is set.
+    Brake { braked: bool },
+    /// Off after too much current, until `reset`.
+    OverCurrent,
+}
+
+pub struct Motor<P, B> {
+    pin: P,
+    brake: B,
+    max_duty: u16,
+    config: Config,
+    /// Speed in billionths
+    ramp: Slew,
+    state: State,
+    /// Time the transistor was turned off for braking
+    off_since: Option<u32>,
+    /// Consecutive readings above the current limit
+    over: u8,
+}
+
+impl<P: PwmPin<Duty = u16>, B: OutputPin> Motor<P, B> {
+    /// Start coasting, with the transistors off.  `pin` has to be at
+    /// `config.frequency_hz`.
+    pub fn new(mut pin: P, mut brake: B, config: Config) -> Self {
+        let _ = brake.set_low();
+        pin.disable();
+        pin.set_duty(0);
+        let max_duty = pin.get_max_duty();
+        Motor {
+            pin,
+            brake,
+            max_duty,
+            config,
+            ramp: Slew::new(0).with_rate(u32::from(FULL) * SCALE, config.ramp_ms),
+            state: State::Coast,
+            off_since: None,
+            over: 0,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// New ramp, minimum duty or current limit.  The frequency is the one of
+    /// the timer.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.ramp.set_rate(u32::from(FULL) * SCALE, config.ramp_ms);
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The speed to ramp to, up to `FULL`, from the speed reached or from 0
+    /// after coasting or braking.  Ignored after an over-current, until
+    /// `reset`.
+    pub fn set_speed(&mut self, speed: u16) {
+        match self.state {
+            State::OverCurrent => return,
+            State::Run => {}
+            State::Coast | State::Brake { .. } => {
+                // Released before the transistor turns on
+                let _ = self.brake.set_low();
+                self.off_since = None;
+                self.pin.set_duty(0);
+                self.pin.enable();
+                self.state = State::Run;
+            }
+        }
+        self.ramp.set_target(u32::from(speed.min(FULL)) * SCALE);
+        self.write();
+    }
+
+    /// The speed set.
+    pub fn target(&self) -> u16 {
+        (self.ramp.target() / SCALE) as u16
+    }
+
+    /// The speed reached on the ramp, 0 when not running.
+    pub fn speed(&self) -> u16 {
+        (self.ramp.value() / SCALE) as u16
+    }
+
+    /// Turn off and let the motor spin down.
+    pub fn coast(&mut self) {
+        if self.state != State::OverCurrent {
+            self.off();
+            self.state = State::Coast;
+        }
+    }
+
+    /// Turn off and short the motor, at an update `BRAKE_DELAY` ticks of the
+    /// clock later.
+    pub fn brake(&mut self) {
+        match self.state {
+            State::OverCurrent | State::Brake { .. } => {}
+            _ => {
+                self.off();
+                self.state = State::Brake { braked: false };
+            }
+        }
+    }
+
+    /// A reading of the voltage on the shunt, in mV, for the over-current
+    /// shutdown.  Only while running, and with a limit.
+    pub fn sense(&mut self, mv: u32) {
+        if self.state != State::Run || self.config.current_limit_ma == 0 {
+            self.over = 0;
+            return;
+        }
+        if self.config.current_ma(mv) <= self.config.current_limit_ma {
+            self.over = 0;
+            return;
+        }
+        self.over += 1;
+        if self.over >= TRIP_READINGS {
+            self.off();
+            self.state = State::OverCurrent;
+        }
+    }
+
+    /// Back to coasting after an over-current, for `set_speed` to start again.
+    pub fn reset(&mut self) {
+        if self.state == State::OverCurrent {
+            self.over = 0;
+            self.state = State::Coast;
+        }
+    }
+
+    /// Ramp the speed, and apply the brake.  Call it often, every few
+    /// milliseconds.
+    pub fn update(&mut self, now: u32) {
+        self.ramp.update(now);
+        match self.state {
+            State::Run => self.write(),
+            State::Brake { braked: false } => match self.off_since {
+                None => self.off_since = Some(now),
+                Some(since) if now.wrapping_sub(since) >= BRAKE_DELAY => {
+                    let _ = self.brake.set_high();
+                    self.state = State::Brake { braked: true };
+                }
+                Some(_) => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn write(&mut self) {
+        let duty = duty(self.speed(), self.config.min_duty, self.max_duty);
+        self.pin.set_duty(duty);
+    }
+
+    fn off(&mut self) {
+        self.pin.disable();
+        let _ = self.brake.set_low();
+        self.pin.set_duty(0);
+        self.ramp.set_value(0);
+        self.off_since = None;
+    }
+
+    /// Turn the transistors off and give the pins back.
+    pub fn release(mut self) -> (P, B) {
+        self.pin.disable();
+        let _ = self.brake.set_low();
+        (self.pin, self.brake)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockPwm;
+    use std::cell::Cell;
+
+    /// Records the level of the brake pin.
+    struct MockBrake<'a>(&'a Cell<bool>);
+
+    impl<'a> OutputPin for MockBrake<'a> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    /// 20 kHz at 72 MHz: 3600 ticks.
+    const MAX_DUTY: u16 = 3599;
+
+    #[test]
+    fn duties() {
+        assert_eq!(duty(0, 0, 1000), 0);
+        assert_eq!(duty(500, 0, 1000), 500);
+        assert_eq!(duty(FULL, 0, MAX_DUTY), MAX_DUTY);
+        assert_eq!(duty(2000, 0, MAX_DUTY), MAX_DUTY);
+        // Any speed starts at the minimum duty
+        assert_eq!(duty(0, 300, 1000), 0);
+        assert_eq!(duty(1, 300, 1000), 300);
+        assert_eq!(duty(500, 300, 1000), 650);
+        assert_eq!(duty(FULL, 300, 1000), 1000);
+        let mut last = 0;
+        for speed in 1..=FULL {
+            let duty = duty(speed, 250, MAX_DUTY);
+            assert!(duty >= last && duty >= MAX_DUTY / 4, "{}", speed);
+            last = duty;
+        }
+    }
+
+    #[test]
+    fn ramp() {
+        let config = Config::default().with_ramp(500);
+        let mut motor = Motor::new(MockPwm::new(1000), NoBrake, config);
+        motor.update(0);
+        motor.set_speed(FULL);
+        assert_eq!(motor.target(), FULL);
+        // 2 per mille per millisecond
+        motor.update(10);
+        assert_eq!(motor.speed(), 20);
+        motor.update(260);
+        assert_eq!(motor.speed(), 520);
+        motor.update(510);
+        assert_eq!(motor.speed(), FULL);
+        assert_eq!(motor.pin.duty, 1000);
+
+        // Down at the same rate
+        motor.set_speed(400);
+        motor.update(610);
+        assert_eq!(motor.speed(), 800);
+        motor.update(5000);
+        assert_eq!(motor.speed(), 400);
+        assert_eq!(motor.pin.duty, 400);
+
+        // Slower than a per mille per update still moves
+        motor.set_config(config.with_ramp(60_000));
+        motor.coast();
+        motor.set_speed(FULL);
+        for now in 1..=600 {
+            motor.update(5000 + now * 10);
+        }
+        // 100 per mille, but for the rounding down of every step
+        assert_eq!(motor.speed(), 99);
+        motor.update(11_010);
+        assert_eq!(motor.speed(), 100);
+
+        // Across the wrap of the clock
+        let config = Config::default().with_ramp(1000);
+        let mut motor = Motor::new(MockPwm::new(1000), NoBrake, config);
+        motor.update(u32::MAX - 99);
+        motor.set_speed(FULL);
+        motor.update(100);
+        assert_eq!(motor.speed(), 200);
+
+        // Without a ramp
+        let mut motor = Motor::new(MockPwm::new(1000), NoBrake, config.with_ramp(0));
+        motor.set_speed(700);
+        assert_eq!(motor.speed(), 700);
+        assert_eq!(motor.pin.duty, 700);
+    }
+
+    #[test]
+    fn soft_start() {
+        let config = Config::default().with_ramp(1000).with_min_duty(200);
+        let mut motor = Motor::new(MockPwm::new(MAX_DUTY), NoBrake, config);
+        assert!(!motor.pin.enabled);
+        assert_eq!(motor.state(), State::Coast);
+
+        motor.update(0);
+        motor.set_speed(FULL);
+        assert!(motor.pin.enabled);
+        assert_eq!(motor.pin.duty, 0);
+        motor.update(1);
+        // Just started, at the minimum duty
+        assert_eq!(motor.speed(), 1);
+        assert_eq!(motor.pin.duty, duty(1, 200, MAX_DUTY));
+        assert!(motor.pin.duty >= MAX_DUTY / 5);
+        motor.update(500);
+        assert_eq!(motor.speed(), 500);
+        assert_eq!(motor.pin.duty, duty(500, 200, MAX_DUTY));
+        motor.update(1000);
+        assert_eq!(motor.pin.duty, MAX_DUTY);
+
+        // Coasting turns off at once, and starts from 0 again
+        motor.coast();
+        assert_eq!(motor.state(), State::Coast);
+        assert!(!motor.pin.enabled);
+        assert_eq!(motor.speed(), 0);
+        motor.set_speed(300);
+        motor.update(1100);
+        assert_eq!(motor.speed(), 100);
+
+        let (pin, _) = motor.release();
+        assert!(!pin.enabled);
+    }
+
+    #[test]
+    fn brake() {
+        let brake = Cell::new(false);
+        let config = Config::default().with_ramp(0);
+        let mut motor = Motor::new(MockPwm::new(MAX_DUTY), MockBrake(&brake), config);
+        motor.set_speed(FULL);
+        motor.update(0);
+        assert_eq!(motor.pin.duty, MAX_DUTY);
+
+        // The transistor off first, the brake two ticks later: the first
+        // tick may come right away
+        motor.brake();
+        assert!(!motor.pin.enabled);
+        assert!(!brake.get());
+        motor.update(10);
+        assert!(!brake.get());
+        motor.update(11);
+        assert!(!brake.get());
+        motor.update(12);
+        assert!(brake.get());
+        assert_eq!(motor.state(), State::Brake { braked: true });
+
+        // Released before driving again
+        motor.set_speed(500);
+        assert!(!brake.get());
+        assert!(motor.pin.enabled);
+        assert_eq!(motor.pin.duty, duty(500, 0, MAX_DUTY));
+
+        // Coasting cancels a brake not applied yet
+        motor.brake();
+        motor.update(20);
+        motor.coast();
+        motor.update(30);
+        assert!(!brake.get());
+        assert_eq!(motor.state(), State::Coast);
+
+        // Coasting releases an applied brake
+        motor.set_speed(500);
+        motor.brake();
+        motor.update(40);
+        motor.update(42);
+        assert!(brake.get());
+        motor.coast();
+        assert!(!brake.get());
+    }
+
+    #[test]
+    fn over_current() {
+        // 2 A through 0.1 Ohm: 200 mV
+        let config = Config::default().with_ramp(0).with_current_limit(2000, 100);
+        assert_eq!(config.current_ma(150), 1500);
+        let mut motor = Motor::new(MockPwm::new(MAX_DUTY), NoBrake, config);
+        motor.set_speed(FULL);
+        motor.update(0);
+
+        // Spikes pass
+        for &mv in &[150, 250, 250, 150, 200, 250, 190] {
+            motor.sense(mv);
+        }
+        assert_eq!(motor.state(), State::Run);
+        assert!(motor.pin.enabled);
+
+        for _ in 0..TRIP_READINGS {
+            motor.sense(210);
+        }
+        assert_eq!(motor.state(), State::OverCurrent);
+        assert!(!motor.pin.enabled);
+        assert_eq!(motor.speed(), 0);
+
+        // Stays off until reset
+        motor.set_speed(500);
+        motor.coast();
+        motor.brake();
+        motor.update(10);
+        assert_eq!(motor.state(), State::OverCurrent);
+        assert!(!motor.pin.enabled);
+        motor.reset();
+        assert_eq!(motor.state(), State::Coast);
+        motor.set_speed(500);
+        assert!(motor.pin.enabled);
+
+        // Without a limit
+        let config = Config::default().with_ramp(0);
+        let mut motor = Motor::new(MockPwm::new(MAX_DUTY), NoBrake, config);
+        motor.set_speed(FULL);
+        for _ in 0..10 {
+            motor.sense(3300);
+        }
+        assert_eq!(motor.state(), State::Run);
+    }
+}
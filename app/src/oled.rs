@@ -0,0 +1,876 @@
+//! SSD1306 and SH1106 driver that only sends what changed since the last
+//! flush.
+//!
+//! The `ssd1306` crate sends the whole 1 KiB framebuffer on every `flush()`.
+//! Here the framebuffer is compared with a copy of what the display shows,
+//! for the pages that were touched, and only the changed column range of each
+//! page is sent.  Consecutive pages with the same range share one address
+//! window on the SSD1306; the SH1106 only addresses one page at a time.  When
+//! nothing changed, nothing is sent.
+//!
+//! The display is connected over I2C (`I2cInterface`) or over SPI with a
+//! data/command pin (`SpiInterface`).  The framebuffer is always 128x64, a
+//! 128x32 panel shows its top half.  See `display` for the one the examples
+//! use.
+//!
+//! `update` is `flush` for displays that may be unplugged or glitch: after an
+//! error it recovers the bus (see `i2c`) and initializes the display again.
+//! If that fails the display is considered absent, drawing still works but
+//! nothing is sent, and it is looked for again from time to time.
+//!
+//! The contrast, inversion and 180 degree rotation (`Settings`), the power
+//! (`Power`, see `screensaver`) and a shift of everything that is drawn can
+//! be changed at any time.  They are sent with the next flush, and again
+//! after every initialization.
+
+use embedded_graphics::drawable::Pixel;
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::unsignedcoord::UnsignedCoord;
+use embedded_graphics::Drawing;
+use embedded_hal::blocking::{i2c, spi};
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::framebuffer::{Framebuffer, PAGES, WIDTH};
+
+/// I2C address of most modules, 0x3D when the address pin is pulled high.
+pub const DEFAULT_ADDRESS: u8 = 0x3c;
+
+/// I2C control byte for a list of commands.
+const CONTROL_COMMANDS: u8 = 0x00;
+/// I2C control byte for a run of display data.
+const CONTROL_DATA: u8 = 0x40;
+
+/// Without the multiplex ratio and COM pins, which depend on the height, and
+/// without turning the display on.
+const SSD1306_INIT: &[u8] = &[
+    0xae, // display off
+    0xd5, 0x80, // clock divide ratio and oscillator frequency
+    0xd3, 0x00, // no display offset
+    0x40, // start line 0
+    0x8d, 0x14, // enable the charge pump
+    0x20, 0x00, // horizontal addressing mode
+    0xd9, 0xf1, // pre-charge period
+    0xdb, 0x40, // VCOMH deselect level
+    0xa4, // show the RAM contents
+    0x2e, // no scrolling
+];
+
+/// Same as `SSD1306_INIT`. The SH1106 has no addressing modes nor scrolling,
+/// and its charge pump is a DC-DC converter.
+const SH1106_INIT: &[u8] = &[
+    0xae, // display off
+    0xd5, 0x80, // clock divide ratio and oscillator frequency
+    0xd3, 0x00, // no display offset
+    0x40, // start line 0
+    0xad, 0x8b, // enable the DC-DC converter
+    0xd9, 0x1f, // pre-charge period
+    0xdb, 0x40, // VCOMH deselect level
+    0xa4, // show the RAM contents
+];
+
+/// The SH1106 has 132 columns of RAM, the panel shows the middle 128.
+const SH1106_COLUMN_OFFSET: u8 = 2;
+
+/// Most `update` calls between two attempts to find an absent display.
+pub const MAX_BACKOFF: u16 = 64;
+
+/// A dimmed display has its contrast divided by this.
+pub const DIM_DIVISOR: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Controller {
+    Ssd1306,
+    Sh1106,
+}
+
+/// The controller and the size of a display module.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Panel {
+    pub controller: Controller,
+    /// 64 or 32 rows, always 128 columns
+    pub height: u32,
+}
+
+impl Panel {
+    pub const SSD1306_128X64: Panel = Panel {
+        controller: Controller::Ssd1306,
+        height: 64,
+    };
+    pub const SSD1306_128X32: Panel = Panel {
+        controller: Controller::Ssd1306,
+        height: 32,
+    };
+    pub const SH1106_128X64: Panel = Panel {
+        controller: Controller::Sh1106,
+        height: 64,
+    };
+
+    pub fn pages(&self) -> u32 {
+        self.height / 8
+    }
+}
+
+/// What can be changed on a running display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// 0 to 255, how much current goes through the pixels
+    pub contrast: u8,
+    /// Unlit pixels on a lit background
+    pub inverted: bool,
+    /// Upside down, for modules mounted the other way around
+    pub rotated: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            contrast: 0xcf,
+            inverted: false,
+            rotated: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Power {
+    On,
+    /// On with a fraction of the contrast
+    Dimmed,
+    /// Display off (sleep mode), the contents are kept
+    Off,
+}
+
+/// How commands and display data get to the controller.
+pub trait Interface {
+    type Error;
+
+    fn commands(&mut self, cmds: &[u8]) -> Result<(), Self::Error>;
+    fn data(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Bring the bus back to a usable state after an error.
+    fn recover(&mut self) {}
+}
+
+/// I2C, where a control byte in front tells commands from data.
+pub struct I2cInterface<I2C> {
+    i2c: I2C,
+    addr: u8,
+    recovery: Option<fn() -> bool>,
+}
+
+impl<I2C> I2cInterface<I2C> {
+    pub fn new(i2c: I2C, addr: u8) -> Self {
+        I2cInterface {
+            i2c,
+            addr,
+            recovery: None,
+        }
+    }
+
+    /// Call `recovery` after errors, like `i2c::recover_i2c1`.
+    pub fn with_recovery(mut self, recovery: fn() -> bool) -> Self {
+        self.recovery = Some(recovery);
+        self
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C: i2c::Write> Interface for I2cInterface<I2C> {
+    type Error = I2C::Error;
+
+    fn commands(&mut self, cmds: &[u8]) -> Result<(), Self::Error> {
+        let mut buf = [CONTROL_COMMANDS; 32];
+        buf[1..=cmds.len()].copy_from_slice(cmds);
+        self.i2c.write(self.addr, &buf[..=cmds.len()])
+    }
+
+    fn data(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let mut buf = [CONTROL_DATA; WIDTH as usize + 1];
+        buf[1..=data.len()].copy_from_slice(data);
+        self.i2c.write(self.addr, &buf[..=data.len()])
+    }
+
+    fn recover(&mut self) {
+        if let Some(recovery) = self.recovery {
+            recovery();
+        }
+    }
+}
+
+/// 4-wire SPI: the DC pin is low for commands and high for data, CS is low
+/// during a transfer.
+pub struct SpiInterface<SPI, CS, DC> {
+    spi: SPI,
+    cs: CS,
+    dc: DC,
+}
+
+impl<SPI, CS, DC> SpiInterface<SPI, CS, DC>
+where
+    SPI: spi::Write<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+{
+    pub fn new(spi: SPI, mut cs: CS, dc: DC) -> Self {
+        // GPIO writes don't fail on this chip
+        let _ = cs.set_high();
+        SpiInterface { spi, cs, dc }
+    }
+
+    pub fn release(self) -> (SPI, CS, DC) {
+        (self.spi, self.cs, self.dc)
+    }
+
+    fn transfer(&mut self, data: bool, bytes: &[u8]) -> Result<(), SPI::Error> {
+        let _ = if data {
+            self.dc.set_high()
+        } else {
+            self.dc.set_low()
+        };
+        let _ = self.cs.set_low();
+        let res = self.spi.write(bytes);
+        let _ = self.cs.set_high();
+        res
+    }
+}
+
+impl<SPI, CS, DC> Interface for SpiInterface<SPI, CS, DC>
+where
+    SPI: spi::Write<u8>,
+    CS: OutputPin,
+    DC: OutputPin,
+{
+    type Error = SPI::Error;
+
+    fn commands(&mut self, cmds: &[u8]) -> Result<(), Self::Error> {
+        self.transfer(false, cmds)
+    }
+
+    fn data(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.transfer(true, data)
+    }
+}
+
+/// What `update` noticed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A transfer failed, but the display works again after a recovery
+    Recovered,
+    /// The display doesn't answer anymore and is considered absent
+    Lost,
+    /// The absent display answers again
+    Found,
+}
+
+pub struct Oled<DI> {
+    interface: DI,
+    panel: Panel,
+    fb: Framebuffer,
+    /// What the display shows, as of the last flush
+    shown: Framebuffer,
+    /// The display contents are unknown: send everything on the next flush
+    full: bool,
+    initialized: bool,
+    absent: bool,
+    /// Updates to wait before looking for an absent display, and the wait
+    /// after the next failure
+    wait: u16,
+    backoff: u16,
+    recoveries: u32,
+    settings: Settings,
+    power: Power,
+    /// The settings or the power changed since they were sent
+    pending: bool,
+    /// Offset of what is drawn, in pixels
+    shift: (u32, u32),
+}
+
+impl<DI, E> Oled<DI>
+where
+    DI: Interface<Error = E>,
+{
+    pub fn new(interface: DI, panel: Panel) -> Self {
+        Oled {
+            interface,
+            panel,
+            fb: Framebuffer::new(),
+            shown: Framebuffer::new(),
+            full: true,
+            initialized: false,
+            absent: false,
+            wait: 0,
+            backoff: 1,
+            recoveries: 0,
+            settings: Settings::default(),
+            power: Power::On,
+            pending: true,
+            shift: (0, 0),
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), E> {
+        self.full = true;
+        let height = self.panel.height;
+        let init = match self.panel.controller {
+            Controller::Ssd1306 => SSD1306_INIT,
+            Controller::Sh1106 => SH1106_INIT,
+        };
+        self.interface.commands(init)?;
+        // Multiplex ratio and COM pins configuration
+        let com_pins = if height > 32 { 0x12 } else { 0x02 };
+        self.interface
+            .commands(&[0xa8, height as u8 - 1, 0xda, com_pins])?;
+        // Turns the display on
+        self.send_settings()?;
+        self.initialized = true;
+        Ok(())
+    }
+
+    fn send_settings(&mut self) -> Result<(), E> {
+        let Settings {
+            contrast,
+            inverted,
+            rotated,
+        } = self.settings;
+        let contrast = match self.power {
+            Power::Dimmed => contrast / DIM_DIVISOR,
+            _ => contrast,
+        };
+        // Rotated, column 0 is mapped to SEG0 and COM0 is scanned first
+        let (remap, scan) = if rotated { (0xa0, 0xc0) } else { (0xa1, 0xc8) };
+        let inversion = if inverted { 0xa7 } else { 0xa6 };
+        let on = if self.power == Power::Off { 0xae } else { 0xaf };
+        self.interface
+            .commands(&[0x81, contrast, remap, scan, inversion, on])?;
+        self.pending = false;
+        Ok(())
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// Change the settings, on the next flush.
+    pub fn set_settings(&mut self, settings: Settings) {
+        if settings.rotated != self.settings.rotated {
+            // The remapping only applies to the data written after it
+            self.full = true;
+        }
+        self.pending |= settings != self.settings;
+        self.settings = settings;
+    }
+
+    pub fn power(&self) -> Power {
+        self.power
+    }
+
+    /// Dim the display or turn it off or on, on the next flush.
+    pub fn set_power(&mut self, power: Power) {
+        self.pending |= power != self.power;
+        self.power = power;
+    }
+
+    /// Move everything drawn from now on right by `dx` and down by `dy`
+    /// pixels, against burn-in.  Pixels moved off the display are lost.
+    pub fn set_shift(&mut self, dx: u32, dy: u32) {
+        self.shift = (dx, dy);
+    }
+
+    pub fn shift(&self) -> (u32, u32) {
+        self.shift
+    }
+
+    /// Initialize the display if needed and send the changes, recovering
+    /// from errors.  Use it instead of `init` and `flush`.
+    ///
+    /// An absent display is looked for again after 1 update, then 2, 4...
+    /// up to `MAX_BACKOFF` updates.
+    pub fn update(&mut self) -> Option<Event> {
+        if self.absent {
+            if self.wait > 0 {
+                self.wait -= 1;
+                return None;
+            }
+            if self.restart().is_ok() {
+                self.absent = false;
+                return Some(Event::Found);
+            }
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            self.wait = self.backoff;
+            return None;
+        }
+
+        let res = if self.initialized {
+            self.flush()
+        } else {
+            self.init().and_then(|_| self.flush())
+        };
+        if res.is_ok() {
+            return None;
+        }
+        if self.restart().is_ok() {
+            self.recoveries += 1;
+            return Some(Event::Recovered);
+        }
+        self.absent = true;
+        self.backoff = 1;
+        self.wait = 1;
+        Some(Event::Lost)
+    }
+
+    fn restart(&mut self) -> Result<(), E> {
+        self.interface.recover();
+        self.init()?;
+        self.flush()
+    }
+
+    /// Whether the display answered the last time it was written to.
+    pub fn is_present(&self) -> bool {
+        !self.absent
+    }
+
+    /// Errors that `update` recovered from.
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+
+    pub fn panel(&self) -> Panel {
+        self.panel
+    }
+
+    /// Width and height of the panel, which may be less than the framebuffer.
+    pub fn size(&self) -> (u32, u32) {
+        (WIDTH, self.panel.height)
+    }
+
+    /// Clear the framebuffer. Like drawing, it takes effect on `flush()`.
+    pub fn clear(&mut self) {
+        self.fb.clear();
+    }
+
+    pub fn framebuffer(&mut self) -> &mut Framebuffer {
+        &mut self.fb
+    }
+
+    /// Send the changes to the display.
+    pub fn flush(&mut self) -> Result<(), E> {
+        if self.pending {
+            if let Err(e) = self.send_settings() {
+                self.full = true;
+                return Err(e);
+            }
+        }
+        let touched = self.fb.take_touched();
+        let touched = if self.full { 0xff } else { touched };
+
+        // Changed column range of every page
+        let mut windows: [Option<(u8, u8)>; PAGES as usize] = [None; PAGES as usize];
+        for page in 0..self.panel.pages() {
+            if touched & (1 << page) == 0 {
+                continue;
+            }
+            if self.full {
+                windows[page as usize] = Some((0, WIDTH as u8 - 1));
+                continue;
+            }
+            let new = self.fb.page(page);
+            let old = self.shown.page(page);
+            let first = new.iter().zip(old).position(|(n, o)| n != o);
+            let last = new.iter().zip(old).rposition(|(n, o)| n != o);
+            if let (Some(first), Some(last)) = (first, last) {
+                windows[page as usize] = Some((first as u8, last as u8));
+            }
+        }
+
+        let res = self.send(&windows);
+        // After an error the display contents are unknown
+        self.full = res.is_err();
+        res
+    }
+
+    pub fn release(self) -> DI {
+        self.interface
+    }
+
+    fn send(&mut self, windows: &[Option<(u8, u8)>]) -> Result<(), E> {
+        let mut page = 0;
+        while page < windows.len() {
+            let (start, end) = match windows[page] {
+                Some(window) => window,
+                None => {
+                    page += 1;
+                    continue;
+                }
+            };
+            let mut last = page;
+            if self.panel.controller == Controller::Ssd1306 {
+                while last + 1 < windows.len() && windows[last + 1] == windows[page] {
+                    last += 1;
+                }
+                self.interface
+                    .commands(&[0x21, start, end, 0x22, page as u8, last as u8])?;
+            } else {
+                let column = start + SH1106_COLUMN_OFFSET;
+                self.interface
+                    .commands(&[0xb0 | page as u8, column & 0x0f, 0x10 | (column >> 4)])?;
+            }
+            for p in page..=last {
+                let bytes = &self.fb.page(p as u32)[start as usize..=end as usize];
+                self.interface.data(bytes)?;
+                self.shown.copy_page(p as u32, &self.fb);
+            }
+            page = last + 1;
+        }
+        Ok(())
+    }
+}
+
+impl<DI> Drawing<PixelColorU8> for Oled<DI> {
+    fn draw<T>(&mut self, item_pixels: T)
+    where
+        T: Iterator<Item = Pixel<PixelColorU8>>,
+    {
+        let (dx, dy) = self.shift;
+        self.fb.draw(
+            item_pixels.map(|Pixel(UnsignedCoord(x, y), color)| {
+                Pixel(UnsignedCoord(x + dx, y + dy), color)
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::screens;
+    use core::cell::Cell;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts the bytes that would go over the bus.
+    #[derive(Default)]
+    struct MockI2c {
+        bytes: usize,
+        writes: usize,
+        /// Writes that fail next
+        glitches: usize,
+        /// Nothing answers
+        down: bool,
+        /// Every command sent, without the control bytes
+        commands: Vec<u8>,
+    }
+
+    impl i2c::Write for MockI2c {
+        type Error = ();
+
+        fn write(&mut self, _addr: u8, bytes: &[u8]) -> Result<(), ()> {
+            self.writes += 1;
+            if self.down {
+                return Err(());
+            }
+            if self.glitches > 0 {
+                self.glitches -= 1;
+                return Err(());
+            }
+            self.bytes += bytes.len();
+            if bytes[0] == CONTROL_COMMANDS {
+                self.commands.extend_from_slice(&bytes[1..]);
+            }
+            Ok(())
+        }
+    }
+
+    type MockOled = Oled<I2cInterface<MockI2c>>;
+
+    /// Bytes sent by one flush.
+    fn flush(oled: &mut MockOled) -> usize {
+        oled.interface.i2c.bytes = 0;
+        oled.flush().unwrap();
+        oled.interface.i2c.bytes
+    }
+
+    fn oled_with(panel: Panel) -> MockOled {
+        let interface = I2cInterface::new(MockI2c::default(), DEFAULT_ADDRESS);
+        let mut oled = Oled::new(interface, panel);
+        oled.init().unwrap();
+        oled
+    }
+
+    fn oled() -> MockOled {
+        oled_with(Panel::SSD1306_128X64)
+    }
+
+    #[test]
+    fn first_flush_sends_everything() {
+        let mut oled = oled();
+        // One window command, then 8 pages of data with their control byte
+        assert_eq!(flush(&mut oled), 7 + 8 * 129);
+        assert_eq!(flush(&mut oled), 0);
+    }
+
+    #[test]
+    fn single_pixel() {
+        let mut oled = oled();
+        flush(&mut oled);
+        oled.framebuffer().set_pixel(10, 20, true);
+        assert_eq!(flush(&mut oled), 7 + 2);
+        assert_eq!(flush(&mut oled), 0);
+    }
+
+    #[test]
+    fn redrawing_the_same_frame_sends_nothing() {
+        let mut oled = oled();
+        screens::counter(&mut oled, 1233);
+        flush(&mut oled);
+        oled.clear();
+        screens::counter(&mut oled, 1233);
+        assert_eq!(flush(&mut oled), 0);
+    }
+
+    #[test]
+    fn counter_increment() {
+        let mut oled = oled();
+        screens::counter(&mut oled, 1233);
+        flush(&mut oled);
+        oled.clear();
+        screens::counter(&mut oled, 1234);
+        // Only the last 12 pixel wide digit changes, over at most 3 pages
+        let sent = flush(&mut oled);
+        assert!(sent > 0);
+        assert!(sent <= 3 * (7 + 13));
+    }
+
+    #[test]
+    fn sh1106_pages() {
+        let mut oled = oled_with(Panel::SH1106_128X64);
+        // A page address and the two halves of the column address per page
+        assert_eq!(flush(&mut oled), 8 * (4 + 129));
+        oled.framebuffer().set_pixel(10, 20, true);
+        oled.framebuffer().set_pixel(10, 30, true);
+        assert_eq!(flush(&mut oled), 2 * (4 + 2));
+    }
+
+    #[test]
+    fn half_height_panel() {
+        let mut oled = oled_with(Panel::SSD1306_128X32);
+        assert_eq!(oled.size(), (128, 32));
+        assert_eq!(flush(&mut oled), 7 + 4 * 129);
+        // Below the panel
+        oled.framebuffer().set_pixel(10, 40, true);
+        assert_eq!(flush(&mut oled), 0);
+        oled.framebuffer().set_pixel(10, 31, true);
+        assert_eq!(flush(&mut oled), 7 + 2);
+    }
+
+    /// Records the level of a pin.
+    struct MockPin<'a>(&'a Cell<bool>);
+
+    impl<'a> OutputPin for MockPin<'a> {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    /// Records the transfers with the DC pin level, checking that CS is low.
+    struct MockSpi<'a> {
+        cs: &'a Cell<bool>,
+        dc: &'a Cell<bool>,
+        transfers: Vec<(bool, Vec<u8>)>,
+    }
+
+    impl<'a> spi::Write<u8> for MockSpi<'a> {
+        type Error = ();
+
+        fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+            assert!(!self.cs.get());
+            self.transfers.push((self.dc.get(), bytes.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spi_interface() {
+        let (cs, dc) = (Cell::new(false), Cell::new(false));
+        let spi = MockSpi {
+            cs: &cs,
+            dc: &dc,
+            transfers: Vec::new(),
+        };
+        let interface = SpiInterface::new(spi, MockPin(&cs), MockPin(&dc));
+        assert!(cs.get());
+        let mut oled = Oled::new(interface, Panel::SSD1306_128X64);
+        oled.init().unwrap();
+        oled.flush().unwrap();
+        assert!(cs.get());
+
+        let (spi, _, _) = oled.release().release();
+        let transfers = spi.transfers;
+        // Three init commands, the window and the pages, without control bytes
+        assert_eq!(transfers.len(), 3 + 1 + 8);
+        assert!(transfers[..4].iter().all(|(data, _)| !data));
+        assert_eq!(transfers[3].1, vec![0x21, 0, 127, 0x22, 0, 7]);
+        assert!(transfers[4..].iter().all(|(data, bytes)| *data && bytes.len() == 128));
+    }
+
+    static RECOVERIES: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_recovery() -> bool {
+        RECOVERIES.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    #[test]
+    fn recovers_after_an_error() {
+        let interface =
+            I2cInterface::new(MockI2c::default(), DEFAULT_ADDRESS).with_recovery(count_recovery);
+        let mut oled = Oled::new(interface, Panel::SSD1306_128X64);
+        assert_eq!(oled.update(), None);
+        assert_eq!(flush(&mut oled), 0);
+
+        oled.framebuffer().set_pixel(10, 20, true);
+        oled.interface.i2c.glitches = 1;
+        assert_eq!(oled.update(), Some(Event::Recovered));
+        assert_eq!(RECOVERIES.load(Ordering::Relaxed), 1);
+        assert_eq!(oled.recoveries(), 1);
+        assert!(oled.is_present());
+        // The whole frame was sent again after the initialization
+        assert_eq!(flush(&mut oled), 0);
+        assert!(oled.shown.pixel(10, 20));
+    }
+
+    #[test]
+    fn absent_display_is_found_again() {
+        let i2c = MockI2c {
+            down: true,
+            ..MockI2c::default()
+        };
+        let mut oled = Oled::new(I2cInterface::new(i2c, DEFAULT_ADDRESS), Panel::SSD1306_128X64);
+        assert_eq!(oled.update(), Some(Event::Lost));
+        assert!(!oled.is_present());
+
+        // Looked for again after 1 update, then 2, 4 and 8
+        let mut attempts = Vec::new();
+        for n in 1..=20 {
+            let writes = oled.interface.i2c.writes;
+            assert_eq!(oled.update(), None);
+            if oled.interface.i2c.writes != writes {
+                attempts.push(n);
+            }
+        }
+        assert_eq!(attempts, vec![2, 5, 10, 19]);
+
+        oled.interface.i2c.down = false;
+        let mut n = 20;
+        while oled.update().is_none() {
+            n += 1;
+        }
+        assert_eq!(n, 19 + 16);
+        assert!(oled.is_present());
+        assert_eq!(oled.recoveries(), 0);
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let i2c = MockI2c {
+            down: true,
+            ..MockI2c::default()
+        };
+        let mut oled = Oled::new(I2cInterface::new(i2c, DEFAULT_ADDRESS), Panel::SSD1306_128X64);
+        for _ in 0..10 * MAX_BACKOFF {
+            oled.update();
+        }
+        let writes = oled.interface.i2c.writes;
+        for _ in 0..=MAX_BACKOFF {
+            oled.update();
+        }
+        assert!(oled.interface.i2c.writes > writes);
+    }
+
+    /// Commands sent by one flush.
+    fn flush_commands(oled: &mut MockOled) -> Vec<u8> {
+        oled.interface.i2c.commands.clear();
+        oled.flush().unwrap();
+        oled.interface.i2c.commands.clone()
+    }
+
+    #[test]
+    fn settings_are_sent_once() {
+        let mut oled = oled();
+        flush(&mut oled);
+        oled.set_settings(Settings {
+            contrast: 0x10,
+            inverted: true,
+            ..Settings::default()
+        });
+        assert_eq!(flush_commands(&mut oled), vec![0x81, 0x10, 0xa1, 0xc8, 0xa7, 0xaf]);
+        assert_eq!(flush(&mut oled), 0);
+
+        // Setting the same values again sends nothing
+        oled.set_settings(oled.settings());
+        assert_eq!(flush(&mut oled), 0);
+    }
+
+    #[test]
+    fn rotation_redraws_everything() {
+        let mut oled = oled();
+        flush(&mut oled);
+        oled.set_settings(Settings {
+            rotated: true,
+            ..Settings::default()
+        });
+        oled.interface.i2c.commands.clear();
+        assert_eq!(flush(&mut oled), 7 + 7 + 8 * 129);
+        assert_eq!(&oled.interface.i2c.commands[..4], &[0x81, 0xcf, 0xa0, 0xc0]);
+    }
+
+    #[test]
+    fn power() {
+        let mut oled = oled();
+        flush(&mut oled);
+        oled.set_power(Power::Dimmed);
+        assert_eq!(flush_commands(&mut oled)[..2], [0x81, 0xcf / DIM_DIVISOR]);
+        oled.set_power(Power::Off);
+        assert_eq!(flush_commands(&mut oled)[5], 0xae);
+        oled.set_power(Power::On);
+        assert_eq!(flush_commands(&mut oled), vec![0x81, 0xcf, 0xa1, 0xc8, 0xa6, 0xaf]);
+    }
+
+    #[test]
+    fn settings_survive_a_recovery() {
+        let mut oled = oled_with(Panel::SSD1306_128X64);
+        oled.update();
+        oled.set_settings(Settings {
+            contrast: 0x20,
+            ..Settings::default()
+        });
+        oled.set_power(Power::Off);
+        // The settings fail, then the display is initialized again
+        oled.interface.i2c.glitches = 1;
+        oled.interface.i2c.commands.clear();
+        assert_eq!(oled.update(), Some(Event::Recovered));
+        let commands = &oled.interface.i2c.commands;
+        let init = &commands[SSD1306_INIT.len() + 4..SSD1306_INIT.len() + 10];
+        assert_eq!(init, &[0x81, 0x20, 0xa1, 0xc8, 0xa6, 0xae]);
+        assert_eq!(flush(&mut oled), 0);
+    }
+
+    #[test]
+    fn shifted_drawing() {
+        let mut oled = oled();
+        oled.set_shift(1, 1);
+        oled.draw(core::iter::once(Pixel(UnsignedCoord(0, 0), 1.into())));
+        assert!(oled.framebuffer().pixel(1, 1));
+        assert!(!oled.framebuffer().pixel(0, 0));
+        assert_eq!(oled.shift(), (1, 1));
+    }
+}
@@ -0,0 +1,85 @@
+//! General purpose timers in encoder mode (quadrature encoder interface).
+//!
+//! The timer counts up and down on both edges of both channels (encoder mode
+//! 3, 4 counts per quadrature cycle) with the input filter enabled.  The
+//! channel pins of the Blue Pill, without remapping:
+//!
+//! ```text
+//! TIM2: CH1 PA0, CH2 PA1
+//! TIM3: CH1 PA6, CH2 PA7
+//! TIM4: CH1 PB6, CH2 PB7
+//! ```
+//!
+//! The pins are inputs, usually with a pull-up for encoders that connect
+//! them to ground.  TIM2 is also the PWM timer of `potentiometer2.rs`, and
+//! PB6/PB7 are the I2C1 pins without remap (the display uses PB8/PB9).
+
+use stm32f1xx_hal::gpio::gpioa::{PA0, PA1, PA6, PA7};
+use stm32f1xx_hal::gpio::gpiob::{PB6, PB7};
+use stm32f1xx_hal::gpio::Input;
+use stm32f1xx_hal::pac::{self, TIM2, TIM3, TIM4};
+use stm32f1xx_hal::rcc::APB1;
+
+use crate::encoder::Counter;
+
+/// Input filter: sampling at fDTS / 32, 8 samples.
+const FILTER: u32 = 0b1111;
+
+/// The channel 1 and 2 pins of a timer.
+pub trait Pins<TIM> {}
+
+impl<M1, M2> Pins<TIM2> for (PA0<Input<M1>>, PA1<Input<M2>>) {}
+impl<M1, M2> Pins<TIM3> for (PA6<Input<M1>>, PA7<Input<M2>>) {}
+impl<M1, M2> Pins<TIM4> for (PB6<Input<M1>>, PB7<Input<M2>>) {}
+
+pub struct Qei<TIM, PINS> {
+    tim: TIM,
+    pins: PINS,
+}
+
+macro_rules! qei {
+    ($($TIMX:ident: ($timX:ident, $timXen:ident, $timXrst:ident),)+) => {
+        $(
+            impl<PINS: Pins<$TIMX>> Qei<$TIMX, PINS> {
+                pub fn $timX(tim: $TIMX, pins: PINS, _apb1: &mut APB1) -> Self {
+                    // The HAL doesn't expose the enable and reset registers
+                    let rcc = unsafe { &*pac::RCC::ptr() };
+                    rcc.apb1enr.modify(|_, w| w.$timXen().set_bit());
+                    rcc.apb1rstr.modify(|_, w| w.$timXrst().set_bit());
+                    rcc.apb1rstr.modify(|_, w| w.$timXrst().clear_bit());
+
+                    // CC1S = 01 and CC2S = 01: IC1 on TI1 and IC2 on TI2.  The
+                    // PAC only has the output mode view of CCMR1
+                    tim.ccmr1_output.write(|w| unsafe {
+                        w.bits(0b01 | FILTER << 4 | 0b01 << 8 | FILTER << 12)
+                    });
+                    // Both inputs not inverted
+                    tim.ccer.write(|w| unsafe { w.bits(0) });
+                    // SMS = 011: encoder mode 3
+                    tim.smcr.write(|w| unsafe { w.bits(0b011) });
+                    tim.arr.write(|w| unsafe { w.bits(0xffff) });
+                    tim.cr1.write(|w| w.cen().set_bit());
+
+                    Qei { tim, pins }
+                }
+
+                pub fn release(self) -> ($TIMX, PINS) {
+                    self.tim.cr1.write(|w| w.cen().clear_bit());
+                    (self.tim, self.pins)
+                }
+            }
+
+            impl<PINS> Counter for Qei<$TIMX, PINS> {
+                fn count(&self) -> u16 {
+                    self.tim.cnt.read().bits() as u16
+                }
+            }
+        )+
+    }
+}
+
+qei! {
+    TIM2: (tim2, tim2en, tim2rst),
+    TIM3: (tim3, tim3en, tim3rst),
+    TIM4: (tim4, tim4en, tim4rst),
+}
@@ -0,0 +1,140 @@
+//! Timer triggered sampling of one ADC1 channel, for `scope`.
+//!
+//! TIM3 overflows at the sample rate and its update event (TRGO) starts a
+//! conversion, so the samples are evenly spaced no matter what the CPU is
+//! doing.  The end of conversion interrupt stores them in a buffer and stops
+//! the timer once the capture is complete.  The binary has to forward it:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn ADC1_2() {
+//!     app::sampler::handle_interrupt();
+//! }
+//! ```
+
+use core::ptr::addr_of;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cortex_m::peripheral::NVIC;
+use embedded_hal::adc::Channel;
+use stm32f1xx_hal::pac::{self, Interrupt, ADC1, TIM3};
+use stm32f1xx_hal::rcc::{Clocks, APB1, APB2};
+
+use crate::adc;
+use crate::scope::{Plan, MAX_SAMPLES};
+
+/// SMPx = 010: 13.5 ADC cycles, 26 cycles per conversion.
+const SAMPLE_TIME: u32 = 0b010;
+const EOCIE: u32 = 1 << 5;
+
+// Only written by `handle_interrupt` while a capture runs
+static mut SAMPLES: [u16; MAX_SAMPLES] = [0; MAX_SAMPLES];
+static LEN: AtomicUsize = AtomicUsize::new(0);
+static WANTED: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Sampler<PIN> {
+    adc: ADC1,
+    tim: TIM3,
+    pin: PIN,
+    timer_hz: u32,
+}
+
+impl<PIN: Channel<ADC1, ID = u8>> Sampler<PIN> {
+    /// Set up ADC1 to convert `pin` on TIM3 updates.  The ADC clock is the one
+    /// chosen with `rcc.cfgr.adcclk()`.
+    pub fn new(
+        adc: ADC1,
+        tim: TIM3,
+        pin: PIN,
+        clocks: Clocks,
+        apb1: &mut APB1,
+        apb2: &mut APB2,
+    ) -> Self {
+        adc::power_up(&adc, clocks, apb2);
+        let timer_hz = adc::trigger_timer(&tim, clocks, apb1);
+
+        let channel = u32::from(PIN::channel());
+        if channel < 10 {
+            adc.smpr2
+                .write(|w| unsafe { w.bits(SAMPLE_TIME << (3 * channel)) });
+        } else {
+            adc.smpr1
+                .write(|w| unsafe { w.bits(SAMPLE_TIME << (3 * (channel - 10))) });
+        }
+        // One conversion of `channel` per trigger
+        adc.sqr1.write(|w| unsafe { w.bits(0) });
+        adc.sqr3.write(|w| unsafe { w.bits(channel) });
+        adc.cr1.write(|w| unsafe { w.bits(EOCIE) });
+        adc.cr2
+            .write(|w| unsafe { w.bits(adc::ADON | adc::EXTTRIG | adc::EXTSEL_TIM3_TRGO) });
+
+        Sampler {
+            adc,
+            tim,
+            pin,
+            timer_hz,
+        }
+    }
+
+    /// Clock of TIM3, for `Plan::new`.
+    pub fn timer_hz(&self) -> u32 {
+        self.timer_hz
+    }
+
+    /// Start a capture of `plan.samples` samples, stopping the one running.
+    pub fn start(&mut self, plan: &Plan) {
+        self.tim.cr1.write(|w| w.cen().clear_bit());
+        NVIC::mask(Interrupt::ADC1_2);
+        LEN.store(0, Ordering::Relaxed);
+        WANTED.store(plan.samples.min(MAX_SAMPLES), Ordering::Release);
+
+        self.tim
+            .psc
+            .write(|w| unsafe { w.bits(plan.prescaler - 1) });
+        self.tim.arr.write(|w| unsafe { w.bits(plan.reload - 1) });
+        unsafe { NVIC::unmask(Interrupt::ADC1_2) };
+        // Load PSC and ARR; this first update already takes a sample
+        self.tim.egr.write(|w| w.ug().set_bit());
+        self.tim.cr1.write(|w| w.cen().set_bit());
+    }
+
+    pub fn is_done(&self) -> bool {
+        LEN.load(Ordering::Acquire) >= WANTED.load(Ordering::Relaxed)
+    }
+
+    /// The samples of the last capture, empty until it is done.
+    pub fn samples(&self) -> &[u16] {
+        if !self.is_done() {
+            return &[];
+        }
+        let len = LEN.load(Ordering::Acquire);
+        // The interrupt doesn't write to the buffer until the next `start`,
+        // which needs `&mut self`
+        unsafe { slice::from_raw_parts(addr_of!(SAMPLES) as *const u16, len) }
+    }
+
+    pub fn release(self) -> (ADC1, TIM3, PIN) {
+        self.tim.cr1.write(|w| w.cen().clear_bit());
+        NVIC::mask(Interrupt::ADC1_2);
+        self.adc.cr2.write(|w| unsafe { w.bits(0) });
+        (self.adc, self.tim, self.pin)
+    }
+}
+
+/// Store a sample. Call it from the `ADC1_2` handler.
+pub fn handle_interrupt() {
+    let adc = unsafe { &*pac::ADC1::ptr() };
+    // Reading DR clears EOC
+    let sample = adc.dr.read().bits() as u16;
+    let len = LEN.load(Ordering::Relaxed);
+    let wanted = WANTED.load(Ordering::Relaxed);
+    if len < wanted {
+        unsafe { SAMPLES[len] = sample };
+        LEN.store(len + 1, Ordering::Release);
+    }
+    if len + 1 >= wanted {
+        let tim = unsafe { &*pac::TIM3::ptr() };
+        tim.cr1.write(|w| w.cen().clear_bit());
+    }
+}
@@ -0,0 +1,226 @@
+//! I2C bus scanner: which addresses answer and which parts they are.
+//!
+//! Every 7-bit address that isn't reserved is probed, and the ones that
+//! answer are identified by the address and, where the part has one, by its
+//! id register:
+//!
+//! ```text
+//! 0x3c 0x3d  SSD1306 display (SH1106 modules answer the same)
+//! 0x50-0x57  24Cxx EEPROM, a read of address 0 works
+//! 0x68 0x69  MPU6050, WHO_AM_I (0x75) is 0x68 (0x70 MPU6500, 0x71 MPU9250)
+//! 0x76 0x77  BMP280, id (0xd0) is 0x58 (0x60 BME280, 0x55 BMP180)
+//! ```
+//!
+//! A part that answers at a known address with another id, like the DS3231
+//! clock at 0x68, is reported as unknown.
+
+use core::fmt;
+
+use embedded_hal::blocking::i2c::WriteRead;
+use heapless::consts::*;
+use heapless::Vec;
+
+/// 0x00-0x07 and 0x78-0x7f are reserved by the I2C specification.
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+const MPU_WHO_AM_I: u8 = 0x75;
+const BMP_ID: u8 = 0xd0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Part {
+    Ssd1306,
+    Eeprom,
+    Mpu6050,
+    Mpu6500,
+    Mpu9250,
+    Bmp280,
+    Bme280,
+    Bmp180,
+}
+
+impl Part {
+    pub fn name(self) -> &'static str {
+        match self {
+            Part::Ssd1306 => "SSD1306",
+            Part::Eeprom => "EEPROM",
+            Part::Mpu6050 => "MPU6050",
+            Part::Mpu6500 => "MPU6500",
+            Part::Mpu9250 => "MPU9250",
+            Part::Bmp280 => "BMP280",
+            Part::Bme280 => "BME280",
+            Part::Bmp180 => "BMP180",
+        }
+    }
+}
+
+/// An address that answered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Device {
+    pub addr: u8,
+    pub part: Option<Part>,
+}
+
+/// "0x3c SSD1306", or "0x42 ?" for unknown parts.
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.part.map(Part::name).unwrap_or("?");
+        write!(f, "0x{:02x} {}", self.addr, name)
+    }
+}
+
+/// The devices found on a bus, more are ignored.
+pub type Devices = Vec<Device, U16>;
+
+fn read_register<I2C: WriteRead>(i2c: &mut I2C, addr: u8, reg: u8) -> Option<u8> {
+    let mut value = [0];
+    i2c.write_read(addr, &[reg], &mut value).ok()?;
+    Some(value[0])
+}
+
+/// Which part answers at `addr`, if it is a known one.
+pub fn identify<I2C: WriteRead>(i2c: &mut I2C, addr: u8) -> Option<Part> {
+    match addr {
+        // The displays can't be read over I2C
+        0x3c | 0x3d => Some(Part::Ssd1306),
+        0x50..=0x57 => read_register(i2c, addr, 0).map(|_| Part::Eeprom),
+        0x68 | 0x69 => match read_register(i2c, addr, MPU_WHO_AM_I)? {
+            0x68 => Some(Part::Mpu6050),
+            0x70 => Some(Part::Mpu6500),
+            0x71 => Some(Part::Mpu9250),
+            _ => None,
+        },
+        0x76 | 0x77 => match read_register(i2c, addr, BMP_ID)? {
+            0x58 => Some(Part::Bmp280),
+            0x60 => Some(Part::Bme280),
+            0x55 => Some(Part::Bmp180),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Probe every address with `probe`, which tells whether it is acknowledged
+/// (see `i2c::probe`), and identify the devices that answer.
+pub fn scan<I2C, P>(i2c: &mut I2C, mut probe: P) -> Devices
+where
+    I2C: WriteRead,
+    P: FnMut(&mut I2C, u8) -> bool,
+{
+    let mut devices = Devices::new();
+    for addr in FIRST_ADDRESS..=LAST_ADDRESS {
+        if probe(i2c, addr) {
+            let part = identify(i2c, addr);
+            if devices.push(Device { addr, part }).is_err() {
+                break;
+            }
+        }
+    }
+    devices
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Devices by address, with their registers.
+    struct MockBus {
+        devices: std::vec::Vec<(u8, [u8; 256])>,
+        probed: std::vec::Vec<u8>,
+    }
+
+    impl MockBus {
+        fn new() -> Self {
+            MockBus {
+                devices: std::vec::Vec::new(),
+                probed: std::vec::Vec::new(),
+            }
+        }
+
+        fn with(mut self, addr: u8, reg: u8, value: u8) -> Self {
+            let mut regs = [0; 256];
+            regs[reg as usize] = value;
+            self.devices.push((addr, regs));
+            self
+        }
+
+        fn probe(&mut self, addr: u8) -> bool {
+            self.probed.push(addr);
+            self.devices.iter().any(|&(a, _)| a == addr)
+        }
+    }
+
+    impl WriteRead for MockBus {
+        type Error = ();
+
+        fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            let regs = self
+                .devices
+                .iter()
+                .find(|&&(a, _)| a == addr)
+                .map(|(_, regs)| regs)
+                .ok_or(())?;
+            let reg = bytes[0] as usize;
+            buffer.copy_from_slice(&regs[reg..reg + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    fn scan_bus(bus: &mut MockBus) -> Devices {
+        scan(bus, |bus, addr| bus.probe(addr))
+    }
+
+    #[test]
+    fn probes_the_unreserved_addresses() {
+        let mut bus = MockBus::new();
+        assert!(scan_bus(&mut bus).is_empty());
+        assert_eq!(bus.probed.len(), 112);
+        assert_eq!(bus.probed[0], 0x08);
+        assert_eq!(bus.probed[111], 0x77);
+    }
+
+    #[test]
+    fn identifies_by_signature() {
+        let mut bus = MockBus::new()
+            .with(0x3c, 0, 0)
+            .with(0x50, 0, 0xff)
+            .with(0x68, MPU_WHO_AM_I, 0x68)
+            .with(0x76, BMP_ID, 0x60)
+            .with(0x77, BMP_ID, 0x58);
+        let devices = scan_bus(&mut bus);
+        let parts: std::vec::Vec<_> = devices.iter().map(|d| (d.addr, d.part)).collect();
+        assert_eq!(
+            parts,
+            vec![
+                (0x3c, Some(Part::Ssd1306)),
+                (0x50, Some(Part::Eeprom)),
+                (0x68, Some(Part::Mpu6050)),
+                (0x76, Some(Part::Bme280)),
+                (0x77, Some(Part::Bmp280)),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_parts() {
+        // A DS3231 clock at the MPU6050 address and something elsewhere
+        let mut bus = MockBus::new().with(0x68, 0, 0x12).with(0x42, 0, 0);
+        let devices = scan_bus(&mut bus);
+        assert_eq!(devices.len(), 2);
+        assert!(devices.iter().all(|d| d.part.is_none()));
+    }
+
+    #[test]
+    fn format() {
+        let device = Device {
+            addr: 0x3c,
+            part: Some(Part::Ssd1306),
+        };
+        assert_eq!(device.to_string(), "0x3c SSD1306");
+        let device = Device {
+            addr: 0x0a,
+            part: None,
+        };
+        assert_eq!(device.to_string(), "0x0a ?");
+    }
+}
@@ -0,0 +1,619 @@
+//! Oscilloscope on the 128x64 display.
+//!
+//! A capture is a run of ADC samples taken at a fixed rate (see `sampler`).
+//! The first trigger crossing after one division of pre-trigger samples is
+//! put one division from the left edge; without a trigger the capture is
+//! shown from its start, like the auto mode of a scope.
+//!
+//! The screen shows 8 divisions of 16 pixels horizontally and 3 vertically,
+//! with 0 V at the bottom of the trace, and two lines of text:
+//!
+//! ```text
+//! +--------------------------------+
+//! |  :   :   :   :   :   :   :   : |
+//! |..:...:.../---\.:...:.../---\.: |
+//! |  :   :  /:   :\:   :  /:   :\: |
+//! |--:---:-/-:---:-\---:-/-:---:-\-|
+//! | 1ms  1.0V   1.5V/              |
+//! | H3.21 L0.12 A1.65              |
+//! +--------------------------------+
+//! ```
+//!
+//! When the timebase needs fewer samples than the ADC can take, several
+//! samples are taken per pixel and the column shows their minimum and
+//! maximum, so short spikes don't disappear.
+
+use core::fmt::Write;
+
+use embedded_graphics::drawable::Pixel;
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::unsignedcoord::UnsignedCoord;
+use embedded_graphics::Drawing;
+use heapless::consts::*;
+use heapless::String;
+
+use crate::widgets::{format_fixed, Area, Grid, Label};
+
+/// Full scale of the 12-bit ADC.
+pub const ADC_MAX: u16 = 4095;
+/// Supply voltage of the Blue Pill, the ADC reference.
+pub const VREF_MV: u32 = 3300;
+
+/// Width of the trace in pixels.
+pub const WIDTH: usize = 128;
+/// Pixels per division.
+pub const DIV: u32 = 16;
+/// Height of the trace, 3 divisions.
+pub const TRACE_HEIGHT: u32 = 3 * DIV;
+/// Column of the trigger point.
+pub const TRIGGER_X: usize = DIV as usize;
+
+/// Timebases in microseconds per division.
+pub const TIMEBASES: [u32; 12] = [
+    200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000, 1_000_000,
+];
+/// Vertical scales in millivolts per division.
+pub const SCALES: [u32; 4] = [200, 500, 1000, 2000];
+
+/// Shortest time between samples, so that the interrupt that stores them
+/// keeps up.
+pub const MIN_PERIOD_US: u32 = 10;
+/// Most samples per pixel.
+pub const MAX_DECIMATION: usize = 8;
+/// Samples of the largest capture: two screens at the largest decimation.
+pub const MAX_SAMPLES: usize = 2 * WIDTH * MAX_DECIMATION;
+
+/// Millivolts of a sample, rounded.
+pub fn to_mv(sample: u16) -> u32 {
+    let max = u32::from(ADC_MAX);
+    (u32::from(sample) * VREF_MV + max / 2) / max
+}
+
+/// The sample closest to `mv`.
+pub fn from_mv(mv: u32) -> u16 {
+    ((mv.min(VREF_MV) * u32::from(ADC_MAX) + VREF_MV / 2) / VREF_MV) as u16
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slope {
+    Rising,
+    Falling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trigger {
+    pub level: u16,
+    pub slope: Slope,
+    /// How far the signal has to go to the other side of the level before a
+    /// crossing counts, so that noise around the level doesn't trigger.
+    pub hysteresis: u16,
+}
+
+impl Trigger {
+    pub fn new(level: u16, slope: Slope) -> Self {
+        Trigger {
+            level,
+            slope,
+            hysteresis: ADC_MAX / 50,
+        }
+    }
+
+    /// Index of the first sample past the level, from `from` on.
+    pub fn find(&self, samples: &[u16], from: usize) -> Option<usize> {
+        let mut armed = false;
+        for (i, &s) in samples.iter().enumerate() {
+            match self.slope {
+                Slope::Rising => {
+                    if s < self.level.saturating_sub(self.hysteresis) {
+                        armed = true;
+                    } else if armed && s >= self.level {
+                        if i >= from {
+                            return Some(i);
+                        }
+                        armed = false;
+                    }
+                }
+                Slope::Falling => {
+                    if s > self.level.saturating_add(self.hysteresis) {
+                        armed = true;
+                    } else if armed && s <= self.level {
+                        if i >= from {
+                            return Some(i);
+                        }
+                        armed = false;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Timer and capture settings for a timebase.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plan {
+    /// Timer clock divider, PSC + 1
+    pub prescaler: u32,
+    /// Timer clocks per sample, ARR + 1
+    pub reload: u32,
+    /// Samples per pixel
+    pub decimation: usize,
+    /// Samples to capture
+    pub samples: usize,
+}
+
+impl Plan {
+    /// Settings for `us_per_div` with a timer clocked at `timer_hz`.
+    pub fn new(us_per_div: u32, timer_hz: u32) -> Self {
+        let pixel_us = u64::from(us_per_div) / u64::from(DIV);
+        let decimation = (pixel_us / u64::from(MIN_PERIOD_US))
+            .max(1)
+            .min(MAX_DECIMATION as u64) as usize;
+        // Timer clocks per sample, only limited by 16-bit PSC and ARR
+        let ticks = u64::from(us_per_div) * u64::from(timer_hz)
+            / (1_000_000 * u64::from(DIV) * decimation as u64);
+        let ticks = ticks.max(1);
+        let prescaler = ticks.div_ceil(0x1_0000);
+        Plan {
+            prescaler: prescaler as u32,
+            reload: (ticks / prescaler) as u32,
+            decimation,
+            samples: 2 * WIDTH * decimation,
+        }
+    }
+
+    /// Actual time between samples in nanoseconds.
+    pub fn period_ns(&self, timer_hz: u32) -> u32 {
+        (u64::from(self.prescaler) * u64::from(self.reload) * 1_000_000_000
+            / u64::from(timer_hz)) as u32
+    }
+}
+
+/// Minimum, maximum and average of the samples shown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: u16,
+    pub max: u16,
+    pub avg: u16,
+}
+
+impl Stats {
+    pub fn of(samples: &[u16]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut min = u16::MAX;
+        let mut max = 0;
+        let mut sum: u32 = 0;
+        for &s in samples {
+            min = min.min(s);
+            max = max.max(s);
+            sum += u32::from(s);
+        }
+        Some(Stats {
+            min,
+            max,
+            avg: (sum / samples.len() as u32) as u16,
+        })
+    }
+}
+
+/// Splits `samples` in runs of `factor` samples and writes the range of each
+/// to `columns`.  A range also reaches the last sample of the previous run,
+/// so that the trace has no gaps on steep edges.  Returns the number of
+/// columns written.
+pub fn decimate(samples: &[u16], factor: usize, columns: &mut [(u16, u16)]) -> usize {
+    let factor = factor.max(1);
+    let mut prev = None;
+    let mut n = 0;
+    for (run, column) in samples.chunks(factor).zip(columns.iter_mut()) {
+        let mut lo = prev.unwrap_or(run[0]);
+        let mut hi = lo;
+        for &s in run {
+            lo = lo.min(s);
+            hi = hi.max(s);
+        }
+        *column = (lo, hi);
+        prev = Some(run[run.len() - 1]);
+        n += 1;
+    }
+    n
+}
+
+/// What the buttons adjust.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    Timebase,
+    Scale,
+    Level,
+    Slope,
+}
+
+impl Setting {
+    pub fn next(self) -> Self {
+        match self {
+            Setting::Timebase => Setting::Scale,
+            Setting::Scale => Setting::Level,
+            Setting::Level => Setting::Slope,
+            Setting::Slope => Setting::Timebase,
+        }
+    }
+}
+
+/// Trigger level step of `Scope::adjust`.
+const LEVEL_STEP_MV: u32 = 100;
+
+pub struct Scope {
+    /// Index in `TIMEBASES`
+    pub timebase: usize,
+    /// Index in `SCALES`
+    pub scale: usize,
+    pub trigger: Trigger,
+    pub selected: Setting,
+    /// Columns of the last capture, see `decimate`
+    columns: [(u16, u16); WIDTH],
+    shown: usize,
+    stats: Option<Stats>,
+    triggered: bool,
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Scope {
+            timebase: 2,
+            scale: 2,
+            trigger: Trigger::new(from_mv(1500), Slope::Rising),
+            selected: Setting::Timebase,
+            columns: [(0, 0); WIDTH],
+            shown: 0,
+            stats: None,
+            triggered: false,
+        }
+    }
+
+    pub fn us_per_div(&self) -> u32 {
+        TIMEBASES[self.timebase]
+    }
+
+    pub fn mv_per_div(&self) -> u32 {
+        SCALES[self.scale]
+    }
+
+    /// Change the selected setting by `steps`, the indexes stop at the ends.
+    pub fn adjust(&mut self, steps: i32) {
+        fn step(index: usize, steps: i32, len: usize) -> usize {
+            (index as i32 + steps).max(0).min(len as i32 - 1) as usize
+        }
+        match self.selected {
+            Setting::Timebase => self.timebase = step(self.timebase, steps, TIMEBASES.len()),
+            Setting::Scale => self.scale = step(self.scale, steps, SCALES.len()),
+            Setting::Level => {
+                let mv = to_mv(self.trigger.level) as i32 + steps * LEVEL_STEP_MV as i32;
+                // Round to the step, samples don't map to whole millivolts
+                let mv = (mv + LEVEL_STEP_MV as i32 / 2) / LEVEL_STEP_MV as i32
+                    * LEVEL_STEP_MV as i32;
+                self.trigger.level = from_mv(mv.max(0) as u32);
+            }
+            Setting::Slope => {
+                if steps % 2 != 0 {
+                    self.trigger.slope = match self.trigger.slope {
+                        Slope::Rising => Slope::Falling,
+                        Slope::Falling => Slope::Rising,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Timer and capture settings for the current timebase.
+    pub fn plan(&self, timer_hz: u32) -> Plan {
+        Plan::new(self.us_per_div(), timer_hz)
+    }
+
+    /// Look for the trigger in a capture made with `plan` and keep the part
+    /// to show.
+    pub fn update(&mut self, samples: &[u16], plan: &Plan) {
+        let d = plan.decimation;
+        let before = TRIGGER_X * d;
+        let after = (WIDTH - TRIGGER_X) * d;
+        let last = samples.len().saturating_sub(after);
+        let found = self
+            .trigger
+            .find(samples, before)
+            .filter(|&i| i <= last);
+        self.triggered = found.is_some();
+        let start = found.map_or(0, |i| i - before);
+        let end = samples.len().min(start + WIDTH * d);
+        let shown = &samples[start..end];
+        self.shown = decimate(shown, d, &mut self.columns);
+        self.stats = Stats::of(shown);
+    }
+
+    pub fn stats(&self) -> Option<Stats> {
+        self.stats
+    }
+
+    /// Whether the trigger was found in the last capture.
+    pub fn triggered(&self) -> bool {
+        self.triggered
+    }
+
+    /// Row of the trace for a sample, clamped to the trace.
+    fn row(&self, sample: u16) -> u32 {
+        let px = to_mv(sample) * DIV / self.mv_per_div();
+        TRACE_HEIGHT - 1 - px.min(TRACE_HEIGHT - 1)
+    }
+
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D) {
+        Area::new(0, 0, WIDTH as u32, TRACE_HEIGHT).fill(disp, 0);
+
+        // Dotted division lines
+        disp.draw(
+            (0..WIDTH as u32 * TRACE_HEIGHT)
+                .map(|i| (i % WIDTH as u32, i / WIDTH as u32))
+                .filter(|&(x, y)| {
+                    let on_x = x % DIV == DIV - 1 && y % 4 == 0;
+                    let on_y = y % DIV == 0 && x % 4 == 3;
+                    on_x || on_y
+                })
+                .map(|(x, y)| Pixel(UnsignedCoord(x, y), 1.into())),
+        );
+
+        // Trigger level on the left edge, trigger point on the top
+        let level = self.row(self.trigger.level);
+        let marker = (0..3)
+            .map(|x| (x, level))
+            .chain((0..3).map(|y| (TRIGGER_X as u32, y)));
+        disp.draw(marker.map(|(x, y)| Pixel(UnsignedCoord(x, y), 1.into())));
+
+        for (x, &(lo, hi)) in self.columns[..self.shown].iter().enumerate() {
+            let (top, bottom) = (self.row(hi), self.row(lo));
+            disp.draw(
+                (top..=bottom).map(move |y| Pixel(UnsignedCoord(x as u32, y), 1.into())),
+            );
+        }
+
+        let grid = Grid::new(Area::new(0, TRACE_HEIGHT as i32, WIDTH as u32, 16), 3, 2);
+        let mut text: String<U16> = String::new();
+        format_time(&mut text, self.us_per_div());
+        self.label(disp, &text, grid.cell(0, 0), Setting::Timebase);
+
+        text = String::new();
+        format_volts(&mut text, self.mv_per_div());
+        self.label(disp, &text, grid.cell(1, 0), Setting::Scale);
+
+        text = String::new();
+        if !self.triggered {
+            let _ = text.push('?');
+        }
+        format_volts(&mut text, to_mv(self.trigger.level));
+        self.label(disp, &text, grid.cell(2, 0), Setting::Level);
+
+        let slope = match self.trigger.slope {
+            Slope::Rising => "/",
+            Slope::Falling => "\\",
+        };
+        // The slope shares the level cell, after the longest level text
+        let cell = grid.cell(2, 0);
+        let area = Area::new(cell.x + cell.width as i32 - 6, cell.y, 6, cell.height);
+        self.label(disp, slope, area, Setting::Slope);
+
+        if let Some(stats) = self.stats {
+            let values = [('H', stats.max), ('L', stats.min), ('A', stats.avg)];
+            for (col, &(name, value)) in values.iter().enumerate() {
+                text = String::new();
+                let _ = text.push(name);
+                format_fixed(&mut text, (to_mv(value) / 10) as i32, 2);
+                Label::new(&text, grid.cell(col as u32, 1)).draw(disp);
+            }
+        } else {
+            grid.span(0, 1, 3, 1).fill(disp, 0);
+        }
+    }
+
+    fn label<D: Drawing<PixelColorU8>>(&self, disp: &mut D, text: &str, area: Area, setting: Setting) {
+        Label::new(text, area)
+            .with_inverted(self.selected == setting)
+            .draw(disp);
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// "200us", "5ms", "1s".
+fn format_time<W: Write>(w: &mut W, us: u32) {
+    let _ = if us >= 1_000_000 {
+        write!(w, "{}s", us / 1_000_000)
+    } else if us >= 1000 {
+        write!(w, "{}ms", us / 1000)
+    } else {
+        write!(w, "{}us", us)
+    };
+}
+
+/// "500mV", "1.6V".
+fn format_volts<W: Write>(w: &mut W, mv: u32) {
+    if mv >= 1000 {
+        format_fixed(w, (mv / 100) as i32, 1);
+        let _ = w.write_char('V');
+    } else {
+        let _ = write!(w, "{}mV", mv);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+
+    /// A square wave between `lo` and `hi`, `period` samples long, with some
+    /// noise.
+    fn square(len: usize, period: usize, lo: u16, hi: u16) -> Vec<u16> {
+        (0..len)
+            .map(|i| {
+                let noise = [0, 30, 0, 0, 25, 0, 10][i % 7];
+                if i % period < period / 2 {
+                    lo + noise
+                } else {
+                    hi - noise
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trigger_slopes() {
+        let samples = square(100, 20, 1000, 3000);
+        let rising = Trigger::new(2000, Slope::Rising);
+        assert_eq!(rising.find(&samples, 0), Some(10));
+        assert_eq!(rising.find(&samples, 11), Some(30));
+        let falling = Trigger::new(2000, Slope::Falling);
+        assert_eq!(falling.find(&samples, 0), Some(20));
+        // Never crosses
+        assert_eq!(Trigger::new(3500, Slope::Rising).find(&samples, 0), None);
+    }
+
+    #[test]
+    fn trigger_ignores_noise() {
+        // Wiggles around the level, then a real edge
+        let samples = [1000, 1990, 2010, 1985, 2020, 1995, 2005, 3000, 1000, 3000];
+        let trigger = Trigger::new(2000, Slope::Rising);
+        assert_eq!(trigger.find(&samples, 0), Some(2));
+        assert_eq!(trigger.find(&samples, 3), Some(9));
+        // Starting above the level, the first rising edge is later
+        assert_eq!(trigger.find(&samples[2..], 0), Some(7));
+    }
+
+    #[test]
+    fn decimation_keeps_peaks() {
+        let mut samples = [100u16; 32];
+        samples[13] = 4000;
+        samples[20] = 0;
+        let mut columns = [(0, 0); 8];
+        assert_eq!(decimate(&samples, 4, &mut columns), 8);
+        assert_eq!(columns[2], (100, 100));
+        assert_eq!(columns[3], (100, 4000));
+        assert_eq!(columns[5], (0, 100));
+
+        // Steps join the previous column
+        let samples = [0, 0, 1000, 1000, 1000, 1000];
+        assert_eq!(decimate(&samples, 2, &mut columns), 3);
+        assert_eq!(&columns[..3], &[(0, 0), (0, 1000), (1000, 1000)]);
+
+        // Not enough room, or a short last run
+        assert_eq!(decimate(&samples, 1, &mut columns[..4]), 4);
+        assert_eq!(decimate(&samples[..5], 2, &mut columns), 3);
+        assert_eq!(columns[2], (1000, 1000));
+    }
+
+    #[test]
+    fn plans() {
+        // 8 MHz HSI, the timer runs at the APB1 clock
+        let plan = Plan::new(200, 8_000_000);
+        assert_eq!((plan.decimation, plan.prescaler, plan.reload), (1, 1, 100));
+        assert_eq!(plan.period_ns(8_000_000), 12_500);
+        assert_eq!(plan.samples, 256);
+
+        // 72 MHz, 1 ms/div: 62.5 us per pixel, 6 samples
+        let plan = Plan::new(1000, 72_000_000);
+        assert_eq!(plan.decimation, 6);
+        assert_eq!(plan.period_ns(72_000_000), 10_416);
+
+        // Every timebase fits the timer and the buffer, and samples fill the
+        // screen width in the right time within 1%
+        for &clock in [8_000_000, 36_000_000, 72_000_000].iter() {
+            for &us in TIMEBASES.iter() {
+                let plan = Plan::new(us, clock);
+                assert!(plan.prescaler >= 1 && plan.prescaler <= 0x1_0000);
+                assert!(plan.reload >= 1 && plan.reload <= 0x1_0000);
+                assert!(plan.samples <= MAX_SAMPLES);
+                assert!(plan.period_ns(clock) >= MIN_PERIOD_US * 1000);
+                let screen_ns = u64::from(plan.period_ns(clock))
+                    * (WIDTH * plan.decimation) as u64;
+                let expected = u64::from(us) * 8 * 1000;
+                assert!(screen_ns * 100 >= expected * 99, "{} {}", clock, us);
+                assert!(screen_ns * 100 <= expected * 101, "{} {}", clock, us);
+            }
+        }
+    }
+
+    #[test]
+    fn stats() {
+        assert_eq!(Stats::of(&[]), None);
+        let stats = Stats::of(&[10, 20, 30, 40]).unwrap();
+        assert_eq!((stats.min, stats.max, stats.avg), (10, 40, 25));
+    }
+
+    #[test]
+    fn millivolts() {
+        assert_eq!(to_mv(0), 0);
+        assert_eq!(to_mv(ADC_MAX), VREF_MV);
+        assert_eq!(from_mv(VREF_MV), ADC_MAX);
+        assert_eq!(from_mv(5000), ADC_MAX);
+        for &mv in [0, 100, 1650, 3200].iter() {
+            assert!((to_mv(from_mv(mv)) as i32 - mv as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn settings() {
+        let mut scope = Scope::new();
+        scope.adjust(-10);
+        assert_eq!(scope.us_per_div(), TIMEBASES[0]);
+        scope.adjust(100);
+        assert_eq!(scope.us_per_div(), TIMEBASES[TIMEBASES.len() - 1]);
+
+        scope.selected = scope.selected.next().next();
+        assert_eq!(scope.selected, Setting::Level);
+        scope.adjust(1);
+        assert_eq!(to_mv(scope.trigger.level), 1600);
+        scope.adjust(-100);
+        assert_eq!(scope.trigger.level, 0);
+
+        scope.selected = scope.selected.next();
+        scope.adjust(1);
+        assert_eq!(scope.trigger.slope, Slope::Falling);
+        scope.adjust(2);
+        assert_eq!(scope.trigger.slope, Slope::Falling);
+    }
+
+    #[test]
+    fn trace() {
+        let mut scope = Scope::new();
+        scope.trigger.level = from_mv(500);
+        let plan = Plan::new(200, 8_000_000);
+        // 0 and 1 V, the rising edges at 0, 40, 80... plus a phase of 25
+        let samples: Vec<u16> = (0..plan.samples)
+            .map(|i| if (i + 25) % 40 < 20 { 0 } else { from_mv(1000) })
+            .collect();
+        scope.update(&samples, &plan);
+        assert!(scope.triggered());
+        let stats = scope.stats().unwrap();
+        assert_eq!((to_mv(stats.min), to_mv(stats.max)), (0, 1000));
+
+        let mut fb = Framebuffer::new();
+        scope.draw(&mut fb);
+        // 1 V/div: 1 V is one division above the bottom row
+        let low = TRACE_HEIGHT - 1;
+        let high = low - DIV;
+        // The rising edge is at the trigger point
+        assert!(fb.pixel(TRIGGER_X as u32 - 1, low) && !fb.pixel(TRIGGER_X as u32 - 1, high));
+        assert!(fb.pixel(TRIGGER_X as u32 + 1, high) && !fb.pixel(TRIGGER_X as u32 + 1, low));
+        // The edge is a vertical line
+        for y in high..=low {
+            assert!(fb.pixel(TRIGGER_X as u32, y));
+        }
+
+        // Without a trigger the capture is shown from the start
+        scope.trigger.level = from_mv(2000);
+        scope.update(&samples, &plan);
+        assert!(!scope.triggered());
+        let mut fb = Framebuffer::new();
+        scope.draw(&mut fb);
+        assert!(fb.pixel(0, high) && fb.pixel(20, low));
+    }
+}
@@ -0,0 +1,50 @@
+//! Screens of the display examples, drawn on anything that implements
+//! `Drawing`: the SSD1306 in the examples, a `Framebuffer` in the tests.
+
+use embedded_graphics::fonts::Font6x8;
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::prelude::*;
+
+use crate::widgets::{Area, Readout};
+
+/// "Hello world!" with "Hello Rust!" underneath (`examples/display.rs`).
+pub fn hello<D: Drawing<PixelColorU8>>(disp: &mut D) {
+    disp.draw(
+        Font6x8::render_str("Hello world!")
+            .with_stroke(Some(1u8.into()))
+            .into_iter(),
+    );
+    disp.draw(
+        Font6x8::render_str("Hello Rust!")
+            .with_stroke(Some(1u8.into()))
+            .translate(Coord::new(0, 16))
+            .into_iter(),
+    );
+}
+
+/// The counter right aligned in the middle of the screen
+/// (`examples/display2.rs`).
+pub fn counter<D: Drawing<PixelColorU8>>(disp: &mut D, counter: u32) {
+    Readout::new(counter as i32, Area::new(0, 28, 128, 16)).draw(disp);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::snapshot::assert_snapshot;
+    use crate::framebuffer::Framebuffer;
+
+    #[test]
+    fn hello_screen() {
+        let mut fb = Framebuffer::new();
+        hello(&mut fb);
+        assert_snapshot("hello", &fb);
+    }
+
+    #[test]
+    fn counter_screen() {
+        let mut fb = Framebuffer::new();
+        counter(&mut fb, 1234);
+        assert_snapshot("counter", &fb);
+    }
+}
@@ -0,0 +1,158 @@
+//! Burn-in protection for displays that stay on.
+//!
+//! OLED pixels age with use, the ones always lit (labels, frames) faster, and
+//! end up visible on a blank screen.  The screensaver dims the display after
+//! a while without activity (button presses, commands...) and turns it off
+//! later.  Activity turns it back on.
+//!
+//! Meanwhile everything drawn is moved by a pixel every `Timings::shift`,
+//! around a 2x2 square, so that no pixel is lit all the time.
+//!
+//! ```ignore
+//! if button.poll(now).is_some() {
+//!     saver.activity(now);
+//! }
+//! saver.apply(&mut disp, now);
+//! draw(&mut disp);
+//! disp.update();
+//! ```
+
+use crate::oled::{Interface, Oled, Power};
+
+/// Positions of the content, one after the other.
+const SHIFTS: [(u32, u32); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+/// Milliseconds, 0 disables the step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timings {
+    /// Without activity, until the display is dimmed
+    pub dim: u32,
+    /// Without activity, until the display is turned off
+    pub off: u32,
+    /// Between moves of the content
+    pub shift: u32,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings {
+            dim: 30_000,
+            off: 120_000,
+            shift: 60_000,
+        }
+    }
+}
+
+pub struct Screensaver {
+    timings: Timings,
+    /// Time of the last activity
+    last: u32,
+    enabled: bool,
+}
+
+impl Screensaver {
+    pub fn new(timings: Timings, now: u32) -> Self {
+        Screensaver {
+            timings,
+            last: now,
+            enabled: true,
+        }
+    }
+
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    pub fn set_timings(&mut self, timings: Timings) {
+        self.timings = timings;
+    }
+
+    /// Disabled, the display stays on and doesn't move.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Something happened, turn the display on for a while.
+    pub fn activity(&mut self, now: u32) {
+        self.last = now;
+    }
+
+    /// How the display should be at `now`.
+    pub fn power(&self, now: u32) -> Power {
+        let idle = now.wrapping_sub(self.last);
+        let after = |ms: u32| self.enabled && ms != 0 && idle >= ms;
+        if after(self.timings.off) {
+            Power::Off
+        } else if after(self.timings.dim) {
+            Power::Dimmed
+        } else {
+            Power::On
+        }
+    }
+
+    /// Offset of the content at `now`.
+    pub fn shift(&self, now: u32) -> (u32, u32) {
+        if !self.enabled || self.timings.shift == 0 {
+            return (0, 0);
+        }
+        SHIFTS[(now / self.timings.shift) as usize % SHIFTS.len()]
+    }
+
+    /// Set the power and the shift of the display.  The shift applies to what
+    /// is drawn next.
+    pub fn apply<DI: Interface>(&self, oled: &mut Oled<DI>, now: u32) {
+        oled.set_power(self.power(now));
+        let (dx, dy) = self.shift(now);
+        oled.set_shift(dx, dy);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dims_then_turns_off() {
+        let mut saver = Screensaver::new(Timings::default(), 0);
+        assert_eq!(saver.power(29_999), Power::On);
+        assert_eq!(saver.power(30_000), Power::Dimmed);
+        assert_eq!(saver.power(120_000), Power::Off);
+        saver.activity(200_000);
+        assert_eq!(saver.power(200_001), Power::On);
+        assert_eq!(saver.power(230_000), Power::Dimmed);
+    }
+
+    #[test]
+    fn disabled_steps() {
+        let timings = Timings {
+            dim: 0,
+            ..Timings::default()
+        };
+        let mut saver = Screensaver::new(timings, 0);
+        assert_eq!(saver.power(100_000), Power::On);
+        assert_eq!(saver.power(120_000), Power::Off);
+        saver.set_enabled(false);
+        assert_eq!(saver.power(1_000_000), Power::On);
+        assert_eq!(saver.shift(60_000), (0, 0));
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut saver = Screensaver::new(Timings::default(), 0);
+        saver.activity(u32::MAX - 1000);
+        assert_eq!(saver.power(1000), Power::On);
+        assert_eq!(saver.power(29_000), Power::Dimmed);
+    }
+
+    #[test]
+    fn shifts_around_a_square() {
+        let saver = Screensaver::new(Timings::default(), 0);
+        let shifts: std::vec::Vec<_> = (0..5).map(|n| saver.shift(n * 60_000)).collect();
+        assert_eq!(shifts, vec![(0, 0), (1, 0), (1, 1), (0, 1), (0, 0)]);
+        assert_eq!(saver.shift(59_999), (0, 0));
+    }
+}
@@ -0,0 +1,310 @@
+//! Scan sequences of ADC1 and the timer that triggers them, for `stream`.
+//!
+//! In scan mode a trigger converts every channel of the sequence, one after
+//! the other.  A conversion takes the sample time of the channel plus 12.5
+//! ADC cycles, and the whole sequence has to fit in a period of the timer:
+//!
+//! ```text
+//! 3 channels at 55.5 cycles, 12 MHz ADC clock: 3 * 68 / 12 MHz = 17 us,
+//! up to ~58 kHz
+//! ```
+//!
+//! The timer period is a whole number of timer clocks, split into a
+//! prescaler and a reload value of up to 65536 each.  Some rates can't be
+//! made exactly (44.1 kHz from 72 MHz), `Timing` then takes the nearest one
+//! and says so.
+
+use heapless::consts::*;
+use heapless::Vec;
+
+pub const MAX_CHANNELS: usize = 16;
+/// 0 to 15 are pins, 16 the temperature sensor and 17 Vrefint.
+pub const MAX_CHANNEL: u8 = 17;
+/// ADC cycles of a conversion after the sampling.
+const CONVERSION_HALF_CYCLES: u32 = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    TooManyChannels,
+    /// Not a channel of ADC1
+    Channel,
+    /// No channels in the sequence
+    Empty,
+    /// The rate is 0, or out of reach of the timer
+    Rate,
+    /// The sequence takes longer than the period
+    TooFast,
+    /// The blocks don't fit in the buffer of `stream`
+    Buffer,
+}
+
+/// Sampling time, in ADC cycles.  Sources with a high impedance need a
+/// longer one to charge the sampling capacitor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleTime {
+    T1_5,
+    T7_5,
+    T13_5,
+    T28_5,
+    T41_5,
+    T55_5,
+    T71_5,
+    T239_5,
+}
+
+impl SampleTime {
+    /// SMPx field value.
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Sampling and conversion, in half ADC cycles.
+    pub fn conversion_half_cycles(self) -> u32 {
+        let sampling = match self {
+            SampleTime::T1_5 => 3,
+            SampleTime::T7_5 => 15,
+            SampleTime::T13_5 => 27,
+            SampleTime::T28_5 => 57,
+            SampleTime::T41_5 => 83,
+            SampleTime::T55_5 => 111,
+            SampleTime::T71_5 => 143,
+            SampleTime::T239_5 => 479,
+        };
+        sampling + CONVERSION_HALF_CYCLES
+    }
+}
+
+/// The channels converted on every trigger, in order.  A channel may appear
+/// more than once, with the same sample time.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sequence {
+    channels: Vec<(u8, SampleTime), U16>,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Sequence {
+            channels: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, channel: u8, sample_time: SampleTime) -> Result<(), Error> {
+        if channel > MAX_CHANNEL {
+            return Err(Error::Channel);
+        }
+        self.channels
+            .push((channel, sample_time))
+            .map_err(|_| Error::TooManyChannels)
+    }
+
+    /// Build a sequence from a list.
+    pub fn of(channels: &[(u8, SampleTime)]) -> Result<Self, Error> {
+        let mut sequence = Sequence::new();
+        for &(channel, sample_time) in channels {
+            sequence.push(channel, sample_time)?;
+        }
+        Ok(sequence)
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    pub fn channels(&self) -> &[(u8, SampleTime)] {
+        &self.channels
+    }
+
+    /// SQR1, SQR2 and SQR3: the channels 5 bits each, from the first one in
+    /// SQR3, and the length - 1 in bits 20-23 of SQR1.
+    pub fn sqr(&self) -> [u32; 3] {
+        let mut sqr = [0; 3];
+        for (i, &(channel, _)) in self.channels.iter().enumerate() {
+            sqr[2 - i / 6] |= u32::from(channel) << (5 * (i % 6));
+        }
+        if !self.channels.is_empty() {
+            sqr[0] |= (self.channels.len() as u32 - 1) << 20;
+        }
+        sqr
+    }
+
+    /// SMPR1 (channels 10-17) and SMPR2 (channels 0-9), 3 bits per channel.
+    /// The other channels keep the shortest time.
+    pub fn smpr(&self) -> [u32; 2] {
+        let mut smpr = [0; 2];
+        for &(channel, sample_time) in self.channels.iter() {
+            let channel = u32::from(channel);
+            let (reg, shift) = if channel < 10 {
+                (1, 3 * channel)
+            } else {
+                (0, 3 * (channel - 10))
+            };
+            smpr[reg] = (smpr[reg] & !(0b111 << shift)) | (sample_time.bits() << shift);
+        }
+        smpr
+    }
+
+    /// How long the conversions take, rounded up.
+    pub fn duration_ns(&self, adc_hz: u32) -> u32 {
+        let half_cycles: u32 = self
+            .channels
+            .iter()
+            .map(|&(_, sample_time)| sample_time.conversion_half_cycles())
+            .sum();
+        let ns = u64::from(half_cycles) * 500_000_000;
+        ns.div_ceil(u64::from(adc_hz)) as u32
+    }
+}
+
+/// Prescaler and reload of a timer, both from 1 to 65536.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub prescaler: u32,
+    pub reload: u32,
+}
+
+impl Timing {
+    /// The timing closest to `rate_hz`, exact if possible.
+    pub fn new(timer_hz: u32, rate_hz: u32) -> Result<Timing, Error> {
+        if rate_hz == 0 {
+            return Err(Error::Rate);
+        }
+        let ticks = (timer_hz + rate_hz / 2) / rate_hz;
+        // A reload of 1 would stop the timer
+        if ticks < 2 || u64::from(ticks) > 65536 * 65536 {
+            return Err(Error::Rate);
+        }
+        let min_prescaler = ticks.div_ceil(65536);
+        // The smallest prescaler that divides the ticks keeps the resolution
+        let exact = (min_prescaler..=65536.min(ticks))
+            .find(|&p| ticks.is_multiple_of(p) && ticks / p <= 65536);
+        let prescaler = exact.unwrap_or(min_prescaler);
+        Ok(Timing {
+            prescaler,
+            reload: (ticks + prescaler / 2) / prescaler,
+        })
+    }
+
+    /// Timer clocks per period.
+    pub fn ticks(&self) -> u32 {
+        self.prescaler * self.reload
+    }
+
+    /// The actual rate, in millihertz.
+    pub fn rate_millihz(&self, timer_hz: u32) -> u32 {
+        let ticks = u64::from(self.ticks());
+        ((u64::from(timer_hz) * 1000 + ticks / 2) / ticks) as u32
+    }
+
+    pub fn is_exact(&self, timer_hz: u32, rate_hz: u32) -> bool {
+        u64::from(self.ticks()) * u64::from(rate_hz) == u64::from(timer_hz)
+    }
+
+    pub fn period_ns(&self, timer_hz: u32) -> u32 {
+        (u64::from(self.ticks()) * 1_000_000_000 / u64::from(timer_hz)) as u32
+    }
+
+    /// Whether `sequence` is converted within a period.
+    pub fn check(&self, sequence: &Sequence, timer_hz: u32, adc_hz: u32) -> Result<(), Error> {
+        if sequence.is_empty() {
+            Err(Error::Empty)
+        } else if sequence.duration_ns(adc_hz) >= self.period_ns(timer_hz) {
+            Err(Error::TooFast)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sequence_registers() {
+        let mut sequence = Sequence::new();
+        for channel in 0..8 {
+            sequence.push(channel, SampleTime::T1_5).unwrap();
+        }
+        sequence.push(16, SampleTime::T239_5).unwrap();
+        let [sqr1, sqr2, sqr3] = sequence.sqr();
+        assert_eq!(sqr3, 1 << 5 | 2 << 10 | 3 << 15 | 4 << 20 | 5 << 25);
+        assert_eq!(sqr2, 6 | 7 << 5 | 16 << 10);
+        assert_eq!(sqr1, 8 << 20);
+
+        let [smpr1, smpr2] = sequence.smpr();
+        assert_eq!(smpr1, 0b111 << 18);
+        assert_eq!(smpr2, 0);
+    }
+
+    #[test]
+    fn sample_times() {
+        let sequence = Sequence::of(&[(9, SampleTime::T55_5), (10, SampleTime::T7_5)]).unwrap();
+        assert_eq!(sequence.smpr(), [0b001, 0b101 << 27]);
+        // 68 and 20 cycles at 12 MHz
+        assert_eq!(sequence.duration_ns(12_000_000), 7334);
+    }
+
+    #[test]
+    fn sequence_errors() {
+        assert_eq!(Sequence::of(&[(18, SampleTime::T1_5)]), Err(Error::Channel));
+        let mut sequence = Sequence::new();
+        for _ in 0..MAX_CHANNELS {
+            sequence.push(0, SampleTime::T1_5).unwrap();
+        }
+        assert_eq!(
+            sequence.push(0, SampleTime::T1_5),
+            Err(Error::TooManyChannels)
+        );
+        assert_eq!(sequence.sqr()[0] >> 20, 15);
+    }
+
+    #[test]
+    fn exact_rates() {
+        for &rate in &[1, 10, 1000, 40_000, 48_000, 100_000, 1_000_000] {
+            let timing = Timing::new(72_000_000, rate).unwrap();
+            assert!(timing.is_exact(72_000_000, rate), "{} Hz", rate);
+            assert!(timing.prescaler <= 65536 && timing.reload <= 65536);
+            assert_eq!(timing.rate_millihz(72_000_000), rate * 1000);
+        }
+        // The resolution is kept when possible
+        assert_eq!(Timing::new(72_000_000, 1000).unwrap().prescaler, 2);
+    }
+
+    #[test]
+    fn inexact_rates() {
+        let timing = Timing::new(72_000_000, 44_100).unwrap();
+        assert!(!timing.is_exact(72_000_000, 44_100));
+        assert_eq!(timing.ticks(), 1633);
+        assert_eq!(timing.rate_millihz(72_000_000), 44_090_631);
+
+        // 7 * 11_000_000 ticks, a prime factor too large for the reload
+        let timing = Timing::new(77_000_000, 7).unwrap();
+        assert!(timing.prescaler <= 65536 && timing.reload <= 65536);
+        let error = timing.rate_millihz(77_000_000) as i32 - 7000;
+        assert!(error.abs() < 10, "{}", error);
+
+        assert_eq!(Timing::new(72_000_000, 0), Err(Error::Rate));
+        assert_eq!(Timing::new(72_000_000, 100_000_000), Err(Error::Rate));
+    }
+
+    #[test]
+    fn too_fast() {
+        let sequence = Sequence::of(&[(0, SampleTime::T239_5), (1, SampleTime::T239_5)]).unwrap();
+        // 2 * 252 cycles at 12 MHz: 42 us
+        let slow = Timing::new(72_000_000, 20_000).unwrap();
+        let fast = Timing::new(72_000_000, 25_000).unwrap();
+        assert_eq!(slow.check(&sequence, 72_000_000, 12_000_000), Ok(()));
+        assert_eq!(
+            fast.check(&sequence, 72_000_000, 12_000_000),
+            Err(Error::TooFast)
+        );
+        assert_eq!(
+            slow.check(&Sequence::new(), 72_000_000, 12_000_000),
+            Err(Error::Empty)
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Hobby servos driven by a PWM channel.
+//!
+//! A servo expects a pulse every 20 ms (50 Hz), its width telling the
+//! position: 1500 us is the middle, the ends are somewhere between 500 and
+//! 2500 us depending on the model.  Beyond them the servo pushes against its
+//! end stop, so `Config` keeps the endpoints of the servo at hand, found by
+//! trying, and the angles in between map linearly to them.
+//!
+//! A timer makes up to four servos, one per channel, all at 50 Hz:
+//!
+//! ```ignore
+//! let (c1, c2, c3, c4) = dp.TIM2.pwm(pins, &mut afio.mapr, 50.hz(), clocks, &mut rcc.apb1);
+//! let mut servo = Servo::new(c1, Config::default());
+//! servo.set_angle(90);
+//! loop {
+//!     servo.update(time.millis());
+//! }
+//! ```
+//!
+//! A servo moves as fast as it can to the position of the pulse, which
+//! jerks the arm and the load.  With a `Config::speed`, `update` moves the
+//! pulse to the target a little at a time instead (`slew`).
+//!
+//! The timer counts whole ticks: `duty` and `pulse_us` convert with the
+//! period of the timer, which the HAL makes as close to 20 ms as its
+//! prescaler allows, a tick being 1/3 us at 72 MHz.
+
+use embedded_hal::PwmPin;
+
+use crate::mapping::Mapping;
+use crate::slew::Slew;
+
+/// Pulses per second.
+pub const FREQUENCY_HZ: u32 = 50;
+pub const PERIOD_US: u32 = 1_000_000 / FREQUENCY_HZ;
+/// The widest range of pulses.
+pub const MIN_PULSE_US: u32 = 500;
+pub const MAX_PULSE_US: u32 = 2500;
+/// Largest 12-bit ADC reading, for `Servo::follow`.
+const FULL_SCALE: u32 = 4095;
+/// Readings at the ends of a potentiometer that map to the endpoints.
+const FOLLOW_DEAD_ZONE: u32 = 16;
+
+/// Duty of a pulse of `pulse_us`, on a timer whose period (20 ms) is
+/// `max_duty` + 1 ticks.
+pub fn duty(pulse_us: u32, max_duty: u16) -> u16 {
+    let ticks = u64::from(pulse_us) * (u64::from(max_duty) + 1);
+    let duty = (ticks + u64::from(PERIOD_US) / 2) / u64::from(PERIOD_US);
+    duty.min(u64::from(max_duty)) as u16
+}
+
+/// The width of a pulse of `duty`, rounded to the microsecond.
+pub fn pulse_us(duty: u16, max_duty: u16) -> u32 {
+    let ticks = u32::from(max_duty) + 1;
+    ((u64::from(duty) * u64::from(PERIOD_US) + u64::from(ticks) / 2) / u64::from(ticks)) as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// Pulse at 0 degrees
+    pub min_us: u32,
+    /// Pulse at `range_deg`
+    pub max_us: u32,
+    /// Degrees between the endpoints
+    pub range_deg: u32,
+    /// Microseconds of pulse per second, 0 for no limit
+    pub speed: u32,
+}
+
+impl Default for Config {
+    /// The range of most servos, 1000 to 2000 us for 180 degrees: safe to
+    /// start with, the endpoints are usually farther.
+    fn default() -> Self {
+        Config {
+            min_us: 1000,
+            max_us: 2000,
+            range_deg: 180,
+            speed: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Endpoints within `MIN_PULSE_US` and `MAX_PULSE_US`, in order.  A servo
+    /// that turns the other way has `min_us` > `max_us`.
+    pub fn with_endpoints(mut self, min_us: u32, max_us: u32) -> Self {
+        self.min_us = min_us.clamp(MIN_PULSE_US, MAX_PULSE_US);
+        self.max_us = max_us.clamp(MIN_PULSE_US, MAX_PULSE_US);
+        self
+    }
+
+    pub fn with_speed(mut self, speed: u32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// The smallest and largest pulse.
+    fn limits(&self) -> (u32, u32) {
+        (self.min_us.min(self.max_us), self.min_us.max(self.max_us))
+    }
+
+    fn angles(&self) -> Mapping {
+        Mapping::new(0, self.range_deg, self.min_us, self.max_us)
+    }
+}
+
+pub struct Servo<P> {
+    pin: P,
+    max_duty: u16,
+    config: Config,
+    /// Pulse sent and pulse to reach, in nanoseconds for the speed limit to
+    /// move by less than a microsecond per update.  Microseconds per second
+    /// are nanoseconds per millisecond.
+    pulse: Slew,
+}
+
+impl<P: PwmPin<Duty = u16>> Servo<P> {
+    /// Start with a pulse in the middle of the endpoints.  `pin` has to be
+    /// at `FREQUENCY_HZ`.
+    pub fn new(mut pin: P, config: Config) -> Self {
+        let max_duty = pin.get_max_duty();
+        let middle = (config.min_us + config.max_us) / 2 * 1000;
+        pin.set_duty(duty(middle / 1000, max_duty));
+        pin.enable();
+        Servo {
+            pin,
+            max_duty,
+            config,
+            pulse: Slew::new(middle).with_rate(config.speed, 1),
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// New endpoints or speed.  The target is kept within the endpoints.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.pulse.set_rate(config.speed, 1);
+        self.set_pulse_us(self.target_us());
+    }
+
+    /// The pulse to reach, within the endpoints.  Without a speed limit it is
+    /// sent right away.
+    pub fn set_pulse_us(&mut self, pulse_us: u32) {
+        let (min, max) = self.config.limits();
+        self.pulse.set_target(pulse_us.clamp(min, max) * 1000);
+        if !self.pulse.is_limited() {
+            self.write();
+        }
+    }
+
+    /// Angle from 0 to `range_deg`, clamped.
+    pub fn set_angle(&mut self, angle: u32) {
+        let pulse_us = self.config.angles().map(angle);
+        self.set_pulse_us(pulse_us);
+    }
+
+    /// Follow a potentiometer: a 12-bit reading from 0 to the full scale
+    /// sets the pulse from one endpoint to the other.
+    pub fn follow(&mut self, reading: u16) {
+        let mapping = Mapping::new(0, FULL_SCALE, self.config.min_us, self.config.max_us)
+            .with_dead_zone(FOLLOW_DEAD_ZONE);
+        self.set_pulse_us(mapping.map(u32::from(reading)));
+    }
+
+    /// The pulse sent.
+    pub fn pulse_us(&self) -> u32 {
+        self.pulse.value() / 1000
+    }
+
+    pub fn target_us(&self) -> u32 {
+        self.pulse.target() / 1000
+    }
+
+    /// The angle of the pulse sent, rounded.
+    pub fn angle(&self) -> u32 {
+        let (min, max) = (self.config.min_us as i32, self.config.max_us as i32);
+        if min == max {
+            return 0;
+        }
+        let from_min = self.pulse_us() as i32 - min;
+        let span = max - min;
+        let range = self.config.range_deg as i32;
+        ((from_min * range + span / 2) / span).clamp(0, range) as u32
+    }
+
+    /// Whether the pulse sent isn't the target yet.
+    pub fn is_moving(&self) -> bool {
+        self.pulse.is_moving()
+    }
+
+    /// Move the pulse toward the target at the configured speed.  Call it
+    /// often, every pulse (20 ms) or more.
+    pub fn update(&mut self, now: u32) {
+        let moving = self.is_moving();
+        self.pulse.update(now);
+        if moving {
+            self.write();
+        }
+    }
+
+    fn write(&mut self) {
+        let duty = duty(self.pulse_us(), self.max_duty);
+        self.pin.set_duty(duty);
+    }
+
+    /// Stop the pulses, the servo goes limp, and give the pin back.
+    pub fn release(mut self) -> P {
+        self.pin.disable();
+        self.pin
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::MockPwm;
+
+    /// The prescaler and auto-reload of the HAL for `freq`:
+    /// `psc = ticks / 2^16`, `arr = ticks / (psc + 1)`, the period being
+    /// `(psc + 1) * (arr + 1)` ticks and `get_max_duty()` `arr`.
+    fn hal_timer(timer_hz: u32, freq: u32) -> (u32, u16) {
+        let ticks = timer_hz / freq;
+        let psc = ticks / (1 << 16);
+        (psc, (ticks / (psc + 1)) as u16)
+    }
+
+    /// 72 MHz (HSE and PLL, APB1 at 36 MHz doubled for the timers), 64 MHz
+    /// (HSI and PLL), 8 MHz (HSI).
+    #[test]
+    fn pulses_at_the_configured_clocks() {
+        for &timer_hz in &[72_000_000, 64_000_000, 8_000_000] {
+            let (psc, max_duty) = hal_timer(timer_hz, FREQUENCY_HZ);
+            let tick_ns = 1e9 * (psc + 1) as f64 / timer_hz as f64;
+            let period_us = tick_ns * (max_duty as f64 + 1.0) / 1000.0;
+            assert!((period_us - 20_000.0).abs() < 1.0, "{} Hz", timer_hz);
+            for pulse in (MIN_PULSE_US..=MAX_PULSE_US).step_by(100) {
+                let duty = duty(pulse, max_duty);
+                // The pulse the servo gets, within a tick
+                let width_us = f64::from(duty) * tick_ns / 1000.0;
+                assert!(
+                    (width_us - f64::from(pulse)).abs() <= tick_ns / 1000.0,
+                    "{} us at {} Hz: {}",
+                    pulse,
+                    timer_hz,
+                    width_us
+                );
+                assert_eq!(pulse_us(duty, max_duty), pulse);
+            }
+        }
+        // 72 MHz: 22 prescaler, 3.27 ticks per microsecond
+        assert_eq!(hal_timer(72_000_000, 50), (21, 65454));
+        assert_eq!(duty(1500, 65454), 4909);
+    }
+
+    #[test]
+    fn angles() {
+        let config = Config::default().with_endpoints(600, 2400);
+        let mut servo = Servo::new(MockPwm::new(65454), config);
+        assert!(servo.pin.enabled);
+        assert_eq!(servo.pulse_us(), 1500);
+        assert_eq!(servo.angle(), 90);
+        assert_eq!(servo.pin.duty, duty(1500, 65454));
+
+        servo.set_angle(0);
+        assert_eq!(servo.pulse_us(), 600);
+        servo.set_angle(180);
+        assert_eq!(servo.pulse_us(), 2400);
+        assert_eq!(servo.angle(), 180);
+        servo.set_angle(45);
+        assert_eq!(servo.pulse_us(), 1050);
+        assert_eq!(servo.angle(), 45);
+        // Clamped to the range and the endpoints
+        servo.set_angle(270);
+        assert_eq!(servo.pulse_us(), 2400);
+        servo.set_pulse_us(3000);
+        assert_eq!(servo.pulse_us(), 2400);
+        servo.set_pulse_us(100);
+        assert_eq!(servo.pulse_us(), 600);
+
+        let pin = servo.release();
+        assert!(!pin.enabled);
+    }
+
+    #[test]
+    fn endpoints() {
+        let config = Config::default().with_endpoints(100, 9000);
+        assert_eq!((config.min_us, config.max_us), (MIN_PULSE_US, MAX_PULSE_US));
+
+        // A servo that turns the other way
+        let config = Config::default().with_endpoints(2000, 1000);
+        let mut servo = Servo::new(MockPwm::new(65454), config);
+        servo.set_angle(0);
+        assert_eq!(servo.pulse_us(), 2000);
+        servo.set_angle(180);
+        assert_eq!(servo.pulse_us(), 1000);
+        assert_eq!(servo.angle(), 180);
+        servo.set_pulse_us(500);
+        assert_eq!(servo.pulse_us(), 1000);
+
+        // Narrower endpoints bring the target in
+        servo.set_config(Config::default().with_endpoints(1200, 1800));
+        assert_eq!(servo.pulse_us(), 1200);
+    }
+
+    #[test]
+    fn slew_rate() {
+        // 1000 us per second: 20 us per pulse
+        let config = Config::default().with_speed(1000);
+        let mut servo = Servo::new(MockPwm::new(65454), config);
+        servo.update(0);
+        servo.set_angle(180);
+        assert_eq!(servo.target_us(), 2000);
+        assert_eq!(servo.pulse_us(), 1500);
+        assert!(servo.is_moving());
+        servo.update(20);
+        assert_eq!(servo.pulse_us(), 1520);
+        assert_eq!(servo.pin.duty, duty(1520, 65454));
+        servo.update(520);
+        assert_eq!(servo.pulse_us(), 2000);
+        assert!(!servo.is_moving());
+
+        // Slower than a microsecond per update still moves
+        servo.set_config(Config::default().with_speed(10));
+        servo.set_pulse_us(1000);
+        for now in (540..1540).step_by(20) {
+            servo.update(now);
+        }
+        assert_eq!(servo.pulse_us(), 1990);
+
+        // Across the wrap of the clock, 1 us in a second
+        let mut servo = Servo::new(MockPwm::new(65454), Config::default().with_speed(1));
+        servo.update(u32::MAX - 499);
+        servo.set_angle(0);
+        servo.update(500);
+        assert_eq!(servo.pulse_us(), 1499);
+    }
+
+    #[test]
+    fn follow() {
+        let config = Config::default().with_endpoints(500, 2500);
+        let mut servo = Servo::new(MockPwm::new(65454), config);
+        servo.follow(0);
+        assert_eq!(servo.pulse_us(), 500);
+        servo.follow(10);
+        assert_eq!(servo.pulse_us(), 500);
+        servo.follow(2048);
+        assert_eq!(servo.pulse_us(), 1500);
+        servo.follow(4095);
+        assert_eq!(servo.pulse_us(), 2500);
+        servo.follow(4085);
+        assert_eq!(servo.pulse_us(), 2500);
+    }
+}
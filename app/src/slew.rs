@@ -0,0 +1,182 @@
+//! A value that follows its target at a limited rate.
+//!
+//! A servo that jumps to every new position jerks the arm and its load, a
+//! motor that starts at full speed draws the stall current.  `Slew` moves
+//! the value toward the target by a step in proportion to the milliseconds
+//! since the last `update`:
+//!
+//! ```ignore
+//! let mut slew = Slew::new(0).with_rate(1000, 500);
+//! slew.set_target(1000);
+//! loop {
+//!     pwm.set_duty(slew.update(time.millis()) as u16);
+//! }
+//! ```
+//!
+//! The values are meant to be fixed point with many fractional bits, like
+//! the nanoseconds of a pulse or the billionths of a speed, so that slow
+//! rates move by less than a unit per update and still add up.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slew {
+    value: u32,
+    target: u32,
+    /// Change of the value in `per_ms` milliseconds, 0 for no limit
+    amount: u32,
+    per_ms: u32,
+    /// Time of the last `update`
+    last: Option<u32>,
+}
+
+impl Slew {
+    /// At `value`, without a limit.
+    pub fn new(value: u32) -> Self {
+        Slew {
+            value,
+            target: value,
+            amount: 0,
+            per_ms: 0,
+            last: None,
+        }
+    }
+
+    pub fn with_rate(mut self, amount: u32, per_ms: u32) -> Self {
+        self.set_rate(amount, per_ms);
+        self
+    }
+
+    /// Move by `amount` every `per_ms` milliseconds.  0 for either is no
+    /// limit: the value jumps to every new target.
+    pub fn set_rate(&mut self, amount: u32, per_ms: u32) {
+        self.amount = amount;
+        self.per_ms = per_ms;
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.amount != 0 && self.per_ms != 0
+    }
+
+    /// The value to reach, right away without a limit.
+    pub fn set_target(&mut self, target: u32) {
+        self.target = target;
+        if !self.is_limited() {
+            self.value = target;
+        }
+    }
+
+    /// Both the value and the target, at once.
+    pub fn set_value(&mut self, value: u32) {
+        self.value = value;
+        self.target = value;
+    }
+
+    pub fn target(&self) -> u32 {
+        self.target
+    }
+
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    /// Whether the value isn't the target yet.
+    pub fn is_moving(&self) -> bool {
+        self.value != self.target
+    }
+
+    /// Move the value toward the target for the time since the last update,
+    /// and return it.  The first update only starts the clock.
+    pub fn update(&mut self, now: u32) -> u32 {
+        let elapsed = self.last.map_or(0, |last| now.wrapping_sub(last));
+        self.last = Some(now);
+        if !self.is_moving() {
+            return self.value;
+        }
+        let step = if self.is_limited() {
+            // The rest of a pause too long for 32 bits is lost
+            let step = u64::from(elapsed) * u64::from(self.amount) / u64::from(self.per_ms);
+            step.min(u64::from(u32::MAX)) as u32
+        } else {
+            u32::MAX
+        };
+        self.value = if self.target > self.value {
+            self.target.min(self.value.saturating_add(step))
+        } else {
+            self.target.max(self.value.saturating_sub(step))
+        };
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate() {
+        // 1000 in 500 ms, 2 per millisecond
+        let mut slew = Slew::new(0).with_rate(1000, 500);
+        assert_eq!(slew.update(0), 0);
+        slew.set_target(1000);
+        assert!(slew.is_moving());
+        assert_eq!(slew.value(), 0);
+        assert_eq!(slew.update(10), 20);
+        assert_eq!(slew.update(260), 520);
+        assert_eq!(slew.update(510), 1000);
+        assert!(!slew.is_moving());
+        assert_eq!(slew.update(1000), 1000);
+
+        // Down at the same rate
+        slew.set_target(400);
+        assert_eq!(slew.update(1100), 800);
+        assert_eq!(slew.update(5000), 400);
+
+        slew.set_value(0);
+        assert_eq!(slew.target(), 0);
+        assert!(!slew.is_moving());
+    }
+
+    #[test]
+    fn slow_rates_add_up() {
+        // 1 in 60 ms, updated every 10 ms
+        let mut slew = Slew::new(0).with_rate(1_000_000, 60_000);
+        slew.update(0);
+        slew.set_target(1_000_000);
+        for now in 1..=600 {
+            slew.update(now * 10);
+        }
+        // 100_000, but for the rounding down of every step
+        assert_eq!(slew.value(), 99_600);
+    }
+
+    #[test]
+    fn clock_wrap() {
+        let mut slew = Slew::new(1000).with_rate(1, 1);
+        slew.update(u32::MAX - 99);
+        slew.set_target(0);
+        assert_eq!(slew.update(100), 800);
+    }
+
+    #[test]
+    fn long_pause() {
+        let mut slew = Slew::new(0).with_rate(u32::MAX, 1);
+        slew.update(0);
+        slew.set_target(u32::MAX);
+        assert_eq!(slew.update(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn no_limit() {
+        let mut slew = Slew::new(0);
+        slew.set_target(700);
+        assert_eq!(slew.value(), 700);
+        assert_eq!(slew.update(0), 700);
+
+        // Turning the limit off doesn't finish a move by itself
+        let mut slew = Slew::new(0).with_rate(1, 1);
+        slew.update(0);
+        slew.set_target(700);
+        slew.set_rate(0, 1);
+        assert!(slew.is_moving());
+        assert_eq!(slew.update(1), 700);
+    }
+}
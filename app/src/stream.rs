@@ -0,0 +1,222 @@
+//! Continuous sampling of several ADC1 channels, moved by DMA.
+//!
+//! TIM3 overflows at the sample rate and its update event (TRGO) starts a
+//! scan of the `Sequence`.  Every conversion is moved by DMA1 channel 1 to a
+//! buffer in circular mode, split in two blocks: while the DMA fills one, the
+//! other is handed to the callback, from the half transfer and transfer
+//! complete interrupts.  The samples of a block are interleaved, a frame
+//! (one sample per channel of the sequence) after the other.  The binary has
+//! to forward the interrupt:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn DMA1_CHANNEL1() {
+//!     app::stream::handle_interrupt();
+//! }
+//! ```
+//!
+//! The callback has until the DMA is done with the other block to return.
+//! When it takes longer, both blocks are filled by the next interrupt and
+//! the one it would get is already being overwritten: both are skipped and
+//! counted by `overruns`, and the block numbers count on through them.
+//!
+//! The pins of the channels have to be analog inputs, with `into_analog`.
+
+use core::cell::Cell;
+use core::ptr::addr_of;
+use core::slice;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::interrupt::{self, Mutex};
+use cortex_m::peripheral::NVIC;
+use stm32f1xx_hal::pac::{self, Interrupt, ADC1, DMA1, TIM3};
+use stm32f1xx_hal::rcc::{Clocks, AHB, APB1, APB2};
+
+use crate::adc;
+use crate::sequence::{Error, Sequence, Timing};
+
+/// Samples of both blocks.
+pub const BUFFER_SAMPLES: usize = 1024;
+
+/// Called with the samples of a block and its number, counting from 0 and
+/// through the skipped blocks.  Frame `i` of block `n` is the frame
+/// `n * frames + i` since the start.
+pub type Callback = fn(&[u16], u32);
+
+const DMA: u32 = 1 << 8;
+const SCAN: u32 = 1 << 8;
+
+const DMA_EN: u32 = 1 << 0;
+const DMA_TCIE: u32 = 1 << 1;
+const DMA_HTIE: u32 = 1 << 2;
+const DMA_CIRC: u32 = 1 << 5;
+const DMA_MINC: u32 = 1 << 7;
+/// PSIZE and MSIZE = 01: 16 bits.
+const DMA_16_BITS: u32 = (0b01 << 8) | (0b01 << 10);
+/// ISR and IFCR bits of channel 1.
+const DMA_HTIF1: u32 = 1 << 2;
+const DMA_TCIF1: u32 = 1 << 1;
+const DMA_GIF1: u32 = 1 << 0;
+
+// Written by the DMA, read by `handle_interrupt`
+static mut BUFFER: [u16; BUFFER_SAMPLES] = [0; BUFFER_SAMPLES];
+static BLOCK_LEN: AtomicU32 = AtomicU32::new(0);
+static BLOCKS: AtomicU32 = AtomicU32::new(0);
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+static CALLBACK: Mutex<Cell<Option<Callback>>> = Mutex::new(Cell::new(None));
+
+pub struct Stream {
+    adc: ADC1,
+    tim: TIM3,
+    dma: DMA1,
+    timing: Timing,
+    timer_hz: u32,
+    block_len: usize,
+}
+
+impl Stream {
+    /// Set up the scan of `sequence` at `rate_hz`, with `frames` frames per
+    /// block.  The ADC clock is the one chosen with `rcc.cfgr.adcclk()`.
+    ///
+    /// Fails if the sequence doesn't fit in a period or the blocks don't fit
+    /// in the buffer.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        adc: ADC1,
+        tim: TIM3,
+        dma: DMA1,
+        sequence: &Sequence,
+        rate_hz: u32,
+        frames: usize,
+        clocks: Clocks,
+        _ahb: &mut AHB,
+        apb1: &mut APB1,
+        apb2: &mut APB2,
+    ) -> Result<Self, Error> {
+        let timer_hz = adc::trigger_timer(&tim, clocks, apb1);
+        let timing = Timing::new(timer_hz, rate_hz)?;
+        timing.check(sequence, timer_hz, clocks.adcclk().0)?;
+        let block_len = frames * sequence.len();
+        if block_len == 0 || 2 * block_len > BUFFER_SAMPLES {
+            return Err(Error::Buffer);
+        }
+
+        adc::power_up(&adc, clocks, apb2);
+        // The HAL doesn't expose the enable register
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        rcc.ahbenr.modify(|_, w| w.dma1en().set_bit());
+
+        let [sqr1, sqr2, sqr3] = sequence.sqr();
+        let [smpr1, smpr2] = sequence.smpr();
+        adc.sqr1.write(|w| unsafe { w.bits(sqr1) });
+        adc.sqr2.write(|w| unsafe { w.bits(sqr2) });
+        adc.sqr3.write(|w| unsafe { w.bits(sqr3) });
+        adc.smpr1.write(|w| unsafe { w.bits(smpr1) });
+        adc.smpr2.write(|w| unsafe { w.bits(smpr2) });
+        adc.cr1.write(|w| unsafe { w.bits(SCAN) });
+        adc.cr2.write(|w| unsafe {
+            w.bits(adc::ADON | DMA | adc::EXTTRIG | adc::EXTSEL_TIM3_TRGO | adc::TSVREFE)
+        });
+
+        tim.psc.write(|w| unsafe { w.bits(timing.prescaler - 1) });
+        tim.arr.write(|w| unsafe { w.bits(timing.reload - 1) });
+
+        Ok(Stream {
+            adc,
+            tim,
+            dma,
+            timing,
+            timer_hz,
+            block_len,
+        })
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// The actual sample rate, in millihertz.
+    pub fn rate_millihz(&self) -> u32 {
+        self.timing.rate_millihz(self.timer_hz)
+    }
+
+    /// Samples per block.
+    pub fn block_len(&self) -> usize {
+        self.block_len
+    }
+
+    /// Start sampling, calling `callback` with every block.
+    pub fn start(&mut self, callback: Callback) {
+        self.stop();
+        interrupt::free(|cs| CALLBACK.borrow(cs).set(Some(callback)));
+        BLOCK_LEN.store(self.block_len as u32, Ordering::Relaxed);
+        BLOCKS.store(0, Ordering::Relaxed);
+        OVERRUNS.store(0, Ordering::Relaxed);
+
+        let adc = unsafe { &*pac::ADC1::ptr() };
+        let ch1 = &self.dma.ch1;
+        ch1.par
+            .write(|w| unsafe { w.bits(&adc.dr as *const _ as u32) });
+        ch1.mar
+            .write(|w| unsafe { w.bits(addr_of!(BUFFER) as u32) });
+        ch1.ndtr
+            .write(|w| unsafe { w.bits(2 * self.block_len as u32) });
+        self.dma.ifcr.write(|w| unsafe { w.bits(DMA_GIF1) });
+        ch1.cr.write(|w| unsafe {
+            w.bits(DMA_EN | DMA_TCIE | DMA_HTIE | DMA_CIRC | DMA_MINC | DMA_16_BITS)
+        });
+        unsafe { NVIC::unmask(Interrupt::DMA1_CHANNEL1) };
+
+        self.tim.cnt.write(|w| unsafe { w.bits(0) });
+        self.tim.cr1.write(|w| w.cen().set_bit());
+    }
+
+    pub fn stop(&mut self) {
+        self.tim.cr1.write(|w| w.cen().clear_bit());
+        NVIC::mask(Interrupt::DMA1_CHANNEL1);
+        self.dma.ch1.cr.write(|w| unsafe { w.bits(0) });
+        // A conversion may be left in DR
+        let _ = self.adc.dr.read();
+    }
+
+    /// Blocks filled by the DMA, the skipped ones too.
+    pub fn blocks(&self) -> u32 {
+        BLOCKS.load(Ordering::Relaxed)
+    }
+
+    /// Blocks skipped because the callback took too long.
+    pub fn overruns(&self) -> u32 {
+        OVERRUNS.load(Ordering::Relaxed)
+    }
+
+    pub fn release(mut self) -> (ADC1, TIM3, DMA1) {
+        self.stop();
+        self.adc.cr2.write(|w| unsafe { w.bits(0) });
+        (self.adc, self.tim, self.dma)
+    }
+}
+
+/// Hand the block the DMA is done with to the callback.  Call it from the
+/// `DMA1_CHANNEL1` handler.
+pub fn handle_interrupt() {
+    let dma = unsafe { &*pac::DMA1::ptr() };
+    let isr = dma.isr.read().bits();
+    dma.ifcr
+        .write(|w| unsafe { w.bits(DMA_GIF1 | DMA_HTIF1 | DMA_TCIF1) });
+    let half = isr & DMA_HTIF1 != 0;
+    let complete = isr & DMA_TCIF1 != 0;
+    if half && complete {
+        // Both blocks were filled since the last interrupt
+        BLOCKS.fetch_add(2, Ordering::Relaxed);
+        OVERRUNS.fetch_add(2, Ordering::Relaxed);
+        return;
+    }
+    let len = BLOCK_LEN.load(Ordering::Relaxed) as usize;
+    let start = if complete { len } else { 0 };
+    // The DMA writes to the other block meanwhile
+    let block = unsafe { slice::from_raw_parts((addr_of!(BUFFER) as *const u16).add(start), len) };
+    let number = BLOCKS.fetch_add(1, Ordering::Relaxed);
+    if let Some(callback) = interrupt::free(|cs| CALLBACK.borrow(cs).get()) {
+        callback(block, number);
+    }
+}
@@ -0,0 +1,52 @@
+//! Millisecond time base driven by the SysTick exception.
+//!
+//! The counter only advances if the binary forwards the exception:
+//!
+//! ```ignore
+//! #[exception]
+//! fn SysTick() {
+//!     app::time::tick();
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use stm32f1xx_hal::rcc::Clocks;
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// A free running millisecond counter. It wraps around after ~49 days, so
+/// compare instants with `wrapping_sub`.
+pub trait Millis {
+    fn millis(&self) -> u32;
+}
+
+/// Millisecond counter incremented from the SysTick exception.
+pub struct SysTickMillis {
+    _syst: SYST,
+}
+
+impl SysTickMillis {
+    /// Configure SysTick to fire every millisecond.
+    pub fn start(mut syst: SYST, clocks: Clocks) -> Self {
+        syst.set_clock_source(SystClkSource::Core);
+        syst.set_reload(clocks.sysclk().0 / 1000 - 1);
+        syst.clear_current();
+        syst.enable_counter();
+        syst.enable_interrupt();
+        SysTickMillis { _syst: syst }
+    }
+}
+
+impl Millis for SysTickMillis {
+    fn millis(&self) -> u32 {
+        TICKS.load(Ordering::Relaxed)
+    }
+}
+
+/// Advance the counter by one millisecond. Call it from the `SysTick` handler.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
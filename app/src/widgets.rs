@@ -0,0 +1,555 @@
+//! Small widgets for the 128x64 monochrome display.
+//!
+//! Every widget owns a rectangular `Area` of the screen and clears it before
+//! drawing, so a screen can be updated by redrawing only some widgets.  With
+//! `oled::Oled` only the pixels that really changed are sent to the display.
+//!
+//! ```ignore
+//! let grid = Grid::new(Area::screen(), 2, 4);
+//! Label::new("Speed", grid.cell(0, 0)).draw(&mut disp);
+//! Readout::new(1234, grid.span(0, 1, 2, 2))
+//!     .with_decimals(1)
+//!     .with_unit("rpm")
+//!     .draw(&mut disp);
+//! Gauge::new(40, 0, 100, grid.span(0, 3, 2, 1)).draw(&mut disp);
+//! ```
+
+use core::fmt::Write;
+
+use embedded_graphics::fonts::{Font12x16, Font6x8};
+use embedded_graphics::pixelcolor::PixelColorU8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rect;
+use heapless::consts::*;
+use heapless::String;
+
+/// A rectangle of the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Area {
+    pub const fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Area {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The whole 128x64 display.
+    pub const fn screen() -> Self {
+        Area::new(0, 0, 128, 64)
+    }
+
+    fn right(&self) -> i32 {
+        self.x + self.width as i32 - 1
+    }
+
+    fn bottom(&self) -> i32 {
+        self.y + self.height as i32 - 1
+    }
+
+    /// The area without a margin of `n` pixels on every side.
+    pub fn shrink(&self, n: u32) -> Area {
+        Area::new(
+            self.x + n as i32,
+            self.y + n as i32,
+            self.width.saturating_sub(2 * n),
+            self.height.saturating_sub(2 * n),
+        )
+    }
+
+    /// Fill the area with `color` (0 is off).
+    pub fn fill<D: Drawing<PixelColorU8>>(&self, disp: &mut D, color: u8) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        disp.draw(
+            Rect::new(
+                Coord::new(self.x, self.y),
+                Coord::new(self.right(), self.bottom()),
+            )
+            .with_stroke(Some(color.into()))
+            .with_fill(Some(color.into()))
+            .into_iter(),
+        );
+    }
+
+    /// Draw a one pixel wide frame along the border of the area.
+    pub fn outline<D: Drawing<PixelColorU8>>(&self, disp: &mut D) {
+        disp.draw(
+            Rect::new(
+                Coord::new(self.x, self.y),
+                Coord::new(self.right(), self.bottom()),
+            )
+            .with_stroke(Some(1u8.into()))
+            .into_iter(),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// The two fonts used by the widgets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextSize {
+    /// 6x8 pixels per character
+    Small,
+    /// 12x16 pixels per character
+    Large,
+}
+
+impl TextSize {
+    /// Width and height of a character.
+    pub fn char_size(self) -> (u32, u32) {
+        match self {
+            TextSize::Small => (6, 8),
+            TextSize::Large => (12, 16),
+        }
+    }
+}
+
+/// Draw `text` with its top left corner at `pos`.
+fn draw_text<D: Drawing<PixelColorU8>>(
+    disp: &mut D,
+    text: &str,
+    pos: Coord,
+    size: TextSize,
+    color: u8,
+) {
+    // The fonts paint the rest of every glyph cell with the fill color
+    let fill = Some((1 - color).into());
+    match size {
+        TextSize::Small => disp.draw(
+            Font6x8::render_str(text)
+                .with_stroke(Some(color.into()))
+                .with_fill(fill)
+                .translate(pos)
+                .into_iter(),
+        ),
+        TextSize::Large => disp.draw(
+            Font12x16::render_str(text)
+                .with_stroke(Some(color.into()))
+                .with_fill(fill)
+                .translate(pos)
+                .into_iter(),
+        ),
+    }
+}
+
+/// Position of a `width` pixels wide run inside `area`.
+fn align_x(area: &Area, width: u32, align: Align) -> i32 {
+    let free = area.width.saturating_sub(width) as i32;
+    match align {
+        Align::Left => area.x,
+        Align::Center => area.x + free / 2,
+        Align::Right => area.x + free,
+    }
+}
+
+/// A line of text, vertically centered in its area. Text that doesn't fit is
+/// cut.
+pub struct Label<'a> {
+    text: &'a str,
+    area: Area,
+    align: Align,
+    size: TextSize,
+    inverted: bool,
+}
+
+impl<'a> Label<'a> {
+    pub fn new(text: &'a str, area: Area) -> Self {
+        Label {
+            text,
+            area,
+            align: Align::Left,
+            size: TextSize::Small,
+            inverted: false,
+        }
+    }
+
+    pub fn with_align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_size(mut self, size: TextSize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Dark text on a lit background, e.g. for a selected item.
+    pub fn with_inverted(mut self, inverted: bool) -> Self {
+        self.inverted = inverted;
+        self
+    }
+
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D) {
+        let (background, color) = if self.inverted { (1, 0) } else { (0, 1) };
+        self.area.fill(disp, background);
+        let (cw, ch) = self.size.char_size();
+        let max_chars = (self.area.width / cw) as usize;
+        // Cut at a character boundary
+        let end = self
+            .text
+            .char_indices()
+            .nth(max_chars)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| self.text.len());
+        let text = &self.text[..end];
+        let width = text.chars().count() as u32 * cw;
+        let x = align_x(&self.area, width, self.align);
+        let y = self.area.y + self.area.height.saturating_sub(ch) as i32 / 2;
+        draw_text(disp, text, Coord::new(x, y), self.size, color);
+    }
+}
+
+/// A number in the large font, with an optional unit in the small font
+/// aligned to its baseline.  The value is fixed point: 1234 with 2 decimals
+/// reads "12.34".
+pub struct Readout<'a> {
+    value: i32,
+    decimals: u8,
+    unit: &'a str,
+    area: Area,
+    align: Align,
+}
+
+impl<'a> Readout<'a> {
+    pub fn new(value: i32, area: Area) -> Self {
+        Readout {
+            value,
+            decimals: 0,
+            unit: "",
+            area,
+            align: Align::Right,
+        }
+    }
+
+    pub fn with_decimals(mut self, decimals: u8) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    pub fn with_unit(mut self, unit: &'a str) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub fn with_align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D) {
+        let mut s: String<U16> = String::new();
+        format_fixed(&mut s, self.value, self.decimals);
+
+        self.area.fill(disp, 0);
+        let (cw, ch) = TextSize::Large.char_size();
+        let (uw, uh) = TextSize::Small.char_size();
+        let unit_width = if self.unit.is_empty() {
+            0
+        } else {
+            // One character of space between the number and the unit
+            (self.unit.len() as u32 + 1) * uw
+        };
+        let width = s.len() as u32 * cw + unit_width;
+        let x = align_x(&self.area, width, self.align);
+        let y = self.area.y + self.area.height.saturating_sub(ch) as i32 / 2;
+        draw_text(disp, s.as_str(), Coord::new(x, y), TextSize::Large, 1);
+        if !self.unit.is_empty() {
+            let ux = x + (s.len() as u32 * cw + uw) as i32;
+            let uy = y + (ch - uh) as i32;
+            draw_text(disp, self.unit, Coord::new(ux, uy), TextSize::Small, 1);
+        }
+    }
+}
+
+/// Write `value / 10^decimals` with exactly `decimals` decimals.
+pub(crate) fn format_fixed<W: Write>(w: &mut W, value: i32, decimals: u8) {
+    if decimals == 0 {
+        let _ = write!(w, "{}", value);
+        return;
+    }
+    let scale = 10i64.pow(u32::from(decimals));
+    let value = i64::from(value);
+    let sign = if value < 0 { "-" } else { "" };
+    let abs = value.abs();
+    let _ = write!(
+        w,
+        "{}{}.{:0width$}",
+        sign,
+        abs / scale,
+        abs % scale,
+        width = decimals as usize
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    /// Fills from left to right
+    Horizontal,
+    /// Fills from bottom to top
+    Vertical,
+}
+
+/// A framed bar filled in proportion to a value, for progress and levels.
+pub struct Gauge {
+    value: i32,
+    min: i32,
+    max: i32,
+    area: Area,
+    orientation: Orientation,
+}
+
+impl Gauge {
+    pub fn new(value: i32, min: i32, max: i32, area: Area) -> Self {
+        Gauge {
+            value,
+            min,
+            max,
+            area,
+            orientation: Orientation::Horizontal,
+        }
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D) {
+        self.area.fill(disp, 0);
+        self.area.outline(disp);
+        // One pixel of frame and one of gap
+        let inner = self.area.shrink(2);
+        let range = i64::from(self.max) - i64::from(self.min);
+        let value = i64::from(self.value.max(self.min).min(self.max)) - i64::from(self.min);
+        let fill = |len: u32| {
+            if range <= 0 {
+                0
+            } else {
+                (value * i64::from(len) / range) as u32
+            }
+        };
+        let bar = match self.orientation {
+            Orientation::Horizontal => {
+                Area::new(inner.x, inner.y, fill(inner.width), inner.height)
+            }
+            Orientation::Vertical => {
+                let h = fill(inner.height);
+                Area::new(inner.x, inner.bottom() + 1 - h as i32, inner.width, h)
+            }
+        };
+        bar.fill(disp, 1);
+    }
+}
+
+/// 8x8 pixels bitmaps, one byte per row with the leftmost pixel in the most
+/// significant bit.
+pub mod icons {
+    pub type Bitmap = [u8; 8];
+
+    pub const CHECK: Bitmap = [0x00, 0x01, 0x03, 0x06, 0x8c, 0xd8, 0x70, 0x20];
+    pub const CROSS: Bitmap = [0x00, 0x42, 0x24, 0x18, 0x18, 0x24, 0x42, 0x00];
+    pub const ARROW_UP: Bitmap = [0x18, 0x3c, 0x7e, 0xdb, 0x18, 0x18, 0x18, 0x18];
+    pub const ARROW_DOWN: Bitmap = [0x18, 0x18, 0x18, 0x18, 0xdb, 0x7e, 0x3c, 0x18];
+    pub const ARROW_RIGHT: Bitmap = [0x08, 0x0c, 0x06, 0xff, 0xff, 0x06, 0x0c, 0x08];
+    pub const BELL: Bitmap = [0x18, 0x3c, 0x3c, 0x3c, 0x7e, 0xff, 0x00, 0x18];
+    pub const BATTERY: Bitmap = [0x00, 0xfe, 0x82, 0xbb, 0xbb, 0x82, 0xfe, 0x00];
+}
+
+/// An 8x8 icon.
+pub struct Icon {
+    bitmap: &'static icons::Bitmap,
+    pos: Coord,
+}
+
+impl Icon {
+    pub fn new(bitmap: &'static icons::Bitmap, pos: Coord) -> Self {
+        Icon { bitmap, pos }
+    }
+
+    pub fn draw<D: Drawing<PixelColorU8>>(&self, disp: &mut D) {
+        let pos = self.pos;
+        let bitmap = self.bitmap;
+        disp.draw(
+            (0..64)
+                .map(move |i| {
+                    let (col, row) = (i % 8, i / 8);
+                    let on = bitmap[row as usize] & (0x80 >> col) != 0;
+                    (pos.0 + col, pos.1 + row, on)
+                })
+                .filter(|&(x, y, _)| x >= 0 && y >= 0)
+                .map(|(x, y, on)| Pixel(UnsignedCoord(x as u32, y as u32), (on as u8).into())),
+        );
+    }
+}
+
+/// Splits an area in equal columns and rows. The remainder pixels are spread
+/// so that the cells always cover the whole area.
+pub struct Grid {
+    area: Area,
+    cols: u32,
+    rows: u32,
+}
+
+impl Grid {
+    pub fn new(area: Area, cols: u32, rows: u32) -> Self {
+        Grid {
+            area,
+            cols: cols.max(1),
+            rows: rows.max(1),
+        }
+    }
+
+    pub fn cell(&self, col: u32, row: u32) -> Area {
+        self.span(col, row, 1, 1)
+    }
+
+    /// The area covered by `cols` x `rows` cells starting at `col`, `row`.
+    pub fn span(&self, col: u32, row: u32, cols: u32, rows: u32) -> Area {
+        let x0 = col * self.area.width / self.cols;
+        let x1 = (col + cols).min(self.cols) * self.area.width / self.cols;
+        let y0 = row * self.area.height / self.rows;
+        let y1 = (row + rows).min(self.rows) * self.area.height / self.rows;
+        Area::new(
+            self.area.x + x0 as i32,
+            self.area.y + y0 as i32,
+            x1.saturating_sub(x0),
+            y1.saturating_sub(y0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framebuffer::snapshot::assert_snapshot;
+    use crate::framebuffer::Framebuffer;
+
+    fn lit(fb: &Framebuffer, area: Area) -> usize {
+        let mut n = 0;
+        for y in area.y..=area.bottom() {
+            for x in area.x..=area.right() {
+                n += fb.pixel(x as u32, y as u32) as usize;
+            }
+        }
+        n
+    }
+
+    #[test]
+    fn labels() {
+        let mut fb = Framebuffer::new();
+        let grid = Grid::new(Area::screen(), 1, 4);
+        Label::new("left", grid.cell(0, 0)).draw(&mut fb);
+        Label::new("center", grid.cell(0, 1))
+            .with_align(Align::Center)
+            .draw(&mut fb);
+        Label::new("right", grid.cell(0, 2))
+            .with_align(Align::Right)
+            .draw(&mut fb);
+        Label::new("selected", grid.cell(0, 3))
+            .with_inverted(true)
+            .draw(&mut fb);
+        assert_snapshot("widgets_labels", &fb);
+    }
+
+    #[test]
+    fn label_is_cut_to_its_area() {
+        let mut fb = Framebuffer::new();
+        Label::new("much too long", Area::new(0, 0, 30, 8)).draw(&mut fb);
+        assert_eq!(lit(&fb, Area::new(30, 0, 98, 64)), 0);
+    }
+
+    #[test]
+    fn readout() {
+        let mut fb = Framebuffer::new();
+        let grid = Grid::new(Area::screen(), 1, 2);
+        Readout::new(-1234, grid.cell(0, 0))
+            .with_decimals(2)
+            .with_unit("V")
+            .draw(&mut fb);
+        Readout::new(42, grid.cell(0, 1))
+            .with_align(Align::Center)
+            .draw(&mut fb);
+        assert_snapshot("widgets_readout", &fb);
+    }
+
+    #[test]
+    fn fixed_point() {
+        let fmt = |value, decimals| {
+            let mut s: String<U16> = String::new();
+            format_fixed(&mut s, value, decimals);
+            s
+        };
+        assert_eq!(fmt(1234, 2), "12.34");
+        assert_eq!(fmt(-5, 2), "-0.05");
+        assert_eq!(fmt(7, 0), "7");
+    }
+
+    #[test]
+    fn gauges() {
+        let mut fb = Framebuffer::new();
+        // 100 pixels inside the frame: one pixel per unit
+        let area = Area::new(0, 0, 104, 10);
+        Gauge::new(50, 0, 100, area).draw(&mut fb);
+        assert_eq!(lit(&fb, Area::new(2, 2, 100, 6)), 50 * 6);
+        Gauge::new(150, 0, 100, area).draw(&mut fb);
+        assert_eq!(lit(&fb, Area::new(2, 2, 100, 6)), 100 * 6);
+        Gauge::new(-3, 0, 100, area).draw(&mut fb);
+        assert_eq!(lit(&fb, Area::new(2, 2, 100, 6)), 0);
+
+        Gauge::new(30, 0, 100, Area::new(0, 16, 104, 10)).draw(&mut fb);
+        Gauge::new(75, 0, 100, Area::new(110, 0, 10, 64))
+            .with_orientation(Orientation::Vertical)
+            .draw(&mut fb);
+        assert_snapshot("widgets_gauges", &fb);
+    }
+
+    #[test]
+    fn icons() {
+        let mut fb = Framebuffer::new();
+        let all = [
+            &icons::CHECK,
+            &icons::CROSS,
+            &icons::ARROW_UP,
+            &icons::ARROW_DOWN,
+            &icons::ARROW_RIGHT,
+            &icons::BELL,
+            &icons::BATTERY,
+        ];
+        for (i, icon) in all.iter().enumerate() {
+            Icon::new(icon, Coord::new(i as i32 * 10, 0)).draw(&mut fb);
+        }
+        // Top left pixel of the up arrow is off, the tip is on
+        assert!(!fb.pixel(20, 0));
+        assert!(fb.pixel(23, 0));
+        assert_snapshot("widgets_icons", &fb);
+    }
+
+    #[test]
+    fn grid_covers_the_area() {
+        let grid = Grid::new(Area::new(1, 2, 100, 50), 3, 4);
+        let mut width = 0;
+        for col in 0..3 {
+            width += grid.cell(col, 0).width;
+        }
+        assert_eq!(width, 100);
+        assert_eq!(grid.cell(2, 3).right(), 100);
+        assert_eq!(grid.cell(2, 3).bottom(), 51);
+        assert_eq!(grid.span(0, 0, 3, 4), Area::new(1, 2, 100, 50));
+    }
+}
@@ -0,0 +1,463 @@
+//! XMODEM-CRC, XMODEM-1K and YMODEM receiver.
+//!
+//! The receiver drives the transfer: it asks the sender to start by sending
+//! 'C' (CRC mode), acknowledges every valid block with ACK and asks for a
+//! retransmission with NAK.  Blocks have the following format:
+//!
+//! ```text
+//! SOH|STX  seq  !seq  data[128|1024]  crc_hi  crc_lo
+//! ```
+//!
+//! YMODEM adds a block 0 in front of every file carrying its name and size,
+//! and ends the batch with an empty block 0.
+//!
+//! Nothing here depends on the hardware: bytes go through a `Port` and the
+//! received data is handed to a `Sink`, so the engine can run on the host
+//! against `sx` and `sz` from lrzsz, in tests that only run with
+//! `-- --ignored` since they need lrzsz installed.
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+
+/// Time between 'C' requests while waiting for the sender to start.
+const START_TIMEOUT_MS: u32 = 3000;
+/// Time to wait for the next block once the transfer has started.
+const BLOCK_TIMEOUT_MS: u32 = 10000;
+/// Time to wait for every byte inside a block.
+const BYTE_TIMEOUT_MS: u32 = 1000;
+/// Consecutive errors after which the transfer is cancelled.
+const MAX_ERRORS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Single file with XMODEM-CRC or XMODEM-1K blocks.
+    Xmodem,
+    /// Batch of files with their names and sizes.
+    Ymodem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// Too many consecutive timeouts or corrupted blocks.
+    Retries,
+    /// The sender cancelled the transfer.
+    Cancelled,
+    /// A block arrived out of order.
+    Sequence,
+    /// The sink refused the data (e.g. the file doesn't fit).
+    Sink,
+}
+
+/// Byte oriented link to the sender.
+pub trait Port {
+    /// Read one byte, or return `None` if nothing arrives in `timeout_ms`.
+    fn read(&mut self, timeout_ms: u32) -> Option<u8>;
+
+    fn write(&mut self, byte: u8);
+}
+
+/// Destination of the received files.
+pub trait Sink {
+    /// Why the sink refused a file or its data.  The transfer is cancelled
+    /// with `Error::Sink` either way.
+    type Error;
+
+    /// A YMODEM header announced a new file. `size` is `None` if the sender
+    /// didn't tell.
+    fn open(&mut self, _name: &str, _size: Option<u32>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Store `data` at `offset` bytes from the start of the current file.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Called after every accepted block with the number of bytes received
+    /// so far.
+    fn progress(&mut self, _received: u32, _size: Option<u32>) {}
+}
+
+enum Packet {
+    Data { seq: u8, len: usize },
+    Eot,
+    Cancel,
+}
+
+enum PacketError {
+    Timeout,
+    Corrupt,
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &b in data {
+        crc ^= u16::from(b) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Receive a file (XMODEM) or a batch of files (YMODEM). Returns the number
+/// of bytes received, which for XMODEM includes the padding of the last block.
+pub fn receive<P: Port, S: Sink>(port: &mut P, sink: &mut S, mode: Mode) -> Result<u32, Error> {
+    let mut buf = [0u8; 1024];
+    let res = match mode {
+        Mode::Xmodem => receive_file(port, sink, &mut buf, None),
+        Mode::Ymodem => receive_batch(port, sink, &mut buf),
+    };
+    if let Err(Error::Retries) | Err(Error::Sequence) | Err(Error::Sink) = res {
+        cancel(port);
+    }
+    res
+}
+
+fn receive_batch<P: Port, S: Sink>(
+    port: &mut P,
+    sink: &mut S,
+    buf: &mut [u8; 1024],
+) -> Result<u32, Error> {
+    let mut total = 0;
+    loop {
+        let mut errors = 0;
+        let len = loop {
+            port.write(CRC);
+            match read_packet(port, buf, START_TIMEOUT_MS) {
+                Ok(Packet::Data { seq: 0, len }) => break len,
+                Ok(Packet::Cancel) => return Err(Error::Cancelled),
+                // A retransmitted EOT or data block from the previous file
+                Ok(_) => port.write(ACK),
+                Err(_) => purge(port),
+            }
+            errors += 1;
+            if errors >= MAX_ERRORS {
+                return Err(Error::Retries);
+            }
+        };
+        let (name, size) = parse_header(&buf[..len]);
+        if name.is_empty() {
+            // The null header ends the batch
+            port.write(ACK);
+            return Ok(total);
+        }
+        sink.open(name, size).map_err(|_| Error::Sink)?;
+        port.write(ACK);
+        total += receive_file(port, sink, buf, size)?;
+    }
+}
+
+fn receive_file<P: Port, S: Sink>(
+    port: &mut P,
+    sink: &mut S,
+    buf: &mut [u8; 1024],
+    size: Option<u32>,
+) -> Result<u32, Error> {
+    let mut expected: u8 = 1;
+    let mut offset: u32 = 0;
+    let mut errors = 0;
+    let mut started = false;
+    port.write(CRC);
+    loop {
+        let timeout = if started {
+            BLOCK_TIMEOUT_MS
+        } else {
+            START_TIMEOUT_MS
+        };
+        match read_packet(port, buf, timeout) {
+            Ok(Packet::Data { seq, len }) if seq == expected => {
+                let len = match size {
+                    Some(size) => len.min((size - offset) as usize),
+                    None => len,
+                };
+                sink.write(offset, &buf[..len]).map_err(|_| Error::Sink)?;
+                offset += len as u32;
+                expected = expected.wrapping_add(1);
+                started = true;
+                errors = 0;
+                sink.progress(offset, size);
+                port.write(ACK);
+            }
+            // Our ACK got lost, the sender repeated the last block
+            Ok(Packet::Data { seq, .. }) if seq == expected.wrapping_sub(1) => port.write(ACK),
+            Ok(Packet::Data { .. }) => return Err(Error::Sequence),
+            Ok(Packet::Eot) => {
+                port.write(ACK);
+                return Ok(offset);
+            }
+            Ok(Packet::Cancel) => return Err(Error::Cancelled),
+            Err(err) => {
+                errors += 1;
+                if errors >= MAX_ERRORS {
+                    return Err(Error::Retries);
+                }
+                if let PacketError::Corrupt = err {
+                    purge(port);
+                }
+                port.write(if started { NAK } else { CRC });
+            }
+        }
+    }
+}
+
+fn read_packet<P: Port>(
+    port: &mut P,
+    buf: &mut [u8; 1024],
+    timeout_ms: u32,
+) -> Result<Packet, PacketError> {
+    let len = match port.read(timeout_ms).ok_or(PacketError::Timeout)? {
+        SOH => 128,
+        STX => 1024,
+        EOT => return Ok(Packet::Eot),
+        // Two CANs in a row cancel, a single one is line noise
+        CAN => match port.read(BYTE_TIMEOUT_MS) {
+            Some(CAN) => return Ok(Packet::Cancel),
+            _ => return Err(PacketError::Corrupt),
+        },
+        _ => return Err(PacketError::Corrupt),
+    };
+    let mut read = || port.read(BYTE_TIMEOUT_MS).ok_or(PacketError::Timeout);
+    let seq = read()?;
+    let seq_inv = read()?;
+    for b in buf[..len].iter_mut() {
+        *b = read()?;
+    }
+    let crc = u16::from(read()?) << 8 | u16::from(read()?);
+    if seq != !seq_inv || crc != crc16(&buf[..len]) {
+        return Err(PacketError::Corrupt);
+    }
+    Ok(Packet::Data { seq, len })
+}
+
+/// Parse a YMODEM block 0: `name NUL size [mtime mode ...] NUL`.
+fn parse_header(data: &[u8]) -> (&str, Option<u32>) {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let name = core::str::from_utf8(&data[..end]).unwrap_or("?");
+    let mut size: Option<u32> = None;
+    for &b in data[(end + 1).min(data.len())..].iter() {
+        if !b.is_ascii_digit() {
+            break;
+        }
+        let digit = u32::from(b - b'0');
+        size = Some(size.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+    }
+    (name, size)
+}
+
+/// Drop everything until the line is quiet.
+fn purge<P: Port>(port: &mut P) {
+    while port.read(BYTE_TIMEOUT_MS).is_some() {}
+}
+
+fn cancel<P: Port>(port: &mut P) {
+    for _ in 0..5 {
+        port.write(CAN);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+
+    /// Replays a prerecorded sender, collecting everything the receiver says.
+    /// Like a real sender, the next chunk is only sent after the receiver
+    /// answered the previous one.
+    struct Script {
+        chunks: VecDeque<Vec<u8>>,
+        line: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Script {
+        fn new(chunks: Vec<Vec<u8>>) -> Self {
+            Script {
+                chunks: chunks.into(),
+                line: VecDeque::new(),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Port for Script {
+        fn read(&mut self, _timeout_ms: u32) -> Option<u8> {
+            self.line.pop_front()
+        }
+
+        fn write(&mut self, byte: u8) {
+            self.output.push(byte);
+            if self.line.is_empty() {
+                if let Some(chunk) = self.chunks.pop_front() {
+                    self.line.extend(chunk);
+                }
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Files {
+        names: Vec<(String, Option<u32>)>,
+        data: Vec<u8>,
+    }
+
+    impl Sink for Files {
+        type Error = Infallible;
+
+        fn open(&mut self, name: &str, size: Option<u32>) -> Result<(), Infallible> {
+            self.names.push((name.to_string(), size));
+            Ok(())
+        }
+
+        fn write(&mut self, _offset: u32, data: &[u8]) -> Result<(), Infallible> {
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn block(seq: u8, data: &[u8]) -> Vec<u8> {
+        let mut payload = data.to_vec();
+        let len = if payload.len() <= 128 { 128 } else { 1024 };
+        // YMODEM headers are padded with NULs, data with SUBs
+        payload.resize(len, if seq == 0 { 0 } else { 0x1a });
+        let mut v = vec![if len == 128 { SOH } else { STX }, seq, !seq];
+        v.extend_from_slice(&payload);
+        let crc = crc16(&payload);
+        v.push((crc >> 8) as u8);
+        v.push(crc as u8);
+        v
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn xmodem_retransmission() {
+        let mut bad = block(2, &[7; 1000]);
+        bad[10] ^= 1;
+        let mut port = Script::new(vec![
+            block(1, b"hello"),
+            bad,
+            block(2, &[7; 1000]),
+            block(2, &[7; 1000]),
+            vec![EOT],
+        ]);
+        let mut files = Files::default();
+        assert_eq!(receive(&mut port, &mut files, Mode::Xmodem), Ok(128 + 1024));
+        assert_eq!(port.output, vec![CRC, ACK, NAK, ACK, ACK, ACK]);
+        assert_eq!(&files.data[..5], b"hello");
+        assert_eq!(files.data.len(), 128 + 1024);
+    }
+
+    #[test]
+    fn ymodem_trims_to_size() {
+        let mut port = Script::new(vec![
+            block(0, b"song.bin\x00300 0 0"),
+            block(1, &[1; 128]),
+            block(2, &[2; 128]),
+            block(3, &[3; 128]),
+            vec![EOT],
+            block(0, b""),
+        ]);
+        let mut files = Files::default();
+        assert_eq!(receive(&mut port, &mut files, Mode::Ymodem), Ok(300));
+        assert_eq!(files.names, vec![("song.bin".to_string(), Some(300))]);
+        assert_eq!(files.data.len(), 300);
+        assert_eq!(files.data[299], 3);
+    }
+
+    #[test]
+    fn sender_cancels() {
+        let mut port = Script::new(vec![block(1, b"x"), vec![CAN, CAN]]);
+        let mut files = Files::default();
+        assert_eq!(receive(&mut port, &mut files, Mode::Xmodem), Err(Error::Cancelled));
+    }
+
+    mod lrzsz {
+        use super::super::*;
+        use super::Files;
+        use nix::poll::{poll, PollFd, PollFlags};
+        use nix::pty::openpty;
+        use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+        use nix::unistd::{read, write};
+        use std::os::unix::io::{FromRawFd, RawFd};
+        use std::process::{Command, Stdio};
+
+        struct Pty(RawFd);
+
+        impl Port for Pty {
+            fn read(&mut self, timeout_ms: u32) -> Option<u8> {
+                let mut fds = [PollFd::new(self.0, PollFlags::POLLIN)];
+                match poll(&mut fds, timeout_ms as i32) {
+                    Ok(n) if n > 0 => {
+                        let mut b = [0u8];
+                        match read(self.0, &mut b) {
+                            Ok(1) => Some(b[0]),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                }
+            }
+
+            fn write(&mut self, byte: u8) {
+                write(self.0, &[byte]).unwrap();
+            }
+        }
+
+        /// Run one of the lrzsz senders on the slave side of a pseudo-terminal.
+        fn transfer(sender: &str, args: &[&str], mode: Mode) -> (Files, Vec<u8>) {
+            let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+            let path = std::env::temp_dir().join(format!("xmodem-{}.bin", sender));
+            std::fs::write(&path, &data).unwrap();
+
+            let pty = openpty(None, None).unwrap();
+            let mut termios = tcgetattr(pty.slave).unwrap();
+            cfmakeraw(&mut termios);
+            tcsetattr(pty.slave, SetArg::TCSANOW, &termios).unwrap();
+            let slave_in = nix::unistd::dup(pty.slave).unwrap();
+            let child = Command::new(sender)
+                .args(args)
+                .arg(&path)
+                .stdin(unsafe { Stdio::from_raw_fd(slave_in) })
+                .stdout(unsafe { Stdio::from_raw_fd(pty.slave) })
+                .stderr(Stdio::null())
+                .spawn();
+            let mut child = child
+                .unwrap_or_else(|e| panic!("{} (apt install lrzsz): {}", sender, e));
+
+            let mut files = Files::default();
+            let res = receive(&mut Pty(pty.master), &mut files, mode);
+            assert!(child.wait().unwrap().success());
+            assert!(res.is_ok());
+            (files, data)
+        }
+
+        #[test]
+        #[ignore = "needs sx and sz from lrzsz, run with --ignored"]
+        fn sx_1k() {
+            let (files, data) = transfer("sx", &["-k"], Mode::Xmodem);
+            assert_eq!(&files.data[..data.len()], &data[..]);
+            assert!(files.data[data.len()..].iter().all(|&b| b == 0x1a));
+        }
+
+        #[test]
+        #[ignore = "needs sx and sz from lrzsz, run with --ignored"]
+        fn sz_ymodem() {
+            let (files, data) = transfer("sz", &["--ymodem"], Mode::Ymodem);
+            assert_eq!(files.names[0].1, Some(data.len() as u32));
+            assert_eq!(files.data, data);
+        }
+    }
+}
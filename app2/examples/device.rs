@@ -5,16 +5,11 @@
 //!
 //! [`svd2rust`]: https://crates.io/crates/svd2rust
 //!
-//! This example depends on the [`stm32f103xx`] crate so you'll have to add it to your Cargo.toml.
+//! This example uses the [`stm32f1`] crate, which is already a dependency of this crate (the HAL
+//! re-exports it as `stm32f1xx_hal::stm32`). The `rt` feature provides the `interrupt` attribute
+//! and the vector table with the interrupts of the STM32F103.
 //!
-//! [`stm32f103xx`]: https://crates.io/crates/stm32f103xx
-//!
-//! ```
-//! $ edit Cargo.toml && tail $_
-//! [dependencies.stm32f103xx]
-//! features = ["rt"]
-//! version = "0.10.0"
-//! ```
+//! [`stm32f1`]: https://crates.io/crates/stm32f1
 //!
 //! ---
 
@@ -27,7 +22,7 @@ extern crate panic_halt;
 use cortex_m::peripheral::syst::SystClkSource;
 use cortex_m_rt::entry;
 use cortex_m_semihosting::hprint;
-use stm32f30x::{interrupt, Interrupt};
+use stm32f1::stm32f103::{interrupt, Interrupt};
 
 #[entry]
 fn main() -> ! {
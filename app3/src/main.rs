@@ -24,7 +24,7 @@ use stm32f1xx_hal::delay::Delay;
 use stm32f1xx_hal::i2c::{BlockingI2c, DutyCycle, Mode};
 use stm32f1xx_hal::prelude::*;
 use stm32f1xx_hal::stm32;
-use stm32f1xx_hal::{pac, prelude::*, serial::Serial};
+use stm32f1xx_hal::{pac, prelude::*, serial, serial::Serial};
 
 // https://stackoverflow.com/a/50201632
 pub mod write_to {
@@ -79,6 +79,62 @@ pub mod write_to {
     }
 }
 
+/// Terminal mode prints 8x8 characters: 16 columns and 8 rows.
+const COLS: usize = 16;
+/// The last row is reserved for the status bar.
+const TEXT_LEN: usize = COLS * 7;
+const BAUD: u32 = 9_600;
+const STATS_QUERY: &[u8] = b"?stats\n";
+
+/// Receive counters, shown in the status bar and sent back on `?stats`.
+#[derive(Default)]
+struct Stats {
+    received: u32,
+    overrun: u32,
+    framing: u32,
+    noise: u32,
+    parity: u32,
+}
+
+impl Stats {
+    fn count(&mut self, e: serial::Error) {
+        match e {
+            serial::Error::Overrun => self.overrun += 1,
+            serial::Error::Framing => self.framing += 1,
+            serial::Error::Noise => self.noise += 1,
+            serial::Error::Parity => self.parity += 1,
+            _ => {}
+        }
+    }
+
+    /// `fill` is the number of bytes waiting in the text buffer.
+    fn display(&self, fill: usize) -> StatsDisplay {
+        StatsDisplay { stats: self, fill }
+    }
+}
+
+struct StatsDisplay<'a> {
+    stats: &'a Stats,
+    fill: usize,
+}
+
+impl<'a> core::fmt::Display for StatsDisplay<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "baud={} rx={} overrun={} framing={} noise={} parity={} fill={}/{}",
+            BAUD,
+            self.stats.received,
+            self.stats.overrun,
+            self.stats.framing,
+            self.stats.noise,
+            self.stats.parity,
+            self.fill,
+            TEXT_LEN
+        )
+    }
+}
+
 #[entry]
 fn main() -> ! {
     // Get access to the device specific peripherals from the peripheral access crate
@@ -161,7 +217,7 @@ fn main() -> ! {
         dp.USART2,
         (tx2, rx2),
         &mut afio.mapr,
-        9_600.bps(),
+        BAUD.bps(),
         clocks,
         &mut rcc.apb1,
     );
@@ -176,16 +232,30 @@ fn main() -> ! {
     }
 
     /// `while true; do date +'Is anyone there?%Y-%m-%d      %T' > /dev/ttyUSB0; sleep 1; done`
+    /// or `cargo run --bin oledterm -- clock` from the `host` crate
     // let msg = "Hello!\r\n";
     // let msg = format!("Hello {}!\r\n", "World");
     // let mut buf = [0u8; 64];
     // let mut delay = Delay::new(cp.SYST, clocks);
     // let _ = disp.clear();
-    let mut buf = [0u8; 16 * 8];
+    let mut buf = [0u8; TEXT_LEN];
     let mut len = 0;
+    let mut stats = Stats::default();
+    let mut status_page = 0;
+    let mut line = [0u8; 96];
     // let mut clear = false;
     loop {
-        buf[len] = block!(rx2.read()).unwrap();
+        let c = match rx2.read() {
+            Ok(c) => c,
+            Err(nb::Error::WouldBlock) => continue,
+            Err(nb::Error::Other(e)) => {
+                // `read` already cleared the error flags
+                stats.count(e);
+                continue;
+            }
+        };
+        stats.received += 1;
+        buf[len] = c;
         len += 1;
         //if clear {
         //    // disp.init().unwrap();
@@ -195,9 +265,42 @@ fn main() -> ! {
         // if c == b'\n' {
         //     clear = true;
         // }
-        if buf[len - 1] == b'\n' {
+        if buf[..len].ends_with(STATS_QUERY) {
+            // `echo '?stats' > /dev/ttyUSB0` answers with the counters, the
+            // query is dropped from the pending text
+            len -= STATS_QUERY.len();
+            let msg = write_to::show(&mut line, format_args!("{}\r\n", stats.display(len)))
+                .unwrap_or("?\r\n");
+            for b in msg.bytes() {
+                block!(tx2.write(b)).ok();
+            }
+        } else if buf[len - 1] == b'\n' || len == TEXT_LEN {
             let _ = disp.clear();
             let _ = disp.write_str(unsafe { core::str::from_utf8_unchecked(&buf[..len]) });
+            // Pad the text area so that the status bar always lands in the last row
+            for _ in len..TEXT_LEN {
+                let _ = disp.write_str(" ");
+            }
+            let status = match status_page {
+                0 => write_to::show(
+                    &mut line,
+                    format_args!("{:>5}bd{:>9}", BAUD, stats.received),
+                ),
+                _ => write_to::show(
+                    &mut line,
+                    format_args!(
+                        "O{}F{}N{}P{} {:>3}%",
+                        stats.overrun,
+                        stats.framing,
+                        stats.noise,
+                        stats.parity,
+                        len * 100 / TEXT_LEN
+                    ),
+                ),
+            };
+            let status = status.unwrap_or("");
+            let _ = disp.write_str(&status[..status.len().min(COLS)]);
+            status_page = (status_page + 1) % 2;
             len = 0;
         }
     }
@@ -0,0 +1,206 @@
+//! Record the stream of the ADC logger (`app/examples/logger.rs`) to CSV.
+//!
+//! ```
+//! adclog --port /dev/ttyUSB0 --output trace.csv
+//! adclog --duration 60 > trace.csv
+//! adclog --dry-run   # then write frames to the printed pty
+//! ```
+//!
+//! The frames and the timing are reported on stderr every `--report`
+//! seconds: frames received and lost, the measured sample rate against the
+//! one of the board and the jitter of the arrivals, see `host::record`.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use clap::{App, Arg};
+
+use host::frame::{Decoder, Frame};
+use host::link::Link;
+use host::record::{self, Timing, Unwrapper};
+
+/// What the board said about the stream.
+struct Info {
+    rate_millihz: u32,
+    channels: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Counters {
+    frames: u64,
+    lost: u64,
+    errors: u64,
+    /// Frames before the first info frame, without a rate or channels
+    skipped: u64,
+}
+
+fn positive(matches: &clap::ArgMatches, name: &str) -> f64 {
+    match matches.value_of(name).unwrap().parse() {
+        Ok(value) if value > 0.0 => value,
+        _ => {
+            clap::Error::value_validation_auto(format!("{} must be a positive number", name)).exit()
+        }
+    }
+}
+
+fn report(timing: &Timing, counters: &Counters, info: &Option<Info>) {
+    let mut line = format!(
+        "{} frames, {} lost, {} errors",
+        counters.frames, counters.lost, counters.errors
+    );
+    if counters.skipped > 0 {
+        line += &format!(", {} before the first info", counters.skipped);
+    }
+    if let Some(report) = timing.report() {
+        line += &format!(", {:.3} Hz", report.rate_hz);
+        if let Some(info) = info {
+            let nominal = f64::from(info.rate_millihz) / 1000.0;
+            line += &format!(" ({:+.0} ppm)", report.ppm(nominal));
+        }
+        line += &format!(
+            ", jitter {:.0} us rms, {:.0} us max",
+            report.rms_us, report.max_us
+        );
+    }
+    eprintln!("{}", line);
+}
+
+fn main() -> io::Result<()> {
+    let matches = App::new("adclog")
+        .about("Records the ADC logger stream to CSV")
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .short("p")
+                .default_value("/dev/ttyUSB0"),
+        )
+        .arg(Arg::with_name("baud").long("baud").default_value("115200"))
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help("CSV file, standard output by default"),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .long("duration")
+                .short("d")
+                .takes_value(true)
+                .help("Seconds to record, until interrupted by default"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .short("r")
+                .help("Seconds between reports")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Read from a pseudo-terminal instead of the serial port"),
+        )
+        .get_matches();
+
+    let baud: u32 = matches
+        .value_of("baud")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| clap::Error::value_validation_auto("bad baud rate".to_string()).exit());
+    let every = Duration::from_secs_f64(positive(&matches, "report"));
+    let duration = if matches.is_present("duration") {
+        Some(Duration::from_secs_f64(positive(&matches, "duration")))
+    } else {
+        None
+    };
+    let mut out: Box<dyn Write> = match matches.value_of("output") {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    let mut link = if matches.is_present("dry-run") {
+        let link = Link::pty().expect("creating the pseudo-terminal");
+        eprintln!("reading from {}", link.path());
+        link
+    } else {
+        Link::serial(matches.value_of("port").unwrap(), baud)
+    };
+
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut decoder = Decoder::new();
+    let mut unwrapper = Unwrapper::new();
+    let mut timing = Timing::new();
+    let mut counters = Counters::default();
+    let mut info: Option<Info> = None;
+    let mut connected = true;
+    let mut buf = [0; 256];
+    while duration.is_none_or(|d| start.elapsed() < d) {
+        let n = match link.receive(&mut buf) {
+            Ok(n) => {
+                if !connected {
+                    eprintln!("{}: reconnected", link.path());
+                    connected = true;
+                }
+                n
+            }
+            Err(e) => {
+                if connected {
+                    eprintln!("{}: {}, retrying", link.path(), e);
+                    connected = false;
+                }
+                std::thread::sleep(Duration::from_secs(1));
+                0
+            }
+        };
+        let now = start.elapsed().as_secs_f64();
+        for frame in buf[..n].iter().filter_map(|&b| decoder.push(b)) {
+            match frame {
+                Ok(Frame::Info {
+                    rate_millihz,
+                    channels,
+                }) => {
+                    let changed = info
+                        .as_ref()
+                        .is_none_or(|i| i.channels != channels.as_slice());
+                    if changed {
+                        // A new stream, with its own columns
+                        writeln!(out, "{}", record::csv_header(channels.as_slice()))?;
+                        unwrapper = Unwrapper::new();
+                        timing.clear();
+                    }
+                    info = Some(Info {
+                        rate_millihz,
+                        channels: channels.as_slice().to_vec(),
+                    });
+                }
+                Ok(Frame::Samples { seq, samples }) => match &info {
+                    Some(info) => {
+                        let (number, lost) = unwrapper.unwrap(seq);
+                        counters.frames += 1;
+                        counters.lost += lost;
+                        timing.push(number, now);
+                        writeln!(
+                            out,
+                            "{}",
+                            record::csv_row(number, info.rate_millihz, samples.as_slice())
+                        )?;
+                    }
+                    None => counters.skipped += 1,
+                },
+                Err(_) => counters.errors += 1,
+            }
+        }
+        if last_report.elapsed() >= every {
+            out.flush()?;
+            report(&timing, &counters, &info);
+            timing.clear();
+            last_report = Instant::now();
+        }
+    }
+    out.flush()?;
+    report(&timing, &counters, &info);
+    Ok(())
+}
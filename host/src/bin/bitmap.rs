@@ -0,0 +1,87 @@
+//! Convert a PNG or PBM image into a constant `app::image::Image`.
+//!
+//! ```
+//! bitmap logo.png > src/logo.rs
+//! bitmap --dither --invert photo.png --name PHOTO
+//! bitmap --preview logo.png   # prints the result as a PBM
+//! ```
+//!
+//! The images in `app/images` are converted by the `build.rs` of `app`, this
+//! is for the other ones.
+
+use std::path::Path;
+use std::process;
+
+use clap::{App, Arg};
+
+use host::bitmap;
+
+fn main() {
+    let matches = App::new("bitmap")
+        .about("Converts images to 1-bit SSD1306 page format Rust constants")
+        .arg(Arg::with_name("image").required(true).help("PNG or PBM file"))
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .short("n")
+                .takes_value(true)
+                .help("Name of the constant, from the file name by default"),
+        )
+        .arg(
+            Arg::with_name("dither")
+                .long("dither")
+                .short("d")
+                .help("Floyd-Steinberg dithering instead of a threshold"),
+        )
+        .arg(
+            Arg::with_name("threshold")
+                .long("threshold")
+                .short("t")
+                .default_value("127")
+                .help("Pixels brighter than this are lit"),
+        )
+        .arg(
+            Arg::with_name("invert")
+                .long("invert")
+                .short("i")
+                .help("Light dark pixels instead"),
+        )
+        .arg(
+            Arg::with_name("preview")
+                .long("preview")
+                .help("Print the converted image as a plain PBM"),
+        )
+        .get_matches();
+
+    let path = Path::new(matches.value_of("image").unwrap());
+    let gray = bitmap::load(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        process::exit(1);
+    });
+    let level: u8 = matches
+        .value_of("threshold")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| {
+            clap::Error::value_validation_auto("threshold must be 0-255".to_string()).exit()
+        });
+
+    let mut bits = if matches.is_present("dither") {
+        bitmap::dither(&gray)
+    } else {
+        bitmap::threshold(&gray, level)
+    };
+    if matches.is_present("invert") {
+        bits = bitmap::invert(&bits);
+    }
+
+    if matches.is_present("preview") {
+        print!("{}", bitmap::to_pbm(&bits));
+        return;
+    }
+    let name = matches
+        .value_of("name")
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| bitmap::const_name(path));
+    print!("{}", bitmap::to_rust(&name, &bits));
+}
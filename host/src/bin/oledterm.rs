@@ -0,0 +1,135 @@
+//! Feed the serial terminal of app3 from the host.
+//!
+//! ```
+//! oledterm clock
+//! oledterm --rate 0.2 text 'Is anyone there?{date} {time}'
+//! oledterm cmd 'uptime -p'
+//! oledterm --dry-run load   # then `cat` the printed pty
+//! ```
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{App, AppSettings, Arg, SubCommand};
+
+use host::link::Link;
+use host::source::{Renderer, Source};
+use host::term;
+
+fn main() {
+    let matches = App::new("oledterm")
+        .about("Sends text to the app3 OLED terminal")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .short("p")
+                .default_value("/dev/ttyUSB0"),
+        )
+        .arg(Arg::with_name("baud").long("baud").default_value("9600"))
+        .arg(
+            Arg::with_name("rate")
+                .long("rate")
+                .short("r")
+                .help("Updates per second")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("once")
+                .long("once")
+                .help("Send a single update and exit"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Write to a pseudo-terminal instead of the serial port"),
+        )
+        .subcommand(
+            SubCommand::with_name("text")
+                .about("Text with {date}, {time}, {load} and {cpu} placeholders")
+                .arg(Arg::with_name("template").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("clock").about("Date and time").arg(
+                Arg::with_name("format")
+                    .help("strftime format")
+                    .default_value("%Y-%m-%d\n%H:%M:%S"),
+            ),
+        )
+        .subcommand(SubCommand::with_name("load").about("Load average and CPU usage"))
+        .subcommand(
+            SubCommand::with_name("cmd")
+                .about("Output of a shell command")
+                .arg(Arg::with_name("command").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("file")
+                .about("Contents of a file")
+                .arg(Arg::with_name("path").required(true)),
+        )
+        .get_matches();
+
+    let source = match matches.subcommand() {
+        ("text", Some(m)) => Source::Text(m.value_of("template").unwrap().to_string()),
+        ("clock", Some(m)) => Source::Clock(m.value_of("format").unwrap().to_string()),
+        ("load", _) => Source::Load,
+        ("cmd", Some(m)) => Source::Command(m.value_of("command").unwrap().to_string()),
+        ("file", Some(m)) => Source::File(PathBuf::from(m.value_of("path").unwrap())),
+        _ => unreachable!(),
+    };
+    let rate: f64 = match matches.value_of("rate").unwrap().parse() {
+        Ok(rate) if rate > 0.0 => rate,
+        _ => clap::Error::value_validation_auto("rate must be a positive number".to_string())
+            .exit(),
+    };
+    let baud: u32 = matches
+        .value_of("baud")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| clap::Error::value_validation_auto("bad baud rate".to_string()).exit());
+
+    let mut link = if matches.is_present("dry-run") {
+        let link = Link::pty().expect("creating the pseudo-terminal");
+        eprintln!("writing to {}", link.path());
+        link
+    } else {
+        Link::serial(matches.value_of("port").unwrap(), baud)
+    };
+
+    let period = Duration::from_secs_f64(1.0 / rate);
+    let mut renderer = Renderer::new(source);
+    let mut connected = true;
+    loop {
+        let start = Instant::now();
+        match renderer.render() {
+            Ok(text) => match link.send(&term::layout(&text)) {
+                Ok(()) => {
+                    if !connected {
+                        eprintln!("{}: reconnected", link.path());
+                        connected = true;
+                    }
+                }
+                Err(e) => {
+                    if connected {
+                        eprintln!("{}: {}, retrying", link.path(), e);
+                        connected = false;
+                    }
+                }
+            },
+            Err(e) => eprintln!("render: {}", e),
+        }
+        if matches.is_present("once") {
+            break;
+        }
+        // Retry a lost port at least once per second
+        let wait = if connected {
+            period
+        } else {
+            period.min(Duration::from_secs(1))
+        };
+        if let Some(rest) = wait.checked_sub(start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Converting images to the 1-bit page format of the SSD1306.
+//!
+//! The display memory is split in pages of 8 rows, and every byte holds a
+//! column of 8 pixels of a page with the top one in the least significant
+//! bit (see `app::framebuffer`).  An image `width` pixels wide and `height`
+//! high becomes `width * ceil(height / 8)` bytes, page after page.
+//!
+//! PNG (any color type, 8 bits per channel or less) and PBM (plain `P1` and
+//! raw `P4`) images are supported.  Gray images are made black and white with
+//! a threshold, or with Floyd-Steinberg dithering.
+//!
+//! This module only uses `std` and `png`, because the `build.rs` of `app`
+//! includes it to convert the images in `app/images` at build time.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+/// An image with one byte per pixel, 0 is black and 255 white.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gray {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// An image with one `bool` per pixel, true is a lit pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bits {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<bool>,
+}
+
+impl Bits {
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        self.data[(y * self.width + x) as usize]
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Load a PNG or PBM file, by its extension.
+pub fn load(path: &Path) -> io::Result<Gray> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let file = BufReader::new(File::open(path)?);
+    match ext.to_ascii_lowercase().as_str() {
+        "png" => read_png(file),
+        "pbm" => read_pbm(file),
+        _ => Err(invalid("unknown image format, use .png or .pbm")),
+    }
+}
+
+pub fn read_png<R: Read>(reader: R) -> io::Result<Gray> {
+    let mut decoder = png::Decoder::new(reader);
+    // Palettes to RGB, less than 8 bits to 8 bits
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder
+        .read_info()
+        .map_err(|e| invalid(&e.to_string()))?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader
+        .next_frame(&mut buf)
+        .map_err(|e| invalid(&e.to_string()))?;
+    if info.bit_depth == png::BitDepth::Sixteen {
+        return Err(invalid("16-bit PNGs are not supported"));
+    }
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        // Expanded to RGB
+        png::ColorType::Indexed => 3,
+    };
+    let data = buf
+        .chunks(channels)
+        .take((info.width * info.height) as usize)
+        .map(|px| {
+            let (gray, alpha) = match channels {
+                1 => (u32::from(px[0]), 255),
+                2 => (u32::from(px[0]), u32::from(px[1])),
+                3 => (luma(px), 255),
+                _ => (luma(px), u32::from(px[3])),
+            };
+            // Transparent pixels are black, like an unlit display
+            (gray * alpha / 255) as u8
+        })
+        .collect();
+    Ok(Gray {
+        width: info.width,
+        height: info.height,
+        data,
+    })
+}
+
+/// ITU-R BT.601 luma of an RGB pixel.
+fn luma(px: &[u8]) -> u32 {
+    (299 * u32::from(px[0]) + 587 * u32::from(px[1]) + 114 * u32::from(px[2])) / 1000
+}
+
+/// Read a PBM image. In PBM 1 is black, which is taken as an unlit pixel.
+pub fn read_pbm<R: BufRead>(mut reader: R) -> io::Result<Gray> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut pos = 0;
+
+    // Header fields are separated by whitespace, with `#` comments
+    let mut field = || -> io::Result<String> {
+        loop {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("truncated PBM header"));
+        }
+        Ok(String::from_utf8_lossy(&bytes[start..pos]).into_owned())
+    };
+    let magic = field()?;
+    let width: u32 = field()?.parse().map_err(|_| invalid("bad PBM width"))?;
+    let height: u32 = field()?.parse().map_err(|_| invalid("bad PBM height"))?;
+    let count = width
+        .checked_mul(height)
+        .ok_or_else(|| invalid("PBM image too large"))? as usize;
+
+    // Every pixel takes at least a bit of the file, a bogus size is caught
+    // as truncated data before it is allocated
+    let mut data = Vec::with_capacity(count.min(bytes.len() * 8));
+    match magic.as_str() {
+        "P1" => {
+            for &b in &bytes[pos..] {
+                match b {
+                    b'0' => data.push(255),
+                    b'1' => data.push(0),
+                    _ => {}
+                }
+            }
+        }
+        "P4" => {
+            // A single whitespace character after the height
+            let raster = bytes
+                .get(pos + 1..)
+                .ok_or_else(|| invalid("truncated PBM data"))?;
+            let stride = width.div_ceil(8) as usize;
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let byte = raster
+                        .get(y * stride + x / 8)
+                        .ok_or_else(|| invalid("truncated PBM data"))?;
+                    let black = byte & (0x80 >> (x % 8)) != 0;
+                    data.push(if black { 0 } else { 255 });
+                }
+            }
+        }
+        _ => return Err(invalid("not a PBM image")),
+    }
+    if data.len() < count {
+        return Err(invalid("truncated PBM data"));
+    }
+    data.truncate(count);
+    Ok(Gray {
+        width,
+        height,
+        data,
+    })
+}
+
+/// Write a plain (`P1`) PBM image.
+pub fn to_pbm(bits: &Bits) -> String {
+    let mut out = format!("P1\n{} {}\n", bits.width, bits.height);
+    for row in bits.data.chunks(bits.width as usize) {
+        let line: Vec<&str> = row.iter().map(|&on| if on { "0" } else { "1" }).collect();
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+/// Pixels brighter than `level` are lit.
+pub fn threshold(gray: &Gray, level: u8) -> Bits {
+    Bits {
+        width: gray.width,
+        height: gray.height,
+        data: gray.data.iter().map(|&v| v > level).collect(),
+    }
+}
+
+/// Floyd-Steinberg dithering: the error of every pixel is spread to its
+/// neighbours to the right and below, so the average brightness is kept.
+pub fn dither(gray: &Gray) -> Bits {
+    let (w, h) = (gray.width as usize, gray.height as usize);
+    let mut values: Vec<i32> = gray.data.iter().map(|&v| i32::from(v)).collect();
+    let mut data = vec![false; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let old = values[y * w + x];
+            let on = old > 127;
+            data[y * w + x] = on;
+            let err = old - if on { 255 } else { 0 };
+            let mut spread = |dx: isize, dy: usize, weight: i32| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < w && y + dy < h {
+                    values[(y + dy) * w + nx as usize] += err * weight / 16;
+                }
+            };
+            spread(1, 0, 7);
+            spread(-1, 1, 3);
+            spread(0, 1, 5);
+            spread(1, 1, 1);
+        }
+    }
+    Bits {
+        width: gray.width,
+        height: gray.height,
+        data,
+    }
+}
+
+pub fn invert(bits: &Bits) -> Bits {
+    Bits {
+        data: bits.data.iter().map(|&on| !on).collect(),
+        ..bits.clone()
+    }
+}
+
+/// Pack in the SSD1306 page format. The last page is padded with unlit
+/// pixels.
+pub fn pack(bits: &Bits) -> Vec<u8> {
+    let pages = bits.height.div_ceil(8);
+    let mut out = vec![0; (bits.width * pages) as usize];
+    for y in 0..bits.height {
+        for x in 0..bits.width {
+            if bits.pixel(x, y) {
+                out[((y / 8) * bits.width + x) as usize] |= 1 << (y % 8);
+            }
+        }
+    }
+    out
+}
+
+/// The inverse of `pack`.
+pub fn unpack(width: u32, height: u32, bytes: &[u8]) -> Bits {
+    let data = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| bytes[((y / 8) * width + x) as usize] & (1 << (y % 8)) != 0)
+        .collect();
+    Bits {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Rust source of a constant `app::image::Image` called `name`.
+pub fn to_rust(name: &str, bits: &Bits) -> String {
+    let bytes = pack(bits);
+    let mut out = format!(
+        "pub const {}: Image = Image::new({}, {}, &[\n",
+        name, bits.width, bits.height
+    );
+    for line in bytes.chunks(12) {
+        let line: Vec<String> = line.iter().map(|b| format!("0x{:02x},", b)).collect();
+        out.push_str("    ");
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out.push_str("]);\n");
+    out
+}
+
+/// Constant name for an image file: `rust-logo.png` is `RUST_LOGO`.
+pub fn const_name(path: &Path) -> String {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("IMAGE");
+    let mut name: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Pseudo-random bits.
+    fn noise(width: u32, height: u32, seed: u32) -> Bits {
+        let mut state = seed;
+        let data = (0..width * height)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state & 1 != 0
+            })
+            .collect();
+        Bits {
+            width,
+            height,
+            data,
+        }
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        for &(w, h) in &[(1, 1), (8, 8), (13, 21), (128, 64)] {
+            let bits = noise(w, h, w * 1000 + h);
+            let bytes = pack(&bits);
+            assert_eq!(bytes.len() as u32, w * h.div_ceil(8));
+            assert_eq!(unpack(w, h, &bytes), bits);
+        }
+    }
+
+    #[test]
+    fn page_format() {
+        // Lit pixels at (0, 0), (1, 7) and (2, 8)
+        let mut bits = noise(3, 9, 1);
+        bits.data = vec![false; 27];
+        bits.data[0] = true;
+        bits.data[7 * 3 + 1] = true;
+        bits.data[8 * 3 + 2] = true;
+        assert_eq!(pack(&bits), vec![0x01, 0x80, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn pbm_round_trip() {
+        let bits = noise(13, 5, 7);
+        let gray = read_pbm(Cursor::new(to_pbm(&bits))).unwrap();
+        assert_eq!(threshold(&gray, 127), bits);
+
+        // The same image in the raw format, with a comment
+        let mut raw = b"P4\n# comment\n13 5\n".to_vec();
+        for row in bits.data.chunks(13) {
+            let mut bytes = [0u8; 2];
+            for (x, &on) in row.iter().enumerate() {
+                if !on {
+                    bytes[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            raw.extend_from_slice(&bytes);
+        }
+        let gray = read_pbm(Cursor::new(raw)).unwrap();
+        assert_eq!(threshold(&gray, 127), bits);
+    }
+
+    #[test]
+    fn bad_pbm_headers() {
+        let error = |bytes: &[u8]| {
+            read_pbm(Cursor::new(bytes.to_vec()))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error(b"P4\n1 1"), "truncated PBM data");
+        assert_eq!(error(b"P4\n1"), "truncated PBM header");
+        assert_eq!(error(b"P1\n2 2\n0 1 0"), "truncated PBM data");
+        assert_eq!(error(b"P4\n65536 65536\n\0"), "PBM image too large");
+        assert_eq!(error(b"P4\n60000 60000\n\0"), "truncated PBM data");
+        assert_eq!(error(b"P4\n99999999999 1\n"), "bad PBM width");
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let bits = noise(20, 10, 3);
+        let mut png_data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png_data, 20, 10);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            let pixels: Vec<u8> = bits
+                .data
+                .iter()
+                .flat_map(|&on| {
+                    let v = if on { 255 } else { 0 };
+                    vec![v, v, v]
+                })
+                .collect();
+            writer.write_image_data(&pixels).unwrap();
+        }
+        let gray = read_png(Cursor::new(png_data)).unwrap();
+        let bytes = pack(&threshold(&gray, 127));
+        assert_eq!(unpack(20, 10, &bytes), bits);
+    }
+
+    #[test]
+    fn dithering_keeps_the_brightness() {
+        for &level in &[64u8, 128, 192] {
+            let gray = Gray {
+                width: 64,
+                height: 64,
+                data: vec![level; 64 * 64],
+            };
+            let lit = dither(&gray).data.iter().filter(|&&on| on).count();
+            let expected = 64 * 64 * level as usize / 255;
+            assert!((lit as i32 - expected as i32).abs() < 64, "{} {}", level, lit);
+        }
+    }
+
+    #[test]
+    fn rust_source() {
+        let bits = unpack(2, 3, &[0x05, 0x02]);
+        assert_eq!(
+            to_rust("DOT", &bits),
+            "pub const DOT: Image = Image::new(2, 3, &[\n    0x05, 0x02,\n]);\n"
+        );
+        assert_eq!(const_name(Path::new("images/rust-logo.png")), "RUST_LOGO");
+        assert_eq!(const_name(Path::new("8ball.pbm")), "_8BALL");
+    }
+}
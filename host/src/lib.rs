@@ -0,0 +1,10 @@
+//! Host side tools for the Blue Pill examples.
+
+pub mod bitmap;
+// The format of the ADC logger, shared with the firmware
+#[path = "../../app/src/frame.rs"]
+pub mod frame;
+pub mod link;
+pub mod record;
+pub mod source;
+pub mod term;
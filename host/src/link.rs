@@ -0,0 +1,195 @@
+//! Connection to the board: a serial port that is reopened when it goes away,
+//! or a pseudo-terminal for testing without hardware.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::time::Duration;
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::openpty;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+
+/// How long `receive` waits for data.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub enum Link {
+    Serial {
+        path: String,
+        baud: u32,
+        port: Option<Box<dyn serialport::SerialPort>>,
+    },
+    Pty {
+        master: File,
+        /// Kept open so that the pty survives readers coming and going.
+        _slave: File,
+        path: String,
+    },
+}
+
+impl Link {
+    /// The port is opened lazily by the first `send`.
+    pub fn serial(path: &str, baud: u32) -> Self {
+        Link::Serial {
+            path: path.to_string(),
+            baud,
+            port: None,
+        }
+    }
+
+    /// Create a raw pseudo-terminal. Read what would go to the board from
+    /// `path()`, e.g. with `cat`.
+    pub fn pty() -> io::Result<Self> {
+        let pty = openpty(None, None).map_err(nix_error)?;
+        let mut termios = tcgetattr(pty.slave).map_err(nix_error)?;
+        cfmakeraw(&mut termios);
+        tcsetattr(pty.slave, SetArg::TCSANOW, &termios).map_err(nix_error)?;
+        // Nobody may be reading: drop data instead of blocking when the pty is full
+        fcntl(pty.master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(nix_error)?;
+        let path = fs::read_link(format!("/proc/self/fd/{}", pty.slave))?;
+        Ok(Link::Pty {
+            master: unsafe { File::from_raw_fd(pty.master) },
+            _slave: unsafe { File::from_raw_fd(pty.slave) },
+            path: path.to_string_lossy().into_owned(),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Link::Serial { path, .. } | Link::Pty { path, .. } => path,
+        }
+    }
+
+    /// The open serial port, opening it if needed.
+    fn port<'a>(
+        path: &str,
+        baud: u32,
+        port: &'a mut Option<Box<dyn serialport::SerialPort>>,
+    ) -> io::Result<&'a mut Box<dyn serialport::SerialPort>> {
+        if port.is_none() {
+            let opened = serialport::new(path, baud)
+                .timeout(Duration::from_secs(1))
+                .open()
+                .map_err(|e| io::Error::new(io::ErrorKind::NotConnected, e))?;
+            *port = Some(opened);
+        }
+        Ok(port.as_mut().unwrap())
+    }
+
+    /// Write `data`, reopening the serial port if needed.  After an error the
+    /// port is closed, so the next call tries to reconnect.
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Link::Serial { path, baud, port } => {
+                let res = Link::port(path, *baud, port).and_then(|p| {
+                    p.write_all(data)?;
+                    p.flush()
+                });
+                if res.is_err() {
+                    *port = None;
+                }
+                res
+            }
+            Link::Pty { master, .. } => master.write_all(data),
+        }
+    }
+
+    /// Read what the board sent into `buf`, waiting for it a little.  Returns
+    /// 0 if nothing came.  Reopens the serial port like `send`.
+    pub fn receive(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Link::Serial { path, baud, port } => {
+                let res = Link::port(path, *baud, port).and_then(|p| {
+                    p.set_timeout(RECEIVE_TIMEOUT)?;
+                    match p.read(buf) {
+                        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+                        res => res,
+                    }
+                });
+                if res.is_err() {
+                    *port = None;
+                }
+                res
+            }
+            Link::Pty { master, .. } => {
+                let mut fds = [PollFd::new(master.as_raw_fd(), PollFlags::POLLIN)];
+                if poll(&mut fds, RECEIVE_TIMEOUT.as_millis() as i32).map_err(nix_error)? == 0 {
+                    return Ok(0);
+                }
+                match master.read(buf) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+                    res => res,
+                }
+            }
+        }
+    }
+}
+
+fn nix_error(e: nix::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::{self, Decoder, Frame, MAX_FRAME};
+    use crate::record::Unwrapper;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn pty_loopback() {
+        let mut link = Link::pty().unwrap();
+        let mut reader = OpenOptions::new().read(true).open(link.path()).unwrap();
+        link.send(b"Hello Rust!\n").unwrap();
+        let mut buf = [0u8; 12];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"Hello Rust!\n");
+    }
+
+    /// The board writes frames to the slave side, one of them lost, and the
+    /// host decodes them from the master side.
+    #[test]
+    fn framed_loopback() {
+        let mut link = Link::pty().unwrap();
+        let mut board = OpenOptions::new().write(true).open(link.path()).unwrap();
+        let mut out = [0; MAX_FRAME];
+        let len = frame::encode_info(1_000_000, &[8, 9], &mut out);
+        board.write_all(&out[..len]).unwrap();
+        for seq in (65530..=65535).chain(0..10).filter(|&seq| seq != 3) {
+            let len = frame::encode_samples(seq, &[seq & 0xfff, 4095 - (seq & 0xfff)], &mut out);
+            board.write_all(&out[..len]).unwrap();
+        }
+
+        let mut decoder = Decoder::new();
+        let mut unwrapper = Unwrapper::new();
+        let mut frames = Vec::new();
+        let mut lost = 0;
+        let mut buf = [0; 64];
+        while frames.len() < 15 {
+            let n = link.receive(&mut buf).unwrap();
+            assert!(n > 0, "timeout after {} frames", frames.len());
+            for frame in buf[..n].iter().filter_map(|&b| decoder.push(b)) {
+                match frame.unwrap() {
+                    Frame::Info {
+                        rate_millihz,
+                        channels,
+                    } => {
+                        assert_eq!(rate_millihz, 1_000_000);
+                        assert_eq!(channels.as_slice(), [8, 9]);
+                    }
+                    Frame::Samples { seq, samples } => {
+                        let (number, missing) = unwrapper.unwrap(seq);
+                        lost += missing;
+                        assert_eq!(samples.as_slice(), [seq & 0xfff, 4095 - (seq & 0xfff)]);
+                        frames.push(number);
+                    }
+                }
+            }
+        }
+        assert_eq!(lost, 1);
+        assert_eq!(frames.last(), Some(&(65536 + 9)));
+        // Nothing else comes
+        assert_eq!(link.receive(&mut buf).unwrap(), 0);
+    }
+}
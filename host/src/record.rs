@@ -0,0 +1,220 @@
+//! Bookkeeping of the stream of the ADC logger: frame numbers, lost frames,
+//! timing and CSV rows.  The frames themselves are in `frame`.
+//!
+//! The samples are taken by a timer of the board, so they are evenly spaced
+//! in the board's time.  The host only knows when the frames arrive, and
+//! fits a line through the arrival times: its slope is the actual sample
+//! rate (the crystal of the board against the clock of the computer), the
+//! distance of the arrivals from it is the jitter of the link (serial
+//! adapter, USB, scheduling), which is what `Report` tells.
+
+use std::fmt::Write;
+
+/// Extends the 16-bit sequence numbers of the frames.
+#[derive(Default)]
+pub struct Unwrapper {
+    /// Sequence number of the last frame and number expected for the next
+    last: Option<(u16, u64)>,
+}
+
+impl Unwrapper {
+    pub fn new() -> Self {
+        Unwrapper { last: None }
+    }
+
+    /// The number of a frame, from the sequence number of the first one
+    /// received, and how many frames were lost just before it.  Up to 32767
+    /// frames lost in a row are counted, a jump back (the board restarted)
+    /// goes on from the last number.
+    pub fn unwrap(&mut self, seq: u16) -> (u64, u64) {
+        let (number, lost) = match self.last {
+            None => (u64::from(seq), 0),
+            Some((last, next)) => {
+                let delta = seq.wrapping_sub(last.wrapping_add(1));
+                if delta < 0x8000 {
+                    (next + u64::from(delta), u64::from(delta))
+                } else {
+                    (next, 0)
+                }
+            }
+        };
+        self.last = Some((seq, number + 1));
+        (number, lost)
+    }
+}
+
+/// Timing of the frames received in a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    pub frames: usize,
+    /// Frames per second, in the time of the host
+    pub rate_hz: f64,
+    /// Root mean square of the distance of the arrivals from the fit
+    pub rms_us: f64,
+    pub max_us: f64,
+}
+
+impl Report {
+    /// How far the measured rate is from the one the board says, in parts
+    /// per million.
+    pub fn ppm(&self, nominal_hz: f64) -> f64 {
+        (self.rate_hz / nominal_hz - 1.0) * 1e6
+    }
+}
+
+/// Arrival times of frames, by number.
+#[derive(Default)]
+pub struct Timing {
+    points: Vec<(u64, f64)>,
+}
+
+impl Timing {
+    pub fn new() -> Self {
+        Timing { points: Vec::new() }
+    }
+
+    /// Frame `number` arrived at `secs`.
+    pub fn push(&mut self, number: u64, secs: f64) {
+        self.points.push((number, secs));
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Least squares fit of the arrival times, `None` with less than 3
+    /// frames or all with the same number.
+    pub fn report(&self) -> Option<Report> {
+        if self.points.len() < 3 {
+            return None;
+        }
+        // Relative to the first point, for the precision of the sums
+        let (n0, t0) = self.points[0];
+        let xs: Vec<f64> = self.points.iter().map(|&(n, _)| (n - n0) as f64).collect();
+        let ys: Vec<f64> = self.points.iter().map(|&(_, t)| t - t0).collect();
+        let count = xs.len() as f64;
+        let mean_x = xs.iter().sum::<f64>() / count;
+        let mean_y = ys.iter().sum::<f64>() / count;
+        let var: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+        let cov: f64 = xs
+            .iter()
+            .zip(&ys)
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        if var == 0.0 || cov <= 0.0 {
+            return None;
+        }
+        let period = cov / var;
+        let residuals: Vec<f64> = xs
+            .iter()
+            .zip(&ys)
+            .map(|(x, y)| y - (mean_y + (x - mean_x) * period))
+            .collect();
+        let rms = (residuals.iter().map(|r| r * r).sum::<f64>() / count).sqrt();
+        let max = residuals.iter().fold(0.0f64, |max, r| max.max(r.abs()));
+        Some(Report {
+            frames: self.points.len(),
+            rate_hz: 1.0 / period,
+            rms_us: rms * 1e6,
+            max_us: max * 1e6,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+}
+
+/// The first line of the CSV: frame number, time and a column per channel.
+pub fn csv_header(channels: &[u8]) -> String {
+    let mut line = String::from("frame,time_s");
+    for channel in channels {
+        write!(line, ",ch{}", channel).unwrap();
+    }
+    line
+}
+
+/// A line of the CSV, the time from the frame number and the rate.
+pub fn csv_row(number: u64, rate_millihz: u32, samples: &[u16]) -> String {
+    let secs = number as f64 * 1000.0 / f64::from(rate_millihz);
+    let mut line = format!("{},{:.6}", number, secs);
+    for sample in samples {
+        write!(line, ",{}", sample).unwrap();
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sequence_numbers() {
+        let mut unwrapper = Unwrapper::new();
+        assert_eq!(unwrapper.unwrap(65533), (65533, 0));
+        assert_eq!(unwrapper.unwrap(65534), (65534, 0));
+        // Across the wrap, with 2 frames lost
+        assert_eq!(unwrapper.unwrap(1), (65537, 2));
+        assert_eq!(unwrapper.unwrap(2), (65538, 0));
+        assert_eq!(unwrapper.unwrap(30000), (95536, 29997));
+    }
+
+    #[test]
+    fn restart() {
+        let mut unwrapper = Unwrapper::new();
+        unwrapper.unwrap(500);
+        unwrapper.unwrap(501);
+        // The board restarted: no frames lost, the numbers go on
+        let (number, lost) = unwrapper.unwrap(0);
+        assert_eq!(lost, 0);
+        assert!(number >= 502);
+        assert_eq!(unwrapper.unwrap(1), (number + 1, 0));
+    }
+
+    #[test]
+    fn steady_rate() {
+        let mut timing = Timing::new();
+        assert_eq!(timing.report(), None);
+        // 1 kHz measured by a clock 50 ppm fast, frames 10 and 11 lost
+        for n in (0..1000).filter(|&n| n != 10 && n != 11) {
+            timing.push(1000 + n, 3.0 + n as f64 * 1.00005e-3);
+        }
+        let report = timing.report().unwrap();
+        assert_eq!(report.frames, 998);
+        assert!((report.rate_hz - 999.95).abs() < 0.001, "{:?}", report);
+        assert!((report.ppm(1000.0) + 50.0).abs() < 1.0, "{:?}", report);
+        assert!(report.rms_us < 0.01 && report.max_us < 0.01, "{:?}", report);
+    }
+
+    #[test]
+    fn jitter() {
+        let mut timing = Timing::new();
+        // Frames arriving by bursts of 4, like a USB adapter does
+        for n in 0..400 {
+            let late = (3 - n % 4) as f64 * 1e-3;
+            timing.push(n, n as f64 * 1e-3 + late);
+        }
+        let report = timing.report().unwrap();
+        assert!((report.rate_hz - 1000.0).abs() < 0.1, "{:?}", report);
+        // -1.5 ms to 1.5 ms around the fit
+        assert!((report.max_us - 1500.0).abs() < 20.0, "{:?}", report);
+        assert!((report.rms_us - 1118.0).abs() < 20.0, "{:?}", report);
+
+        timing.clear();
+        assert!(timing.is_empty());
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(csv_header(&[8, 9, 17]), "frame,time_s,ch8,ch9,ch17");
+        assert_eq!(
+            csv_row(1500, 1_000_000, &[2048, 0, 1489]),
+            "1500,1.500000,2048,0,1489"
+        );
+        assert_eq!(csv_row(1, 44_090_631, &[7]), "1,0.000023,7");
+    }
+}
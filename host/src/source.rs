@@ -0,0 +1,133 @@
+//! Things to show on the terminal, rendered again on every update.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use chrono::Local;
+
+pub enum Source {
+    /// Text with `{date}`, `{time}`, `{load}` and `{cpu}` placeholders.
+    Text(String),
+    /// The local time with a `strftime` format.
+    Clock(String),
+    /// Load average and CPU usage.
+    Load,
+    /// Output of a shell command.
+    Command(String),
+    /// Contents of a file.
+    File(PathBuf),
+}
+
+/// Renders a `Source`, keeping the state needed to measure the CPU usage
+/// between two updates.
+pub struct Renderer {
+    source: Source,
+    cpu: Option<CpuTimes>,
+}
+
+impl Renderer {
+    pub fn new(source: Source) -> Self {
+        Renderer { source, cpu: None }
+    }
+
+    pub fn render(&mut self) -> io::Result<String> {
+        match &self.source {
+            Source::Text(template) => {
+                let template = template.clone();
+                self.expand(&template)
+            }
+            Source::Clock(format) => Ok(Local::now().format(format).to_string()),
+            Source::Load => self.expand("load {load}\ncpu  {cpu}%"),
+            Source::Command(cmd) => {
+                let out = Command::new("sh").arg("-c").arg(cmd).output()?;
+                Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+            }
+            Source::File(path) => Ok(String::from_utf8_lossy(&fs::read(path)?).into_owned()),
+        }
+    }
+
+    fn expand(&mut self, template: &str) -> io::Result<String> {
+        let now = Local::now();
+        let mut text = template
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H:%M:%S").to_string());
+        if text.contains("{load}") {
+            let loadavg = fs::read_to_string("/proc/loadavg")?;
+            let load: Vec<&str> = loadavg.split_whitespace().take(3).collect();
+            text = text.replace("{load}", &load.join(" "));
+        }
+        if text.contains("{cpu}") {
+            let cpu = self.cpu_usage()?;
+            text = text.replace("{cpu}", &format!("{:.0}", cpu));
+        }
+        Ok(text)
+    }
+
+    /// CPU usage in percent since the previous call (since boot for the first).
+    fn cpu_usage(&mut self) -> io::Result<f64> {
+        let now = CpuTimes::read()?;
+        let prev = self.cpu.replace(now).unwrap_or_default();
+        Ok(now.usage_since(&prev))
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    fn read() -> io::Result<Self> {
+        let stat = fs::read_to_string("/proc/stat")?;
+        let line = stat.lines().next().unwrap_or("");
+        Ok(Self::parse(line))
+    }
+
+    /// Parse the `cpu  user nice system idle iowait irq softirq steal` line.
+    fn parse(line: &str) -> Self {
+        let fields: Vec<u64> = line
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|f| f.parse().ok())
+            .collect();
+        let total: u64 = fields.iter().take(8).sum();
+        let idle = fields.get(3).unwrap_or(&0) + fields.get(4).unwrap_or(&0);
+        CpuTimes {
+            busy: total - idle,
+            total,
+        }
+    }
+
+    fn usage_since(&self, prev: &CpuTimes) -> f64 {
+        let total = self.total.saturating_sub(prev.total);
+        if total == 0 {
+            return 0.0;
+        }
+        self.busy.saturating_sub(prev.busy) as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cpu_usage() {
+        let a = CpuTimes::parse("cpu  100 0 100 700 100 0 0 0 0 0");
+        let b = CpuTimes::parse("cpu  150 0 150 750 150 0 0 0 0 0");
+        assert_eq!(a.usage_since(&CpuTimes::default()), 20.0);
+        assert_eq!(b.usage_since(&a), 50.0);
+    }
+
+    #[test]
+    fn placeholders() {
+        let mut r = Renderer::new(Source::Text("at {time}\n{load}".to_string()));
+        let text = r.render().unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0].len(), "at 12:00:00".len());
+        assert_eq!(lines[1].split_whitespace().count(), 3);
+    }
+}
@@ -0,0 +1,74 @@
+//! Formatting text for the serial terminal of app3.
+//!
+//! The terminal prints every received byte in the next 8x8 cell of a 16x8
+//! grid, without interpreting control characters, and redraws the screen when
+//! it gets a `\n` or the text area is full.  The last row is its status bar.
+
+/// Columns of the terminal.
+pub const COLS: usize = 16;
+/// Rows available for text.
+pub const ROWS: usize = 7;
+
+/// Lay out `text` so that every line starts on a new row.  Long lines are
+/// wrapped, extra rows are dropped and anything that is not printable ASCII
+/// is replaced by `?`.  The result ends with the `\n` that makes the board
+/// redraw the screen.
+pub fn layout(text: &str) -> Vec<u8> {
+    let mut rows: Vec<Vec<u8>> = Vec::new();
+    for line in text.lines() {
+        let line: Vec<u8> = line
+            .chars()
+            .map(|c| match c {
+                '\t' => b' ',
+                ' '..='~' => c as u8,
+                _ => b'?',
+            })
+            .collect();
+        if line.is_empty() {
+            rows.push(Vec::new());
+        }
+        rows.extend(line.chunks(COLS).map(|row| row.to_vec()));
+    }
+    if rows.is_empty() {
+        rows.push(Vec::new());
+    }
+    rows.truncate(ROWS);
+
+    let last = rows.len() - 1;
+    let mut out = Vec::with_capacity(COLS * ROWS);
+    for (i, mut row) in rows.into_iter().enumerate() {
+        // The final `\n` takes a cell of the last row
+        let width = if i == last { COLS - 1 } else { COLS };
+        row.resize(width, b' ');
+        out.extend(row);
+    }
+    out.push(b'\n');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rows_are_padded() {
+        let out = layout("12:00\nhi");
+        assert_eq!(out.len(), COLS * 2);
+        assert_eq!(&out[..COLS], b"12:00           ");
+        assert_eq!(&out[COLS..], b"hi             \n");
+    }
+
+    #[test]
+    fn long_text_is_wrapped_and_cut() {
+        let text = "x".repeat(COLS * 10);
+        let out = layout(&text);
+        assert_eq!(out.len(), COLS * ROWS);
+        assert_eq!(out[COLS * ROWS - 2], b'x');
+        assert_eq!(out[COLS * ROWS - 1], b'\n');
+    }
+
+    #[test]
+    fn non_ascii() {
+        assert_eq!(&layout("25°C")[..5], b"25?C ");
+    }
+}